@@ -1,9 +1,15 @@
 use std::collections::VecDeque;
-use std::sync::mpsc::{channel, Receiver, RecvError, Sender, TryRecvError};
+use std::sync::mpsc::{channel, Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError};
 use std::thread;
+use std::time::{Duration, Instant};
 use url::Url;
 
-use languageserver_types::{Position, Range};
+pub use lark_span::PositionEncoding;
+
+use languageserver_types::{
+    CompletionItemKind, DocumentHighlight, DocumentSymbol, FileChangeType, FoldingRange, Position,
+    Range, SignatureHelp, SymbolInformation, SymbolKind,
+};
 
 pub type TaskId = usize;
 
@@ -12,11 +18,56 @@ pub type TaskId = usize;
 pub enum QueryRequest {
     TypeAtPosition(TaskId, Url, Position),
     RenameAtPosition(TaskId, Url, Position, String),
+    /// The name a rename at the position would change.
+    PrepareRename(TaskId, Url, Position),
     DefinitionAtPosition(TaskId, Url, Position),
+    TypeDefinitionAtPosition(TaskId, Url, Position),
+    DocumentHighlights(TaskId, Url, Position),
     ReferencesAtPosition(TaskId, Url, Position, bool),
+    CompletionsAtPosition(TaskId, Url, Position),
+    SignatureHelpAtPosition(TaskId, Url, Position),
+    /// Fixes for the diagnostics in part of a file.
+    CodeActions(TaskId, Url, Range),
+    InlayHints(TaskId, Url, Range),
+    /// Formatting edits for a file, or for the lines in the range.
+    Format(TaskId, Url, Option<Range>),
+    /// Semantic tokens for a file; if the IDE has the tokens from an
+    /// earlier request, the id of that result, so we can send only what
+    /// changed.
+    SemanticTokens(TaskId, Url, Option<String>),
+    DocumentSymbols(TaskId, Url),
+    FoldingRanges(TaskId, Url),
+    CodeLenses(TaskId, Url),
+    /// The function or method at a position, for the call hierarchy.
+    PrepareCallHierarchy(TaskId, Url, Position),
+    /// The calls to the function or method defined at a position.
+    IncomingCalls(TaskId, Url, Position),
+    /// The calls made by the function or method defined at a position.
+    OutgoingCalls(TaskId, Url, Position),
+    /// Run the `main` function of a file, sending back what it prints.
+    RunMain(TaskId, Url),
+    /// For each position, the ranges to step through when expanding
+    /// the selection there.
+    SelectionRanges(TaskId, Url, Vec<Position>),
+    WorkspaceSymbols(TaskId, String),
+    /// The IDE no longer wants the answer to this request.
+    Cancel(TaskId),
+    /// The IDE is about to tell us to exit.
+    Shutdown(TaskId),
     OpenFile(Url, String),
-    EditFile(Url, Vec<(Range, String)>),
-    Initialize(TaskId),
+    /// Edits to a file; an edit without a range replaces all of it.
+    EditFile(Url, Vec<(Option<Range>, String)>),
+    CloseFile(Url),
+    /// The diagnostics for a file; if the IDE has the diagnostics from
+    /// an earlier request, the id of that result.
+    PullDiagnostics(TaskId, Url, Option<String>),
+    /// The IDE has connected; columns in positions are counted in
+    /// the given encoding from now on.
+    Initialize(TaskId, PositionEncoding),
+    /// Load the `.lark` files in these workspace folders from disk.
+    LoadWorkspace(Vec<Url>),
+    /// Files on disk were created, changed or deleted.
+    WatchedFilesChanged(Vec<(Url, FileChangeType)>),
 }
 impl QueryRequest {
     /// True if this query will cause us to mutate the state of the
@@ -25,26 +76,187 @@ impl QueryRequest {
         match self {
             QueryRequest::OpenFile(..)
             | QueryRequest::EditFile(..)
+            | QueryRequest::CloseFile(..)
             | QueryRequest::RenameAtPosition(..)
+            | QueryRequest::LoadWorkspace(..)
+            | QueryRequest::WatchedFilesChanged(..)
             | QueryRequest::Initialize(..) => true,
+            QueryRequest::PrepareRename(..) => false,
+            QueryRequest::Cancel(..) => false,
+            QueryRequest::Shutdown(..) => false,
             QueryRequest::TypeAtPosition(..) => false,
             QueryRequest::DefinitionAtPosition(..) => false,
+            QueryRequest::TypeDefinitionAtPosition(..) => false,
+            QueryRequest::DocumentHighlights(..) => false,
+            QueryRequest::PullDiagnostics(..) => false,
             QueryRequest::ReferencesAtPosition(..) => false,
+            QueryRequest::CompletionsAtPosition(..) => false,
+            QueryRequest::SignatureHelpAtPosition(..) => false,
+            QueryRequest::CodeActions(..) => false,
+            QueryRequest::InlayHints(..) => false,
+            QueryRequest::Format(..) => false,
+            QueryRequest::SemanticTokens(..) => false,
+            QueryRequest::DocumentSymbols(..) => false,
+            QueryRequest::FoldingRanges(..) => false,
+            QueryRequest::CodeLenses(..) => false,
+            QueryRequest::PrepareCallHierarchy(..) => false,
+            QueryRequest::IncomingCalls(..) => false,
+            QueryRequest::OutgoingCalls(..) => false,
+            QueryRequest::RunMain(..) => false,
+            QueryRequest::SelectionRanges(..) => false,
+            QueryRequest::WorkspaceSymbols(..) => false,
+        }
+    }
+
+    /// The request this is, if it is one the IDE expects an answer to.
+    pub fn task_id(&self) -> Option<TaskId> {
+        match *self {
+            QueryRequest::TypeAtPosition(task_id, ..)
+            | QueryRequest::RenameAtPosition(task_id, ..)
+            | QueryRequest::PrepareRename(task_id, ..)
+            | QueryRequest::DefinitionAtPosition(task_id, ..)
+            | QueryRequest::TypeDefinitionAtPosition(task_id, ..)
+            | QueryRequest::DocumentHighlights(task_id, ..)
+            | QueryRequest::PullDiagnostics(task_id, ..)
+            | QueryRequest::ReferencesAtPosition(task_id, ..)
+            | QueryRequest::CompletionsAtPosition(task_id, ..)
+            | QueryRequest::SignatureHelpAtPosition(task_id, ..)
+            | QueryRequest::CodeActions(task_id, ..)
+            | QueryRequest::InlayHints(task_id, ..)
+            | QueryRequest::Format(task_id, ..)
+            | QueryRequest::SemanticTokens(task_id, ..)
+            | QueryRequest::DocumentSymbols(task_id, ..)
+            | QueryRequest::FoldingRanges(task_id, ..)
+            | QueryRequest::CodeLenses(task_id, ..)
+            | QueryRequest::PrepareCallHierarchy(task_id, ..)
+            | QueryRequest::IncomingCalls(task_id, ..)
+            | QueryRequest::OutgoingCalls(task_id, ..)
+            | QueryRequest::RunMain(task_id, ..)
+            | QueryRequest::SelectionRanges(task_id, ..)
+            | QueryRequest::WorkspaceSymbols(task_id, ..)
+            | QueryRequest::Initialize(task_id, _)
+            | QueryRequest::Shutdown(task_id) => Some(task_id),
+            QueryRequest::Cancel(..)
+            | QueryRequest::OpenFile(..)
+            | QueryRequest::EditFile(..)
+            | QueryRequest::CloseFile(..)
+            | QueryRequest::LoadWorkspace(..)
+            | QueryRequest::WatchedFilesChanged(..) => None,
         }
     }
 }
 
+/// What an identifier refers to, for semantic highlighting.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SemanticTokenKind {
+    Struct,
+    Function,
+    Method,
+    Field,
+    Variable,
+    Parameter,
+    BuiltinType,
+    BuiltinFunction,
+    BuiltinConstant,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SemanticToken {
+    pub range: Range,
+    pub kind: SemanticTokenKind,
+
+    /// True if this is where the thing is declared, rather than a use.
+    pub declaration: bool,
+}
+
+/// A fix for a diagnostic: what to call it, the diagnostic it fixes
+/// (its range and label) and the edits to the diagnostic's file that
+/// make it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuickFix {
+    pub title: String,
+    pub diagnostic: (Range, String),
+    pub edits: Vec<(Range, String)>,
+}
+
+/// Something we inferred, shown inline at `position` in the file (e.g.
+/// the type of a variable, right after its name).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InlayHint {
+    pub position: Position,
+    pub label: String,
+}
+
+/// What a code lens shows, above the item at `range`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodeLens {
+    pub range: Range,
+    pub kind: CodeLensKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodeLensKind {
+    /// Run the program, starting from this `main` function.
+    RunMain,
+
+    /// The references to the item: the file and range of each.
+    References(Vec<(String, Range)>),
+}
+
+/// A function or method, as shown in the call hierarchy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallHierarchyItem {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub detail: Option<String>,
+
+    /// The file it is defined in.
+    pub file: String,
+
+    /// The whole definition, and just its name.
+    pub range: Range,
+    pub selection_range: Range,
+}
+
 /// Responses back to the LSP services from
 /// the query system.
 pub enum LspResponse {
-    Type(TaskId, String),
+    /// Markdown describing the thing at `range`.
+    Hover(TaskId, Range, String),
     Range(TaskId, Url, Range),
     Ranges(TaskId, Vec<(Url, Range)>),
     WorkspaceEdits(TaskId, Vec<(Url, Range, String)>),
-    Completions(TaskId, Vec<(String, String)>),
-    Initialized(TaskId),
+    /// The range of a name, and the name as a placeholder for a new one.
+    RenameRange(TaskId, Range, String),
+    TextEdits(TaskId, Vec<(Range, String)>),
+    Completions(TaskId, Vec<(String, String, CompletionItemKind)>),
+    SignatureHelp(TaskId, SignatureHelp),
+    QuickFixes(TaskId, Url, Vec<QuickFix>),
+    InlayHints(TaskId, Vec<InlayHint>),
+    SemanticTokens(TaskId, Url, Vec<SemanticToken>, Option<String>),
+    DocumentSymbols(TaskId, Vec<DocumentSymbol>),
+    FoldingRanges(TaskId, Vec<FoldingRange>),
+    CodeLenses(TaskId, Url, Vec<CodeLens>),
+    CallHierarchyItems(TaskId, Vec<CallHierarchyItem>),
+    /// The callers of a function, each with the ranges of its calls.
+    IncomingCalls(TaskId, Vec<(CallHierarchyItem, Vec<Range>)>),
+    /// The callees of a function, each with the ranges of the calls.
+    OutgoingCalls(TaskId, Vec<(CallHierarchyItem, Vec<Range>)>),
+    /// A line printed by a program started with `RunMain`.
+    RunOutput(String),
+    SelectionRanges(TaskId, Vec<Vec<Range>>),
+    DocumentHighlights(TaskId, Vec<DocumentHighlight>),
+    WorkspaceSymbols(TaskId, Vec<SymbolInformation>),
+    Initialized(TaskId, PositionEncoding),
     Nothing(TaskId),
+    /// The request was cancelled before we had an answer.
+    Cancelled(TaskId),
+    /// The request can't be done, for the reason given.
+    Failed(TaskId, String),
     Diagnostics(Url, Vec<(Range, String)>),
+    /// The answer to `PullDiagnostics`, with the id of the earlier
+    /// result the IDE has, if any.
+    PulledDiagnostics(TaskId, Url, Vec<(Range, String)>, Option<String>),
 }
 
 /// An actor in the task system. This gives a uniform way to
@@ -57,7 +269,8 @@ pub trait Actor {
     /// as many as they like. So long as messages remain in the
     /// dequeue, we'll just keep calling back (possibly appending more
     /// messages to the back). Once the queue is empty, we'll block
-    /// until we can fetch more.
+    /// until we can fetch more -- or until the time given by
+    /// `wake_up_at`, when we call back with an empty queue.
     ///
     /// The intended workflow is as follows:
     ///
@@ -70,6 +283,13 @@ pub trait Actor {
     ///     messages if they have arrived in the meantime.
     ///     - This is only important if you are trying to remove outdated messages.
     fn receive_messages(&mut self, messages: &mut VecDeque<Self::InMessage>);
+
+    /// If the actor has work to do once messages stop arriving for a
+    /// while, when to call `receive_messages` (with no messages) to
+    /// do it.
+    fn wake_up_at(&self) -> Option<Instant> {
+        None
+    }
 }
 
 pub struct ActorControl<MessageType: Send + Sync + 'static> {
@@ -77,20 +297,30 @@ pub struct ActorControl<MessageType: Send + Sync + 'static> {
     pub join_handle: std::thread::JoinHandle<()>,
 }
 
+impl<MessageType: Send + Sync + 'static> ActorControl<MessageType> {
+    /// Waits for the actor to stop. It stops once it has handled all
+    /// its messages and every sender (including our `channel`, which
+    /// this drops) is gone.
+    pub fn join(self) -> thread::Result<()> {
+        drop(self.channel);
+        self.join_handle.join()
+    }
+}
+
 pub fn spawn_actor<T: Actor + Send + 'static>(mut actor: T) -> ActorControl<T::InMessage> {
     let (actor_tx, actor_rx) = channel();
     let mut message_queue = VecDeque::default();
 
     let handle = thread::spawn(move || loop {
-        match push_all_pending(&actor_rx, &mut message_queue) {
+        match push_all_pending(&actor_rx, &mut message_queue, actor.wake_up_at()) {
             Ok(()) => {
                 actor.receive_messages(&mut message_queue);
             }
             Err(error) => {
                 match error {
-                    PushAllPendingError::Disconnected => {
-                        eprintln!("Failure during top-level message receive");
-                    }
+                    // Everyone who could send us messages is gone, and
+                    // we've handled all the ones they sent: we're done.
+                    PushAllPendingError::Disconnected => {}
                 }
 
                 break;
@@ -108,22 +338,44 @@ enum PushAllPendingError {
     Disconnected,
 }
 
-fn push_all_pending<T>(rx: &Receiver<T>, vec: &mut VecDeque<T>) -> Result<(), PushAllPendingError> {
+fn push_all_pending<T>(
+    rx: &Receiver<T>,
+    vec: &mut VecDeque<T>,
+    wake_up_at: Option<Instant>,
+) -> Result<(), PushAllPendingError> {
     // If the queue is currently empty, then block until we get at
-    // least one message.
+    // least one message (or it is time to wake up).
     if vec.is_empty() {
-        match rx.recv() {
-            Ok(m) => vec.push_back(m),
-            Err(RecvError) => return Err(PushAllPendingError::Disconnected),
+        match wake_up_at {
+            None => match rx.recv() {
+                Ok(m) => vec.push_back(m),
+                Err(RecvError) => return Err(PushAllPendingError::Disconnected),
+            },
+            Some(wake_up_at) => {
+                let now = Instant::now();
+                let timeout = if wake_up_at > now {
+                    wake_up_at - now
+                } else {
+                    Duration::from_secs(0)
+                };
+                match rx.recv_timeout(timeout) {
+                    Ok(m) => vec.push_back(m),
+                    Err(RecvTimeoutError::Timeout) => return Ok(()),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(PushAllPendingError::Disconnected);
+                    }
+                }
+            }
         }
     }
 
     // Once the queue is non-empty, opportunistically poll for more.
+    // If the senders are gone, the messages they sent still have to
+    // be handled; we only stop once the queue has drained (above).
     loop {
         match rx.try_recv() {
             Ok(m) => vec.push_back(m),
-            Err(TryRecvError::Empty) => break Ok(()),
-            Err(TryRecvError::Disconnected) => break Err(PushAllPendingError::Disconnected),
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break Ok(()),
         }
     }
 }
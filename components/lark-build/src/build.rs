@@ -1,13 +1,16 @@
-use crate::CodegenType;
+use crate::{BackendOptions, CodegenOptions, CodegenType, CrateSource, OutputKind};
+use std::path::Path;
 
 /// Build a source file using the default tools on the given platform
 pub fn build(
     target_filename: &str,
     src: &String,
     codegen_type: CodegenType,
+    options: &CodegenOptions,
+    backend_options: &BackendOptions,
 ) -> std::io::Result<()> {
     match codegen_type {
-        CodegenType::Rust => build_rust(target_filename, src),
+        CodegenType::Rust => build_rust(target_filename, src, options, backend_options),
     }
 }
 
@@ -34,20 +37,63 @@ fn create_src_file(codegen_type: CodegenType) -> tempfile::NamedTempFile {
 }
 
 /// Invoke the Rust compiler to build the source file
-fn build_rust(target_filename: &str, src: &String) -> std::io::Result<()> {
+fn build_rust(
+    target_filename: &str,
+    src: &String,
+    options: &CodegenOptions,
+    backend_options: &BackendOptions,
+) -> std::io::Result<()> {
     use std::io::Write;
     use std::process::Command;
 
-    let mut src_file = create_src_file(CodegenType::Rust);
-    src_file.write_all(src.as_bytes()).unwrap();
-    let src_file_name = src_file.path().to_string_lossy().to_string();
+    // If we're not keeping the source, it lives in a temporary file which
+    // must stay around until the compiler is done with it.
+    let mut _temp_file = None;
+    let src_file_name = if backend_options.keep_source {
+        let src_path = Path::new(target_filename).with_extension("rs");
+        std::fs::write(&src_path, src)?;
+        src_path.to_string_lossy().to_string()
+    } else {
+        let mut src_file = create_src_file(CodegenType::Rust);
+        src_file.write_all(src.as_bytes()).unwrap();
+        let src_file_name = src_file.path().to_string_lossy().to_string();
+        _temp_file = Some(src_file);
+        src_file_name
+    };
+
+    let mut command = Command::new(r"rustc");
+    command.arg(src_file_name).arg("-o").arg(target_filename);
+
+    if let Some(opt_level) = &backend_options.opt_level {
+        command.arg("-C").arg(format!("opt-level={}", opt_level));
+    }
+
+    if backend_options.debug_info {
+        command.arg("-C").arg("debuginfo=2");
+    }
+
+    if let Some(target) = &backend_options.target {
+        command.arg("--target").arg(target);
+    }
+
+    command.args(&backend_options.extra_args);
+
+    if options.output_kind == OutputKind::Library {
+        // The temporary file has a random name, so name the crate after
+        // its output instead; that's the name users will `extern crate`.
+        let crate_type = if options.extern_c {
+            "staticlib"
+        } else {
+            "rlib"
+        };
+        command
+            .arg("--crate-type")
+            .arg(crate_type)
+            .arg("--crate-name")
+            .arg(library_crate_name(target_filename));
+    }
 
-    let output = Command::new(r"rustc")
-        .arg(src_file_name)
-        .arg("-o")
-        .arg(target_filename)
-        .output()
-        .expect("Failed to run Rust compiler");
+    let output = command.output().expect("Failed to run Rust compiler");
 
     if output.status.success() {
         Ok(())
@@ -62,3 +108,118 @@ fn build_rust(target_filename: &str, src: &String) -> std::io::Result<()> {
         Err(Error::new(ErrorKind::Other, combined_compile_msg))
     }
 }
+
+/// Derive the crate name for a library from its file name, e.g. `foo`
+/// for `libfoo.rlib`
+fn library_crate_name(target_filename: &str) -> String {
+    let stem = Path::new(target_filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let stem = if stem.starts_with("lib") && stem.len() > 3 {
+        &stem[3..]
+    } else {
+        &stem[..]
+    };
+
+    let mut name: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    // Crate names can't start with a digit
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert_str(0, "lark_");
+    }
+
+    name
+}
+
+/// Write the generated modules out as a crate for the given platform's build tool
+pub fn emit_crate(
+    target_dir: &Path,
+    crate_name: &str,
+    src: &CrateSource,
+    codegen_type: CodegenType,
+) -> std::io::Result<()> {
+    match codegen_type {
+        CodegenType::Rust => emit_cargo_crate(target_dir, crate_name, src),
+    }
+}
+
+/// Write a Cargo crate into `target_dir`: a `Cargo.toml`, a crate root
+/// (`src/main.rs` if there is a `main` module, `src/lib.rs` otherwise)
+/// and one module per input file next to it
+fn emit_cargo_crate(target_dir: &Path, crate_name: &str, src: &CrateSource) -> std::io::Result<()> {
+    use std::fs;
+
+    let src_dir = target_dir.join("src");
+    fs::create_dir_all(&src_dir)?;
+
+    let mut manifest = format!(
+        "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2018\"\n\n",
+        crate_name
+    );
+    if src.extern_c {
+        manifest.push_str("[lib]\ncrate-type = [\"rlib\", \"staticlib\"]\n\n");
+    }
+    manifest.push_str("[dependencies]\n");
+    fs::write(target_dir.join("Cargo.toml"), manifest)?;
+
+    let mut crate_root = String::new();
+    crate_root.push_str("// Generated by the Lark compiler.\n\n");
+    for module in &src.modules {
+        crate_root.push_str(&format!("mod {};\n", module.name));
+    }
+    crate_root.push_str("\n");
+    // A library without a `main` re-exports everything, so that the
+    // generated items are its public API
+    let reexport = if src.main_module.is_none() {
+        "pub "
+    } else {
+        ""
+    };
+    for module in &src.modules {
+        crate_root.push_str(&format!("{}use self::{}::*;\n", reexport, module.name));
+    }
+
+    let crate_root_file = match &src.main_module {
+        Some(main_module) => {
+            crate_root.push_str(&format!(
+                "\nfn main() {{\n    {}::main()\n}}\n",
+                main_module
+            ));
+            "main.rs"
+        }
+        None => "lib.rs",
+    };
+
+    // Don't leave stale files from an earlier emit around: a root of the
+    // other kind, or modules for input files that are gone
+    let mut current_files: Vec<String> = src
+        .modules
+        .iter()
+        .map(|module| format!("{}.rs", module.name))
+        .collect();
+    current_files.push(crate_root_file.to_string());
+    for entry in fs::read_dir(&src_dir)? {
+        let path = entry?.path();
+        let file_name = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .unwrap_or("")
+            .to_string();
+        if path.is_file() && file_name.ends_with(".rs") && !current_files.contains(&file_name) {
+            fs::remove_file(path)?;
+        }
+    }
+
+    fs::write(src_dir.join(crate_root_file), crate_root)?;
+
+    for module in &src.modules {
+        fs::write(src_dir.join(format!("{}.rs", module.name)), &module.source)?;
+    }
+
+    Ok(())
+}
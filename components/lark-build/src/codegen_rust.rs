@@ -1,3 +1,4 @@
+use crate::{CodegenOptions, CrateSource, ModuleSource, OutputKind};
 use lark_debug_with::DebugWith;
 use lark_entity::{Entity, EntityData, ItemKind, LangItem, MemberKind};
 use lark_error::{Diagnostic, WithError};
@@ -5,7 +6,33 @@ use lark_hir as hir;
 use lark_intern::{Intern, Untern};
 use lark_parser::{ParserDatabase, ParserDatabaseExt};
 use lark_query_system::LarkDatabase;
-use lark_ty::Ty;
+use lark_span::FileName;
+use lark_ty::full_inferred::FullInferred;
+use lark_ty::{PermKind, Ty};
+use lark_type_check::{TypeCheckDatabase, TypeCheckResults};
+
+/// The visibility we give to the items (and struct fields) we generate.
+#[derive(Copy, Clone)]
+pub enum Visibility {
+    /// Everything lives in one file, so there is no need for any.
+    Private,
+
+    /// Items are visible across the modules of an emitted crate.
+    Crate,
+
+    /// Items are the API of a library.
+    Public,
+}
+
+impl Visibility {
+    fn prefix(self) -> &'static str {
+        match self {
+            Visibility::Private => "",
+            Visibility::Crate => "pub(crate) ",
+            Visibility::Public => "pub ",
+        }
+    }
+}
 
 fn build_variable_name(
     db: &LarkDatabase,
@@ -28,9 +55,311 @@ fn build_entity_name(db: &LarkDatabase, entity: Entity) -> String {
     }
 }
 
+/// How the value of an expression is going to be used, which decides
+/// whether a shared access needs a clone or can make do with a reference.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ValueUse {
+    /// The value is stored or passed on, so we need one we own.
+    Owned,
+
+    /// The value is only looked at (compared, printed, branched on).
+    Inspected,
+}
+
+/// Values of these types are `Copy` in Rust, so we never need to worry
+/// about moving, cloning or borrowing them.
+fn is_copy(db: &LarkDatabase, ty: Ty<FullInferred>) -> bool {
+    match ty.base.untern(db).kind {
+        lark_ty::BaseKind::Named(entity) => match entity.untern(db) {
+            EntityData::LangItem(LangItem::Boolean)
+            | EntityData::LangItem(LangItem::Uint)
+            | EntityData::LangItem(LangItem::Int)
+            | EntityData::LangItem(LangItem::Tuple(0)) => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// True if the generated Rust for `place` is already a reference, rather
+/// than the value itself.
+fn place_holds_reference(
+    db: &LarkDatabase,
+    fn_body: &std::sync::Arc<hir::FnBody>,
+    results: &TypeCheckResults<FullInferred>,
+    place: hir::Place,
+) -> bool {
+    match fn_body.tables[place] {
+        hir::PlaceData::Variable(variable) => {
+            let ty = results.ty(variable);
+            if is_copy(db, ty) {
+                false
+            } else if is_argument(fn_body, variable) {
+                parameter_perm(db, fn_body, results, variable) != PermKind::Own
+            } else {
+                ty.perm != PermKind::Own
+            }
+        }
+        hir::PlaceData::Temporary(expression) => match fn_body.tables[expression] {
+            hir::ExpressionData::Place { .. } => {
+                let ty = results.access_ty(expression);
+                results.access_permissions[&expression] != PermKind::Own && !is_copy(db, ty)
+            }
+            _ => false,
+        },
+        hir::PlaceData::Entity(_) | hir::PlaceData::Field { .. } => false,
+    }
+}
+
+/// The variable that `place` is a part of, if any (`a` for `a.b.c`).
+fn root_variable(fn_body: &hir::FnBody, place: hir::Place) -> Option<hir::Variable> {
+    match fn_body.tables[place] {
+        hir::PlaceData::Variable(variable) => Some(variable),
+        hir::PlaceData::Field { owner, .. } => root_variable(fn_body, owner),
+        hir::PlaceData::Entity(_) | hir::PlaceData::Temporary(_) => None,
+    }
+}
+
+/// True if `variable` is one of the parameters of the function.
+fn is_argument(fn_body: &hir::FnBody, variable: hir::Variable) -> bool {
+    match fn_body.arguments {
+        Ok(arguments) => arguments.iter(fn_body).any(|argument| argument == variable),
+        Err(_) => false,
+    }
+}
+
+/// The permission the function needs for its parameter `argument`: the
+/// strongest one with which its body accesses the parameter, or any
+/// place within it. Parameters are always declared owned, but ones that
+/// are only ever shared (resp. borrowed) are taken by `&` (resp. `&mut`)
+/// in the generated Rust, so that callers do not have to give them up.
+fn parameter_perm(
+    db: &LarkDatabase,
+    fn_body: &std::sync::Arc<hir::FnBody>,
+    results: &TypeCheckResults<FullInferred>,
+    argument: hir::Variable,
+) -> PermKind {
+    if is_copy(db, results.ty(argument)) {
+        return PermKind::Own;
+    }
+
+    let mut perm = PermKind::Share;
+    for expression in fn_body.tables.expressions.indices() {
+        let needed = match fn_body.tables[expression] {
+            // Assigning to the parameter itself needs it owned, while
+            // assigning to a field of it only needs it borrowed.
+            hir::ExpressionData::Assignment { place, .. } => match fn_body.tables[place] {
+                hir::PlaceData::Variable(variable) if variable == argument => PermKind::Own,
+                _ if root_variable(fn_body, place) == Some(argument) => PermKind::Borrow,
+                _ => continue,
+            },
+            hir::ExpressionData::Place { place }
+                if root_variable(fn_body, place) == Some(argument)
+                    && !is_copy(db, results.access_ty(expression)) =>
+            {
+                match results.access_permissions.get(&expression) {
+                    Some(&access_perm) => access_perm,
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+
+        perm = match (perm, needed) {
+            (PermKind::Own, _) | (_, PermKind::Own) => PermKind::Own,
+            (PermKind::Borrow, _) | (_, PermKind::Borrow) => PermKind::Borrow,
+            (PermKind::Share, PermKind::Share) => PermKind::Share,
+        };
+    }
+    perm
+}
+
+/// The permissions with which the function called by `expression` (a
+/// call or method call) takes each of its arguments, or `None` if the
+/// callee is not a function we generate, like `debug`.
+fn callee_parameter_perms(
+    db: &LarkDatabase,
+    fn_body: &std::sync::Arc<hir::FnBody>,
+    results: &TypeCheckResults<FullInferred>,
+    expression: hir::Expression,
+) -> Option<Vec<PermKind>> {
+    let callee = match fn_body.tables[expression] {
+        hir::ExpressionData::Call { function, .. } => match fn_body.tables[function] {
+            hir::ExpressionData::Place { place } => match fn_body.tables[place] {
+                hir::PlaceData::Entity(entity) => entity,
+                _ => return None,
+            },
+            _ => return None,
+        },
+        hir::ExpressionData::MethodCall { method, .. } => *results.entities.get(&method.into())?,
+        _ => return None,
+    };
+
+    match callee.untern(db) {
+        EntityData::ItemName {
+            kind: ItemKind::Function,
+            ..
+        }
+        | EntityData::MemberName {
+            kind: MemberKind::Method,
+            ..
+        } => {}
+        _ => return None,
+    }
+
+    let callee_body = db.fn_body(callee).into_value();
+    let callee_results = db.full_type_check(callee).into_value();
+    let arguments = callee_body.arguments.ok()?;
+
+    Some(
+        arguments
+            .iter(&callee_body)
+            .map(|argument| parameter_perm(db, &callee_body, &callee_results, argument))
+            .collect(),
+    )
+}
+
+/// True if `variable` is assigned to, or mutably borrowed, somewhere in
+/// the function body, in which case Rust wants it declared `mut`.
+fn is_mutated(
+    db: &LarkDatabase,
+    fn_body: &std::sync::Arc<hir::FnBody>,
+    results: &TypeCheckResults<FullInferred>,
+    variable: hir::Variable,
+) -> bool {
+    let is_part_of_variable = |expression: hir::Expression| match fn_body.tables[expression] {
+        hir::ExpressionData::Place { place } => root_variable(fn_body, place) == Some(variable),
+        _ => false,
+    };
+
+    fn_body
+        .tables
+        .expressions
+        .indices()
+        .any(|expression| match fn_body.tables[expression] {
+            hir::ExpressionData::Assignment { place, .. } => {
+                root_variable(fn_body, place) == Some(variable)
+            }
+            hir::ExpressionData::Place { .. } => {
+                results.access_permissions.get(&expression) == Some(&PermKind::Borrow)
+                    && is_part_of_variable(expression)
+            }
+            hir::ExpressionData::Call { arguments, .. }
+            | hir::ExpressionData::MethodCall { arguments, .. } => {
+                match callee_parameter_perms(db, fn_body, results, expression) {
+                    Some(perms) => arguments.iter(fn_body).zip(perms).any(|(argument, perm)| {
+                        perm == PermKind::Borrow && is_part_of_variable(argument)
+                    }),
+                    None => false,
+                }
+            }
+            _ => false,
+        })
+}
+
+/// Generates an access to `place`, honoring the permission the type
+/// checker inferred for it: owned accesses move the value, while shared
+/// and borrowed ones reference it (`&` and `&mut` respectively) or, when
+/// an owned value is needed, clone it.
+fn build_place_access(
+    db: &LarkDatabase,
+    fn_body: &std::sync::Arc<hir::FnBody>,
+    results: &TypeCheckResults<FullInferred>,
+    expression: hir::Expression,
+    place: hir::Place,
+    value_use: ValueUse,
+) -> String {
+    let place_text = build_place(db, fn_body, results, place);
+
+    if let hir::PlaceData::Entity(_) = fn_body.tables[place] {
+        return place_text;
+    }
+
+    let perm = match results.access_permissions.get(&expression) {
+        Some(perm) => *perm,
+        None => return place_text,
+    };
+
+    if is_copy(db, results.access_ty(expression)) {
+        return place_text;
+    }
+
+    let holds_reference = place_holds_reference(db, fn_body, results, place);
+    match (perm, value_use) {
+        (PermKind::Own, _) => place_text,
+        (PermKind::Share, ValueUse::Owned) => format!("{}.clone()", place_text),
+        (PermKind::Share, ValueUse::Inspected) => {
+            if holds_reference {
+                place_text
+            } else {
+                format!("&{}", place_text)
+            }
+        }
+        (PermKind::Borrow, ValueUse::Owned) => match fn_body.tables[place] {
+            // Nothing else can see a temporary, so we may as well move it.
+            hir::PlaceData::Temporary(_) if !holds_reference => place_text,
+            _ => format!("{}.clone()", place_text),
+        },
+        (PermKind::Borrow, ValueUse::Inspected) => {
+            if holds_reference {
+                format!("&mut *{}", place_text)
+            } else {
+                format!("&mut {}", place_text)
+            }
+        }
+    }
+}
+
+/// Generates the argument `expression` for a parameter that the callee
+/// takes with the permission `perm` (see `parameter_perm`).
+fn build_argument(
+    db: &LarkDatabase,
+    fn_body: &std::sync::Arc<hir::FnBody>,
+    results: &TypeCheckResults<FullInferred>,
+    expression: hir::Expression,
+    perm: PermKind,
+) -> String {
+    let access_perm = results.access_permissions.get(&expression).cloned();
+
+    match (perm, fn_body.tables[expression]) {
+        (PermKind::Own, _) => build_expression(db, fn_body, results, expression, ValueUse::Owned),
+
+        // Inspecting a place we do not own already gives us a reference.
+        (PermKind::Share, hir::ExpressionData::Place { .. })
+            if access_perm.map_or(false, |access| access != PermKind::Own)
+                && !is_copy(db, results.access_ty(expression)) =>
+        {
+            build_expression(db, fn_body, results, expression, ValueUse::Inspected)
+        }
+        (PermKind::Share, _) => format!(
+            "&{}",
+            build_expression(db, fn_body, results, expression, ValueUse::Owned)
+        ),
+
+        // We can only lend out a place we may mutate; for anything else
+        // the callee mutates a copy instead.
+        (PermKind::Borrow, hir::ExpressionData::Place { place }) => {
+            let place_text = build_place(db, fn_body, results, place);
+            let holds_reference = place_holds_reference(db, fn_body, results, place);
+            match access_perm {
+                Some(PermKind::Borrow) if holds_reference => format!("&mut *{}", place_text),
+                Some(PermKind::Borrow) | Some(PermKind::Own) if !holds_reference => {
+                    format!("&mut {}", place_text)
+                }
+                _ => format!("&mut {}.clone()", place_text),
+            }
+        }
+        (PermKind::Borrow, _) => format!(
+            "&mut {}",
+            build_expression(db, fn_body, results, expression, ValueUse::Owned)
+        ),
+    }
+}
+
 pub fn build_place(
     db: &LarkDatabase,
     fn_body: &std::sync::Arc<hir::FnBody>,
+    results: &TypeCheckResults<FullInferred>,
     place: hir::Place,
 ) -> String {
     match &fn_body.tables[place] {
@@ -41,11 +370,13 @@ pub fn build_place(
 
             format!(
                 "{}.{}",
-                build_place(db, fn_body, *owner),
+                build_place(db, fn_body, results, *owner),
                 identifier.text.untern(db).to_string()
             )
         }
-        hir::PlaceData::Temporary(expression) => build_expression(db, fn_body, *expression),
+        hir::PlaceData::Temporary(expression) => {
+            build_expression(db, fn_body, results, *expression, ValueUse::Inspected)
+        }
     }
 }
 
@@ -90,13 +421,18 @@ pub fn codegen_struct(
     db: &LarkDatabase,
     entity: Entity,
     id: lark_string::GlobalIdentifier,
+    visibility: Visibility,
 ) -> WithError<String> {
     let name = id.untern(db);
     let members = db.members(entity).unwrap();
     let mut output = String::new();
     let mut errors: Vec<Diagnostic> = vec![];
 
-    output.push_str(&format!("struct {} {{\n", name));
+    output.push_str(&format!(
+        "#[derive(Clone)]\n{}struct {} {{\n",
+        visibility.prefix(),
+        name
+    ));
 
     // for Rust output, output the fields first between the curlies
     for member in members.iter() {
@@ -110,7 +446,8 @@ pub fn codegen_struct(
             } => {
                 let member_ty = db.ty(member.entity).accumulate_errors_into(&mut errors);
                 output.push_str(&format!(
-                    "{}: {},\n",
+                    "{}{}: {},\n",
+                    visibility.prefix(),
                     member_name,
                     build_type(db, &member_ty)
                 ));
@@ -128,7 +465,7 @@ pub fn codegen_struct(
                 kind: MemberKind::Method,
                 ..
             } => {
-                let mut result = codegen_function(db, member.entity, member.name);
+                let mut result = codegen_function(db, member.entity, member.name, visibility);
                 if result.errors.len() > 0 {
                     errors.append(&mut result.errors);
                 } else {
@@ -149,48 +486,76 @@ pub fn codegen_struct(
 pub fn build_expression(
     db: &LarkDatabase,
     fn_body: &std::sync::Arc<hir::FnBody>,
+    results: &TypeCheckResults<FullInferred>,
     expression: hir::Expression,
+    value_use: ValueUse,
 ) -> String {
     match fn_body.tables[expression] {
         hir::ExpressionData::Let {
             variable,
             initializer,
             body,
-        } => match initializer {
-            Some(init_expression) => format!(
-                "{{ let {} = {};\n{}}}",
-                build_variable_name(db, fn_body, variable),
-                build_expression(db, fn_body, init_expression),
-                build_expression(db, fn_body, body),
-            ),
-            None => format!("let {};\n", build_variable_name(db, fn_body, variable)),
-        },
+        } => {
+            let mutability = if is_mutated(db, fn_body, results, variable) {
+                "mut "
+            } else {
+                ""
+            };
 
-        hir::ExpressionData::Place { place } => build_place(db, fn_body, place),
+            // The initializer is accessed with the permissions of the
+            // variable, so if it is shared, the variable holds a reference.
+            match initializer {
+                Some(init_expression) => format!(
+                    "{{ let {}{} = {};\n{}}}",
+                    mutability,
+                    build_variable_name(db, fn_body, variable),
+                    build_expression(db, fn_body, results, init_expression, ValueUse::Inspected),
+                    build_expression(db, fn_body, results, body, value_use),
+                ),
+                None => format!(
+                    "{{ let {}{};\n{}}}",
+                    mutability,
+                    build_variable_name(db, fn_body, variable),
+                    build_expression(db, fn_body, results, body, value_use),
+                ),
+            }
+        }
+
+        hir::ExpressionData::Place { place } => {
+            build_place_access(db, fn_body, results, expression, place, value_use)
+        }
 
         hir::ExpressionData::Assignment { place, value } => format!(
             "{} = {};\n",
-            build_place(db, fn_body, place),
-            build_expression(db, fn_body, value)
+            build_place(db, fn_body, results, place),
+            build_expression(db, fn_body, results, value, ValueUse::Owned)
         ),
 
         hir::ExpressionData::MethodCall { method, arguments } => {
-            let mut arguments = arguments.iter(fn_body);
+            let parameter_perms = callee_parameter_perms(db, fn_body, results, expression)
+                .unwrap_or_else(|| vec![PermKind::Own; arguments.len()]);
+            let mut arguments = arguments.iter(fn_body).zip(parameter_perms);
             let mut output = String::new();
 
-            output.push_str(&build_expression(db, fn_body, arguments.next().unwrap()));
+            let (receiver, receiver_perm) = arguments.next().unwrap();
+            let receiver_text = build_argument(db, fn_body, results, receiver, receiver_perm);
+            if receiver_perm == PermKind::Own {
+                output.push_str(&receiver_text);
+            } else {
+                output.push_str(&format!("({})", receiver_text));
+            }
 
             let method_name = fn_body.tables[method].text.untern(db);
             output.push_str(&format!(".{}(", method_name));
 
             let mut first = true;
-            for argument in arguments {
+            for (argument, perm) in arguments {
                 if !first {
                     output.push_str(", ");
                 } else {
                     first = false;
                 }
-                output.push_str(&build_expression(db, fn_body, argument));
+                output.push_str(&build_argument(db, fn_body, results, argument, perm));
             }
             output.push_str(")");
 
@@ -203,12 +568,23 @@ pub fn build_expression(
         } => {
             let mut output = String::new();
 
-            output.push_str(&build_expression(db, fn_body, function));
+            output.push_str(&build_expression(
+                db,
+                fn_body,
+                results,
+                function,
+                ValueUse::Inspected,
+            ));
 
             output.push_str("(");
 
             let mut first = true;
 
+            // Functions take each parameter with the permission their
+            // body needs; `debug` only prints its argument.
+            let parameter_perms = callee_parameter_perms(db, fn_body, results, expression);
+            let mut argument_use = ValueUse::Owned;
+
             match fn_body[function] {
                 hir::ExpressionData::Place {
                     place: function_place,
@@ -217,6 +593,7 @@ pub fn build_expression(
                         EntityData::LangItem(LangItem::Debug) => {
                             output.push_str("\"{}\"");
                             first = false;
+                            argument_use = ValueUse::Inspected;
                         }
                         _ => {}
                     },
@@ -225,13 +602,28 @@ pub fn build_expression(
                 _ => {}
             }
 
-            for argument in arguments.iter(fn_body) {
+            for (index, argument) in arguments.iter(fn_body).enumerate() {
                 if !first {
                     output.push_str(", ");
                 } else {
                     first = false;
                 }
-                output.push_str(&build_expression(db, fn_body, argument));
+                match &parameter_perms {
+                    Some(perms) => output.push_str(&build_argument(
+                        db,
+                        fn_body,
+                        results,
+                        argument,
+                        perms[index],
+                    )),
+                    None => output.push_str(&build_expression(
+                        db,
+                        fn_body,
+                        results,
+                        argument,
+                        argument_use,
+                    )),
+                }
             }
             output.push_str(")");
 
@@ -240,8 +632,8 @@ pub fn build_expression(
 
         hir::ExpressionData::Sequence { first, second } => format!(
             "{};\n {}",
-            build_expression(db, fn_body, first),
-            build_expression(db, fn_body, second)
+            build_expression(db, fn_body, results, first, ValueUse::Inspected),
+            build_expression(db, fn_body, results, second, value_use)
         ),
 
         hir::ExpressionData::If {
@@ -250,9 +642,9 @@ pub fn build_expression(
             if_false,
         } => format!(
             "if {} {{ {} \n}} else {{ {} \n}}",
-            build_expression(db, fn_body, condition),
-            build_expression(db, fn_body, if_true),
-            build_expression(db, fn_body, if_false)
+            build_expression(db, fn_body, results, condition, ValueUse::Inspected),
+            build_expression(db, fn_body, results, if_true, value_use),
+            build_expression(db, fn_body, results, if_false, value_use)
         ),
 
         hir::ExpressionData::Binary {
@@ -261,7 +653,7 @@ pub fn build_expression(
             right,
         } => format!(
             "({} {} {})",
-            build_expression(db, fn_body, left),
+            build_expression(db, fn_body, results, left, ValueUse::Inspected),
             match operator {
                 hir::BinaryOperator::Add => "+",
                 hir::BinaryOperator::Subtract => "-",
@@ -270,7 +662,7 @@ pub fn build_expression(
                 hir::BinaryOperator::Equals => "==",
                 hir::BinaryOperator::NotEquals => "!=",
             },
-            build_expression(db, fn_body, right),
+            build_expression(db, fn_body, results, right, ValueUse::Inspected),
         ),
 
         hir::ExpressionData::Unary { operator, value } => format!(
@@ -278,7 +670,7 @@ pub fn build_expression(
             match operator {
                 hir::UnaryOperator::Not => "!",
             },
-            build_expression(db, fn_body, value)
+            build_expression(db, fn_body, results, value, ValueUse::Inspected)
         ),
 
         hir::ExpressionData::Literal { data } => match data {
@@ -313,7 +705,13 @@ pub fn build_expression(
                     fn_body.tables[identified_expression.identifier]
                         .text
                         .untern(db),
-                    build_expression(db, fn_body, identified_expression.expression),
+                    build_expression(
+                        db,
+                        fn_body,
+                        results,
+                        identified_expression.expression,
+                        ValueUse::Owned,
+                    ),
                 ));
             }
             output.push_str("}");
@@ -330,11 +728,15 @@ pub fn codegen_function(
     db: &LarkDatabase,
     entity: Entity,
     id: lark_string::GlobalIdentifier,
+    visibility: Visibility,
 ) -> WithError<String> {
     let mut output = String::new();
     let mut errors: Vec<Diagnostic> = vec![];
 
     let fn_body = db.fn_body(entity).accumulate_errors_into(&mut errors);
+    let results = db
+        .full_type_check(entity)
+        .accumulate_errors_into(&mut errors);
 
     let signature = db
         .signature(entity)
@@ -345,7 +747,7 @@ pub fn codegen_function(
 
     let name = id.untern(db);
 
-    output.push_str(&format!("fn {}(", name));
+    output.push_str(&format!("{}fn {}(", visibility.prefix(), name));
 
     let mut first = true;
     for (argument, argument_type) in arguments.iter(&fn_body).zip(signature.inputs.iter()) {
@@ -360,7 +762,16 @@ pub fn codegen_function(
             first = false;
         }
 
+        let perm = parameter_perm(db, &fn_body, &results, argument);
+        if perm == PermKind::Own && is_mutated(db, &fn_body, &results, argument) {
+            output.push_str("mut ");
+        }
         output.push_str(&format!("{}: ", argument_name));
+        match perm {
+            PermKind::Own => {}
+            PermKind::Share => output.push_str("&"),
+            PermKind::Borrow => output.push_str("&mut "),
+        }
         output.push_str(&format!("{}", build_type(db, argument_type)));
     }
 
@@ -368,7 +779,13 @@ pub fn codegen_function(
     output.push_str(&format!("{}", build_type(db, &signature.output)));
     output.push_str(&format!(
         " {{\n{} }}\n",
-        build_expression(db, &fn_body, fn_body.root_expression)
+        build_expression(
+            db,
+            &fn_body,
+            &results,
+            fn_body.root_expression,
+            ValueUse::Owned
+        )
     ));
 
     WithError {
@@ -377,48 +794,249 @@ pub fn codegen_function(
     }
 }
 
-/// Converts the MIR context of definitions into Rust source
-pub fn codegen_rust(db: &LarkDatabase) -> WithError<String> {
-    let mut output = String::new();
-    let input_files = db.file_names();
-    let mut errors: Vec<Diagnostic> = vec![];
+/// Generates a `#[no_mangle] extern "C"` function that forwards to the
+/// top-level function `entity`, so it can be called across the C ABI.
+/// Only functions whose arguments and result are all FFI-safe get one;
+/// for the others we leave a comment explaining why they are missing.
+pub fn codegen_extern_c_wrapper(
+    db: &LarkDatabase,
+    entity: Entity,
+    id: lark_string::GlobalIdentifier,
+) -> String {
+    let name = id.untern(db);
 
-    for &input_file in &*input_files {
-        let entities = db.top_level_entities_in_file(input_file);
-
-        for &entity in &*entities {
-            match entity.untern(&db) {
-                EntityData::ItemName {
-                    kind: ItemKind::Function,
-                    id,
-                    ..
-                } => {
-                    let mut result = codegen_function(db, entity, id);
-                    if result.errors.len() > 0 {
-                        errors.append(&mut result.errors);
-                    } else {
-                        output.push_str(&result.value);
+    let fn_body = db.fn_body(entity).into_value();
+    let signature = match db.signature(entity).into_value() {
+        Ok(signature) => signature,
+        Err(_) => return String::new(),
+    };
+    let arguments = fn_body.arguments.unwrap();
+
+    let is_ffi_safe = |rust_type: &str| match rust_type {
+        "bool" | "u32" | "i32" | "()" => true,
+        _ => false,
+    };
+
+    let mut parameters = vec![];
+    for (argument, argument_type) in arguments.iter(&fn_body).zip(signature.inputs.iter()) {
+        let argument_name = build_variable_name(db, &fn_body, argument);
+        let argument_type = build_type(db, argument_type);
+
+        if !is_ffi_safe(&argument_type) {
+            return format!(
+                "// `{}` has no extern \"C\" wrapper: `{}: {}` is not FFI-safe\n",
+                name, argument_name, argument_type
+            );
+        }
+
+        parameters.push((argument_name, argument_type));
+    }
+
+    let output_type = build_type(db, &signature.output);
+    if !is_ffi_safe(&output_type) {
+        return format!(
+            "// `{}` has no extern \"C\" wrapper: returning `{}` is not FFI-safe\n",
+            name, output_type
+        );
+    }
+
+    format!(
+        "#[no_mangle]\npub extern \"C\" fn lark_{}({}) -> {} {{\n{}({}) }}\n",
+        name,
+        parameters
+            .iter()
+            .map(|(argument_name, argument_type)| format!("{}: {}", argument_name, argument_type))
+            .collect::<Vec<_>>()
+            .join(", "),
+        output_type,
+        name,
+        parameters
+            .iter()
+            .map(|(argument_name, _)| &argument_name[..])
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+}
+
+/// Appends the Rust source for every top-level entity in `input_file` to `output`
+fn codegen_file(
+    db: &LarkDatabase,
+    input_file: FileName,
+    visibility: Visibility,
+    extern_c: bool,
+    output: &mut String,
+    errors: &mut Vec<Diagnostic>,
+) {
+    let entities = db.top_level_entities_in_file(input_file);
+
+    for &entity in &*entities {
+        match entity.untern(&db) {
+            EntityData::ItemName {
+                kind: ItemKind::Function,
+                id,
+                ..
+            } => {
+                let mut result = codegen_function(db, entity, id, visibility);
+                if result.errors.len() > 0 {
+                    errors.append(&mut result.errors);
+                } else {
+                    output.push_str(&result.value);
+
+                    if extern_c {
+                        output.push_str(&codegen_extern_c_wrapper(db, entity, id));
                     }
                 }
-                EntityData::ItemName {
-                    kind: ItemKind::Struct,
-                    id,
-                    ..
-                } => {
-                    let mut result = codegen_struct(db, entity, id);
-                    if result.errors.len() > 0 {
-                        errors.append(&mut result.errors);
-                    } else {
-                        output.push_str(&result.value);
-                    }
+            }
+            EntityData::ItemName {
+                kind: ItemKind::Struct,
+                id,
+                ..
+            } => {
+                let mut result = codegen_struct(db, entity, id, visibility);
+                if result.errors.len() > 0 {
+                    errors.append(&mut result.errors);
+                } else {
+                    output.push_str(&result.value);
                 }
-                x => unimplemented!("Can not codegen {:#?}", x.debug_with(db)),
             }
+            x => unimplemented!("Can not codegen {:#?}", x.debug_with(db)),
         }
     }
+}
+
+/// True if `input_file` defines a top-level `main` function
+fn defines_main(db: &LarkDatabase, input_file: FileName) -> bool {
+    let main_name = "main".intern(db);
+
+    db.top_level_entities_in_file(input_file)
+        .iter()
+        .any(|entity| match entity.untern(db) {
+            EntityData::ItemName {
+                kind: ItemKind::Function,
+                id,
+                ..
+            } => id == main_name,
+            _ => false,
+        })
+}
+
+/// Converts the MIR context of definitions into Rust source
+pub fn codegen_rust(db: &LarkDatabase, options: &CodegenOptions) -> WithError<String> {
+    let mut output = String::new();
+    let input_files = db.file_names();
+    let mut errors: Vec<Diagnostic> = vec![];
+
+    let visibility = match options.output_kind {
+        OutputKind::Executable => Visibility::Private,
+        OutputKind::Library => Visibility::Public,
+    };
+
+    for &input_file in &*input_files {
+        codegen_file(
+            db,
+            input_file,
+            visibility,
+            options.emits_extern_c(),
+            &mut output,
+            &mut errors,
+        );
+    }
 
     WithError {
         value: output,
         errors,
     }
 }
+
+/// Converts the MIR context of definitions into Rust source, as one
+/// module per input file. Each module glob-imports the crate root, which
+/// in turn glob-imports every module, so items can refer to each other
+/// across files just as they do in Lark.
+pub fn codegen_rust_crate(db: &LarkDatabase, options: &CodegenOptions) -> WithError<CrateSource> {
+    let input_files = db.file_names();
+    let mut errors: Vec<Diagnostic> = vec![];
+    let mut modules: Vec<ModuleSource> = vec![];
+    let mut main_module = None;
+
+    let visibility = match options.output_kind {
+        OutputKind::Executable => Visibility::Crate,
+        OutputKind::Library => Visibility::Public,
+    };
+
+    for &input_file in &*input_files {
+        let name = module_name(&input_file.id.untern(db), &modules);
+
+        let mut source = String::new();
+        source.push_str("#[allow(unused_imports)]\nuse crate::*;\n\n");
+        codegen_file(
+            db,
+            input_file,
+            visibility,
+            options.emits_extern_c(),
+            &mut source,
+            &mut errors,
+        );
+
+        let is_executable = options.output_kind == OutputKind::Executable;
+        if is_executable && main_module.is_none() && defines_main(db, input_file) {
+            main_module = Some(name.clone());
+        }
+
+        modules.push(ModuleSource { name, source });
+    }
+
+    WithError {
+        value: CrateSource {
+            modules,
+            main_module,
+            extern_c: options.emits_extern_c(),
+        },
+        errors,
+    }
+}
+
+/// Picks a Rust module name for the Lark file at `path`, based on its
+/// file stem and distinct from the names in `existing`
+fn module_name(path: &str, existing: &[ModuleSource]) -> String {
+    // Rust keywords, plus the file names of the crate roots themselves
+    const RESERVED: &[&str] = &[
+        "main", "lib", "as", "break", "const", "continue", "crate", "else", "enum", "extern",
+        "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+        "pub", "ref", "return", "self", "static", "struct", "super", "trait", "true", "type",
+        "unsafe", "use", "where", "while", "dyn", "abstract", "async", "await", "become", "box",
+        "do", "final", "macro", "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+    ];
+
+    let stem = std::path::Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut base: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if base.is_empty() || base.starts_with(|c: char| c.is_ascii_digit()) {
+        base.insert_str(0, "lark_");
+    }
+
+    if RESERVED.contains(&&base[..]) {
+        base.push('_');
+    }
+
+    let mut name = base.clone();
+    let mut suffix = 1;
+    while existing.iter().any(|module| module.name == name) {
+        suffix += 1;
+        name = format!("{}_{}", base, suffix);
+    }
+
+    name
+}
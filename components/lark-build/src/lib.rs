@@ -3,25 +3,138 @@ mod codegen_rust;
 
 use lark_error::WithError;
 use lark_query_system::LarkDatabase;
+use std::path::Path;
 
 #[derive(Copy, Clone)]
 pub enum CodegenType {
     Rust,
 }
 
+/// The kind of artifact we generate code for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutputKind {
+    /// An executable, entered through the `main` function
+    Executable,
+
+    /// A library exposing every top-level function and struct, so that
+    /// other code can call into it
+    Library,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct CodegenOptions {
+    pub output_kind: OutputKind,
+
+    /// Whether to also generate a `#[no_mangle] extern "C"` wrapper for
+    /// each top-level function whose signature is FFI-safe. Only has an
+    /// effect on libraries, which are then built as static libraries.
+    pub extern_c: bool,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        CodegenOptions {
+            output_kind: OutputKind::Executable,
+            extern_c: false,
+        }
+    }
+}
+
+impl CodegenOptions {
+    /// True if we generate the `extern "C"` surface
+    pub fn emits_extern_c(&self) -> bool {
+        self.output_kind == OutputKind::Library && self.extern_c
+    }
+}
+
+/// Options for invoking the backend compiler on the generated source
+#[derive(Clone, Debug, Default)]
+pub struct BackendOptions {
+    /// Optimization level to pass to the backend (e.g. `"3"`); the
+    /// backend's default (unoptimized) if `None`
+    pub opt_level: Option<String>,
+
+    /// Whether to include debug info in the output
+    pub debug_info: bool,
+
+    /// Target triple to compile for; the host if `None`
+    pub target: Option<String>,
+
+    /// Extra arguments handed to the backend compiler as-is
+    pub extra_args: Vec<String>,
+
+    /// Whether to keep the generated source next to the output (e.g.
+    /// `foo.rs` for `foo.exe`) rather than in a temporary file
+    pub keep_source: bool,
+}
+
+/// The generated source for a whole crate, with one module per input file
+pub struct CrateSource {
+    pub modules: Vec<ModuleSource>,
+
+    /// The module defining `main`, if any. Crates with a `main` are
+    /// emitted as binaries, all others as libraries.
+    pub main_module: Option<String>,
+
+    /// True if the crate is a library with an `extern "C"` surface
+    pub extern_c: bool,
+}
+
+/// The generated source for a single input file
+pub struct ModuleSource {
+    pub name: String,
+    pub source: String,
+}
+
 /// Converts the MIR context of definitions into the chosen source type
-pub fn codegen(db: &LarkDatabase, codegen_type: CodegenType) -> WithError<String> {
+pub fn codegen(
+    db: &LarkDatabase,
+    codegen_type: CodegenType,
+    options: &CodegenOptions,
+) -> WithError<String> {
     match codegen_type {
-        CodegenType::Rust => codegen_rust::codegen_rust(db),
+        CodegenType::Rust => codegen_rust::codegen_rust(db, options),
         //CodegenType::C => codegen_c::codegen_c(context),
     }
 }
 
+/// Converts the MIR context of definitions into the chosen source type,
+/// keeping the source of each input file in its own module
+pub fn codegen_crate(
+    db: &LarkDatabase,
+    codegen_type: CodegenType,
+    options: &CodegenOptions,
+) -> WithError<CrateSource> {
+    match codegen_type {
+        CodegenType::Rust => codegen_rust::codegen_rust_crate(db, options),
+    }
+}
+
 /// Builds source code for the given source type
 pub fn build(
     target_filename: &str,
     src: &String,
     codegen_type: CodegenType,
+    options: &CodegenOptions,
+    backend_options: &BackendOptions,
+) -> std::io::Result<()> {
+    build::build(
+        target_filename,
+        &src,
+        codegen_type,
+        options,
+        backend_options,
+    )
+}
+
+/// Writes the generated modules out as a project for the native build
+/// tool of the given source type (e.g. a Cargo crate for Rust), rooted at
+/// `target_dir`
+pub fn emit_crate(
+    target_dir: &Path,
+    crate_name: &str,
+    src: &CrateSource,
+    codegen_type: CodegenType,
 ) -> std::io::Result<()> {
-    build::build(target_filename, &src, codegen_type)
+    build::emit_crate(target_dir, crate_name, src, codegen_type)
 }
@@ -2,6 +2,7 @@ use flexi_logger::{opt_format, Logger};
 use language_reporting::{emit, Diagnostic, Label, Severity};
 use languageserver_types::Position;
 use lark_actor::Actor;
+use lark_build::{BackendOptions, CodegenOptions, OutputKind};
 use lark_entity::{EntityData, ItemKind, MemberKind};
 use lark_intern::{Intern, Untern};
 use lark_language_server::{lsp_serve, LspResponder};
@@ -14,12 +15,90 @@ use lark_span::{ByteIndex, FileName, IntoFileName, Span};
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::{env, io};
 use termcolor::{ColorChoice, StandardStream, WriteColor};
 
-pub fn build(file_name: &str, output_file_name: Option<&str>) {
+/// Options for `lark build`, parsed from the arguments after the input file
+#[derive(Debug, Default)]
+pub struct BuildOptions {
+    /// Where to write the executable. Derived from the input file name if
+    /// not given.
+    pub output_file_name: Option<String>,
+
+    /// If set, emit a Cargo crate into this directory rather than building
+    /// an executable.
+    pub emit_crate: Option<PathBuf>,
+
+    /// What to generate: an executable or a library (`--lib`), and whether
+    /// libraries get an `extern "C"` surface (`--extern-c`).
+    pub codegen: CodegenOptions,
+
+    /// Flags for the backend compiler (optimization, debug info, target,
+    /// extra arguments), and whether to keep the generated source.
+    pub backend: BackendOptions,
+}
+
+impl BuildOptions {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<BuildOptions, String> {
+        let mut options = BuildOptions::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match &arg[..] {
+                "--emit-crate" => match args.next() {
+                    Some(dir) => options.emit_crate = Some(PathBuf::from(dir)),
+                    None => return Err("`--emit-crate` requires a directory".to_string()),
+                },
+                "--lib" => options.codegen.output_kind = OutputKind::Library,
+                "--extern-c" => options.codegen.extern_c = true,
+                "-O" | "--release" => options.backend.opt_level = Some("3".to_string()),
+                "--opt-level" => match args.next() {
+                    Some(level) => options.backend.opt_level = Some(level),
+                    None => return Err("`--opt-level` requires a level".to_string()),
+                },
+                "-g" | "--debug-info" => options.backend.debug_info = true,
+                "--target" => match args.next() {
+                    Some(triple) => options.backend.target = Some(triple),
+                    None => return Err("`--target` requires a target triple".to_string()),
+                },
+                "--backend-arg" => match args.next() {
+                    Some(backend_arg) => options.backend.extra_args.push(backend_arg),
+                    None => return Err("`--backend-arg` requires an argument".to_string()),
+                },
+                "--keep-source" => options.backend.keep_source = true,
+                _ if arg.starts_with("-") => return Err(format!("unknown option `{}`", arg)),
+                _ if options.output_file_name.is_none() => options.output_file_name = Some(arg),
+                _ => return Err(format!("unexpected argument `{}`", arg)),
+            }
+        }
+
+        if options.codegen.extern_c && options.codegen.output_kind != OutputKind::Library {
+            return Err("`--extern-c` requires `--lib`".to_string());
+        }
+
+        if options.emit_crate.is_some() {
+            let backend = &options.backend;
+            if backend.opt_level.is_some()
+                || backend.debug_info
+                || backend.target.is_some()
+                || !backend.extra_args.is_empty()
+                || backend.keep_source
+            {
+                return Err(
+                    "backend options have no effect with `--emit-crate`; pass them to cargo instead"
+                        .to_string(),
+                );
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+pub fn build(file_name: &str, options: &BuildOptions) {
     let mut file = match File::open(file_name) {
         Ok(f) => f,
         Err(err) => {
@@ -48,8 +127,31 @@ pub fn build(file_name: &str, output_file_name: Option<&str>) {
         .unwrap_or_else(|Cancelled| panic!("cancelled"));
 
     if error_count == 0 {
-        let out_file_name = if let Some(path) = output_file_name {
+        if let Some(crate_dir) = &options.emit_crate {
+            let crate_name = crate_name(file_name);
+
+            let error_count = db
+                .emit_crate(crate_dir, &crate_name, &options.codegen, writer.lock())
+                .unwrap_or_else(|Cancelled| panic!("cancelled"));
+            if error_count > 0 {
+                std::process::exit(1);
+            }
+
+            return;
+        }
+
+        let out_file_name = if let Some(path) = &options.output_file_name {
             path.to_string()
+        } else if options.codegen.output_kind == OutputKind::Library {
+            let crate_name = crate_name(file_name);
+
+            if !options.codegen.extern_c {
+                format!("lib{}.rlib", crate_name)
+            } else if cfg!(windows) {
+                format!("{}.lib", crate_name)
+            } else {
+                format!("lib{}.a", crate_name)
+            }
         } else {
             let file_path = if cfg!(windows) {
                 std::path::Path::new(file_name).with_extension("exe")
@@ -60,32 +162,118 @@ pub fn build(file_name: &str, output_file_name: Option<&str>) {
             file_path.file_name().unwrap().to_str().unwrap().to_string()
         };
 
-        db.build(&out_file_name)
+        db.build(&out_file_name, &options.codegen, &options.backend)
             .unwrap_or_else(|Cancelled| panic!("cancelled"));
     }
 }
 
+/// Derives a Cargo package name from the name of the input file
+pub fn crate_name(file_name: &str) -> String {
+    let stem = Path::new(file_name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let name: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    if name.is_empty() {
+        "lark_output".to_string()
+    } else if name.starts_with(|c: char| c.is_ascii_digit()) {
+        // Package names can't start with a digit
+        format!("lark_{}", name)
+    } else {
+        name
+    }
+}
+
 pub trait LarkDatabaseExt {
     fn display_errors(&self, out: impl WriteColor) -> Result<usize, Cancelled>;
 
-    /// Build an executable into `output_file_name`.
-    fn build(&self, output_file_name: &str) -> Result<(), Cancelled>;
+    /// Build an executable (or library, depending on `options`) into
+    /// `output_file_name`, invoking the backend with `backend_options`.
+    fn build(
+        &self,
+        output_file_name: &str,
+        options: &CodegenOptions,
+        backend_options: &BackendOptions,
+    ) -> Result<(), Cancelled>;
+
+    /// Emit a Cargo crate named `crate_name` into `crate_dir`, with one
+    /// module per input file. Errors generating or writing the crate are
+    /// displayed on `out`; returns `Ok(n)` where n is the number of them.
+    fn emit_crate(
+        &self,
+        crate_dir: &Path,
+        crate_name: &str,
+        options: &CodegenOptions,
+        out: impl WriteColor,
+    ) -> Result<usize, Cancelled>;
 }
 
 impl LarkDatabaseExt for LarkDatabase {
-    fn build(&self, output_file_name: &str) -> Result<(), Cancelled> {
-        let source_file = lark_build::codegen(self, lark_build::CodegenType::Rust);
+    fn build(
+        &self,
+        output_file_name: &str,
+        options: &CodegenOptions,
+        backend_options: &BackendOptions,
+    ) -> Result<(), Cancelled> {
+        let source_file = lark_build::codegen(self, lark_build::CodegenType::Rust, options);
 
         lark_build::build(
             &output_file_name,
             &source_file.value,
             lark_build::CodegenType::Rust,
+            options,
+            backend_options,
         )
         .unwrap();
 
         Ok(())
     }
 
+    fn emit_crate(
+        &self,
+        crate_dir: &Path,
+        crate_name: &str,
+        options: &CodegenOptions,
+        mut out: impl WriteColor,
+    ) -> Result<usize, Cancelled> {
+        let crate_source = lark_build::codegen_crate(self, lark_build::CodegenType::Rust, options);
+
+        if !crate_source.errors.is_empty() {
+            for diagnostic in &crate_source.errors {
+                let error = Diagnostic::new(Severity::Error, diagnostic.label.clone())
+                    .with_label(Label::new_primary(diagnostic.span));
+
+                emit(&mut out, &self, &error, &language_reporting::DefaultConfig).unwrap();
+            }
+
+            return Ok(crate_source.errors.len());
+        }
+
+        match lark_build::emit_crate(
+            crate_dir,
+            crate_name,
+            &crate_source.value,
+            lark_build::CodegenType::Rust,
+        ) {
+            Ok(()) => Ok(0),
+            Err(err) => {
+                writeln!(
+                    out,
+                    "failed to emit crate into `{}`: {}",
+                    crate_dir.display(),
+                    err
+                )
+                .unwrap();
+                Ok(1)
+            }
+        }
+    }
+
     /// Displays all errors for the project on stderr. Returns `Ok(n)` where
     /// n is the number of errors (or `Cancelled` if execution is cancelled).
     fn display_errors(&self, mut out: impl WriteColor) -> Result<usize, Cancelled> {
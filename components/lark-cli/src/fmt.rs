@@ -0,0 +1,51 @@
+use lark_parser::{ParserDatabase, ParserDatabaseExt};
+use lark_query_system::LarkDatabase;
+use lark_span::{FileName, IntoFileName};
+use std::fs;
+
+/// Formats each of `file_names` in place. With `check`, nothing is
+/// written; instead we list the files that would change and exit with
+/// an error if there are any.
+pub fn fmt(file_names: &[String], check: bool) {
+    let mut failed = false;
+
+    for file_name in file_names {
+        let contents = match fs::read_to_string(file_name) {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!("failed to read `{}`: {}", file_name, err);
+                failed = true;
+                continue;
+            }
+        };
+
+        let mut db = LarkDatabase::default();
+        let file_id: FileName = file_name.into_file_name(&db);
+        db.add_file(file_id, contents.clone());
+
+        let formatted = match lark_parser::format_file(&db, file_id) {
+            Ok(lines) => lark_parser::formatted_text(&lines),
+            Err(_) => {
+                eprintln!("cannot format `{}`: it has syntax errors", file_name);
+                failed = true;
+                continue;
+            }
+        };
+
+        if formatted == contents {
+            continue;
+        }
+
+        if check {
+            println!("{}", file_name);
+            failed = true;
+        } else if let Err(err) = fs::write(file_name, formatted) {
+            eprintln!("failed to write `{}`: {}", file_name, err);
+            failed = true;
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -1,11 +1,26 @@
-use lark_actor::{spawn_actor, Actor, LspResponse, QueryRequest};
+use lark_actor::spawn_actor;
 use lark_language_server::{lsp_serve, LspResponder};
-use lark_query_system::QuerySystem;
-use std::sync::mpsc::{channel, Receiver, RecvError, Sender, TryRecvError};
+use lark_query_system::{LarkDatabase, QuerySystem};
+use lark_span::FileName;
+use std::sync::Arc;
 
 pub fn ide() {
-    let lsp_responder = spawn_actor(LspResponder);
-    let query_system = spawn_actor(QuerySystem::new(lsp_responder.channel));
+    let lsp_responder = spawn_actor(LspResponder::default());
+    let query_system =
+        QuerySystem::new(lsp_responder.channel.clone()).with_runner(Arc::new(run_main));
+    let query_system = spawn_actor(query_system);
 
-    lsp_serve(query_system.channel);
+    let exit_code = lsp_serve(query_system.channel.clone());
+
+    // Let the actors finish what they're doing before we go: the query
+    // system first, as it may still be sending responses.
+    query_system.join().expect("query system panicked");
+    lsp_responder.join().expect("LSP responder panicked");
+
+    std::process::exit(exit_code);
+}
+
+/// Runs a program for the IDE's "Run" code lens.
+fn run_main(db: &LarkDatabase, file_name: FileName, sink: Box<dyn FnMut(String) + Send>) {
+    lark_eval::eval_file(db, file_name, &mut lark_eval::IOHandler::streaming(sink));
 }
@@ -15,6 +15,7 @@ use flexi_logger::{opt_format, Logger};
 use std::{env, io};
 
 pub mod build;
+mod fmt;
 mod ide;
 mod repl;
 mod run;
@@ -30,19 +31,53 @@ pub fn main() {
     let mut args = std::env::args();
 
     match (args.next(), args.next(), args.next(), args.next()) {
-        (_, Some(ref cmd), Some(ref x), Some(ref out)) if cmd == "build" => {
-            build::build(x, Some(out))
+        (_, Some(ref cmd), Some(ref x), ref rest) if cmd == "build" => {
+            match build::BuildOptions::from_args(rest.clone().into_iter().chain(args)) {
+                Ok(options) => build::build(x, &options),
+                Err(message) => {
+                    eprintln!("error: {}", message);
+                    usage();
+                }
+            }
+        }
+        (_, Some(ref cmd), Some(ref x), ref rest) if cmd == "fmt" => {
+            let (flags, file_names): (Vec<String>, Vec<String>) = Some(x.clone())
+                .into_iter()
+                .chain(rest.clone())
+                .chain(args)
+                .partition(|arg| arg.starts_with("-"));
+            match &flags[..] {
+                [] if !file_names.is_empty() => fmt::fmt(&file_names, false),
+                [flag] if flag == "--check" && !file_names.is_empty() => {
+                    fmt::fmt(&file_names, true)
+                }
+                _ => usage(),
+            }
         }
-        (_, Some(ref cmd), Some(ref x), None) if cmd == "build" => build::build(x, None),
         (_, Some(ref cmd), Some(ref x), None) if cmd == "run" => run::run(x),
         (_, Some(ref cmd), None, None) if cmd == "repl" => repl::repl(),
         (_, Some(ref cmd), None, None) if cmd == "ide" => ide::ide(),
-        _ => {
-            println!("Usage:");
-            println!("  lark build <file> [<output>] - compiles the given file");
-            println!("  lark run <file>              - runs the given file");
-            println!("  lark repl                    - REPL/interactive mode");
-            println!("  lark ide                     - run the Lark languge server/IDE support");
-        }
+        _ => usage(),
     }
 }
+
+fn usage() {
+    println!("Usage:");
+    println!("  lark build <file> [<output>]            - compiles the given file");
+    println!("  lark build <file> --emit-crate <dir>    - emits the given file as a Cargo crate");
+    println!("  lark build <file> --lib [--extern-c]    - compiles the given file as a library");
+    println!("    build options:");
+    println!("      -O, --release                       - optimize the output");
+    println!("      --opt-level <level>                 - set the backend optimization level");
+    println!("      -g, --debug-info                    - include debug info");
+    println!("      --target <triple>                   - compile for the given target");
+    println!("      --backend-arg <arg>                 - pass an extra argument to the backend");
+    println!(
+        "      --keep-source                       - keep the generated source next to the output"
+    );
+    println!("  lark run <file>                         - runs the given file");
+    println!("  lark fmt [--check] <file>...            - formats the given files in place");
+    println!("    --check                               - list the files that need formatting");
+    println!("  lark repl                               - REPL/interactive mode");
+    println!("  lark ide                                - run the Lark languge server/IDE support");
+}
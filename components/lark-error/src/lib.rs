@@ -65,14 +65,41 @@ impl ErrorReported {
 pub struct Diagnostic {
     pub span: Span<FileName>,
     pub label: String,
+    pub kind: DiagnosticKind,
 }
 
 impl Diagnostic {
     pub fn new(label: String, span: Span<FileName>) -> Self {
-        Diagnostic { label, span }
+        Diagnostic {
+            label,
+            span,
+            kind: DiagnosticKind::Other,
+        }
+    }
+
+    pub fn with_kind(self, kind: DiagnosticKind) -> Self {
+        Diagnostic { kind, ..self }
     }
 }
 
+/// What a diagnostic is about, for tools that want to do more than
+/// show its label (e.g., offer to fix it). Most diagnostics are just
+/// `Other`.
+#[derive(Clone, Debug, DebugWith, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DiagnosticKind {
+    Other,
+
+    /// `name` doesn't refer to anything in scope.
+    UnknownIdentifier { name: String },
+
+    /// A struct was constructed without a value for `field`.
+    MissingField { field: String },
+
+    /// A call passed `found` arguments where `expected` were wanted
+    /// (not counting the receiver of a method call).
+    MismatchedArgumentCount { expected: usize, found: usize },
+}
+
 /// Used to indicate an operation that may report an error.  Note that
 /// there is a subtle -- but important! -- difference between
 /// `ErrorReported` and this type -- returning `Err(ErrorReported)`
@@ -3,7 +3,9 @@ use lark_entity::{Entity, EntityData, ItemKind, LangItem, MemberKind};
 use lark_hir as hir;
 use lark_intern::{Intern, Untern};
 use lark_parser::{ParserDatabase, ParserDatabaseExt};
+use lark_query_system::ls_ops::{Cancelled, LsDatabase};
 use lark_query_system::LarkDatabase;
+use lark_span::FileName;
 use std::collections::HashMap;
 use std::fmt;
 
@@ -58,6 +60,10 @@ impl EvalState {
 
 pub struct IOHandler {
     pub redirect: Option<String>,
+
+    /// If set, each line of output is passed here as it is printed,
+    /// rather than redirected or printed to stdout.
+    pub sink: Option<Box<dyn FnMut(String) + Send>>,
 }
 
 impl IOHandler {
@@ -65,14 +71,27 @@ impl IOHandler {
         if redirect_output {
             IOHandler {
                 redirect: Some(String::new()),
+                sink: None,
             }
         } else {
-            IOHandler { redirect: None }
+            IOHandler {
+                redirect: None,
+                sink: None,
+            }
+        }
+    }
+
+    pub fn streaming(sink: Box<dyn FnMut(String) + Send>) -> IOHandler {
+        IOHandler {
+            redirect: None,
+            sink: Some(sink),
         }
     }
 
     pub fn println(&mut self, output: String) {
-        if let Some(redirect_output) = &mut self.redirect {
+        if let Some(sink) = &mut self.sink {
+            sink(output);
+        } else if let Some(redirect_output) = &mut self.redirect {
             redirect_output.push_str(&output);
             redirect_output.push_str("\n");
         } else {
@@ -152,6 +171,15 @@ fn eval_fn_call(
     ready_to_execute: bool,
     io_handler: &mut IOHandler,
 ) -> Value {
+    // A program run for the IDE stops when the IDE no longer wants
+    // it, or when an edit makes the code it runs out of date; calls
+    // are where a program can go on forever. We can't return
+    // `Cancelled` through the evaluator, so we unwind with it instead,
+    // for whoever started the program to catch.
+    if let Err(Cancelled) = db.check_for_cancellation() {
+        std::panic::resume_unwind(Box::new(Cancelled));
+    }
+
     let target = db.fn_body(entity).value;
 
     for (arg, param) in arguments
@@ -491,26 +519,41 @@ pub fn eval(db: &LarkDatabase, io_handler: &mut IOHandler) {
 
     let mut eval_state = EvalState::new();
 
+    for &input_file in &*input_files {
+        eval_main(db, input_file, &mut eval_state, io_handler);
+    }
+}
+
+/// Runs the `main` function of just one file.
+pub fn eval_file(db: &LarkDatabase, input_file: FileName, io_handler: &mut IOHandler) {
+    let mut eval_state = EvalState::new();
+
+    eval_main(db, input_file, &mut eval_state, io_handler);
+}
+
+fn eval_main(
+    db: &LarkDatabase,
+    input_file: FileName,
+    eval_state: &mut EvalState,
+    io_handler: &mut IOHandler,
+) {
     let main_name = "main".intern(&db);
+    let entities = db.top_level_entities_in_file(input_file);
+
+    for &entity in &*entities {
+        match entity.untern(&db) {
+            EntityData::ItemName {
+                kind: ItemKind::Function,
+                id,
+                ..
+            } => {
+                if id == main_name {
+                    let fn_body = db.fn_body(entity);
 
-    for &input_file in &*input_files {
-        let entities = db.top_level_entities_in_file(input_file);
-
-        for &entity in &*entities {
-            match entity.untern(&db) {
-                EntityData::ItemName {
-                    kind: ItemKind::Function,
-                    id,
-                    ..
-                } => {
-                    if id == main_name {
-                        let fn_body = db.fn_body(entity);
-
-                        eval_function(db, &fn_body.value, &mut eval_state, io_handler);
-                    }
+                    eval_function(db, &fn_body.value, eval_state, io_handler);
                 }
-                _ => {}
             }
+            _ => {}
         }
     }
 }
@@ -90,6 +90,9 @@ pub struct FnBodyTables {
     /// Spans corresponding to each index
     pub spans: FxIndexMap<MetaIndex, Span<FileName>>,
 
+    /// Spans of the `{ ... }` blocks in the body, curlies included
+    pub blocks: Vec<Span<FileName>>,
+
     /// The data values for any `List<I>` values that appear elsewhere
     /// in the HIR; the way this works is that all of the list value
     /// are concatenated into one big vector, and each list just pulls
@@ -0,0 +1,58 @@
+//! Call hierarchy. This is newer than the version of
+//! `languageserver-types` we use, so the protocol types are defined
+//! here.
+
+use languageserver_types::{Range, SymbolKind};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyItem {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub uri: String,
+    pub range: Range,
+    pub selection_range: Range,
+}
+
+impl From<lark_actor::CallHierarchyItem> for CallHierarchyItem {
+    fn from(item: lark_actor::CallHierarchyItem) -> Self {
+        CallHierarchyItem {
+            name: item.name,
+            kind: item.kind,
+            detail: item.detail,
+            uri: item.file,
+            range: item.range,
+            selection_range: item.selection_range,
+        }
+    }
+}
+
+/// The parameters of both `callHierarchy/incomingCalls` and
+/// `callHierarchy/outgoingCalls`: an item we returned earlier.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CallHierarchyCallsParams {
+    pub item: CallHierarchyItem,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyIncomingCall {
+    /// The caller.
+    pub from: CallHierarchyItem,
+
+    /// The calls, in the caller.
+    pub from_ranges: Vec<Range>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyOutgoingCall {
+    /// The callee.
+    pub to: CallHierarchyItem,
+
+    /// The calls, in the item the request was for.
+    pub from_ranges: Vec<Range>,
+}
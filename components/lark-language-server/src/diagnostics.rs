@@ -0,0 +1,81 @@
+//! Pull diagnostics, where the IDE asks for a file's diagnostics
+//! rather than waiting for us to publish them. These are newer than
+//! the version of `languageserver-types` we use, so the protocol types
+//! are defined here.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticOptions {
+    /// Editing one file can change the diagnostics of others.
+    pub inter_file_dependencies: bool,
+    pub workspace_diagnostics: bool,
+}
+
+impl Default for DiagnosticOptions {
+    fn default() -> Self {
+        DiagnosticOptions {
+            inter_file_dependencies: true,
+            workspace_diagnostics: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentDiagnosticParams {
+    pub text_document: languageserver_types::TextDocumentIdentifier,
+    pub identifier: Option<String>,
+    pub previous_result_id: Option<String>,
+}
+
+/// All the diagnostics for a file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FullDocumentDiagnosticReport {
+    pub kind: String,
+    pub result_id: String,
+    pub items: Vec<languageserver_types::Diagnostic>,
+}
+
+impl FullDocumentDiagnosticReport {
+    pub fn new(result_id: String, items: Vec<languageserver_types::Diagnostic>) -> Self {
+        FullDocumentDiagnosticReport {
+            kind: "full".into(),
+            result_id,
+            items,
+        }
+    }
+}
+
+/// The diagnostics for a file are those of the earlier result the IDE
+/// told us it has.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnchangedDocumentDiagnosticReport {
+    pub kind: String,
+    pub result_id: String,
+}
+
+impl UnchangedDocumentDiagnosticReport {
+    pub fn new(result_id: String) -> Self {
+        UnchangedDocumentDiagnosticReport {
+            kind: "unchanged".into(),
+            result_id,
+        }
+    }
+}
+
+/// Our diagnostics (the range and message of each), as the protocol
+/// sends them.
+pub fn to_lsp(
+    diagnostics: &[(languageserver_types::Range, String)],
+) -> Vec<languageserver_types::Diagnostic> {
+    diagnostics
+        .iter()
+        .map(|(range, message)| {
+            languageserver_types::Diagnostic::new_simple(*range, message.clone())
+        })
+        .collect()
+}
@@ -0,0 +1,34 @@
+//! Inlay hints. These are newer than the version of
+//! `languageserver-types` we use, so the protocol types are defined
+//! here.
+
+use languageserver_types::{Position, Range, TextDocumentIdentifier};
+use serde::{Deserialize, Serialize};
+
+/// The hint is the type of the thing before it.
+pub const TYPE: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHintParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHint {
+    pub position: Position,
+    pub label: String,
+    pub kind: u32,
+}
+
+impl From<lark_actor::InlayHint> for InlayHint {
+    fn from(hint: lark_actor::InlayHint) -> Self {
+        InlayHint {
+            position: hint.position,
+            label: hint.label,
+            kind: TYPE,
+        }
+    }
+}
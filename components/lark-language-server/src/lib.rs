@@ -1,12 +1,23 @@
-use lark_actor::{self, Actor, LspResponse, QueryRequest};
+use languageserver_types::{
+    code_action_kind, CodeActionOptions, CodeActionProviderCapability, CodeActionResponse,
+    FoldingRangeProviderCapability, TypeDefinitionProviderCapability,
+};
+use lark_actor::{self, Actor, CodeLensKind, LspResponse, PositionEncoding, QueryRequest};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::io;
-use std::io::prelude::{Read, Write};
+use std::io::prelude::{BufRead, Read, Write};
 use std::sync::mpsc::Sender;
 use url::Url;
 
+mod call_hierarchy;
+mod diagnostics;
+mod inlay_hints;
+mod selection_ranges;
+mod semantic_tokens;
+use self::semantic_tokens::{SemanticTokensDeltaParams, SemanticTokensParams};
+
 /// The command given by the IDE to the LSP server. These represent the actions of the user in the IDE,
 /// as well as actions the IDE might perform as a result of user actions (like cancelling a task)
 #[derive(Debug, Serialize, Deserialize)]
@@ -18,6 +29,10 @@ pub enum LSPCommand {
         params: languageserver_types::InitializeParams,
     },
     initialized,
+    shutdown {
+        id: usize,
+    },
+    exit,
     #[serde(rename = "textDocument/didOpen")]
     didOpen {
         params: languageserver_types::DidOpenTextDocumentParams,
@@ -26,6 +41,18 @@ pub enum LSPCommand {
     didChange {
         params: languageserver_types::DidChangeTextDocumentParams,
     },
+    #[serde(rename = "textDocument/didClose")]
+    didClose {
+        params: languageserver_types::DidCloseTextDocumentParams,
+    },
+    #[serde(rename = "textDocument/didSave")]
+    didSave {
+        params: languageserver_types::DidSaveTextDocumentParams,
+    },
+    #[serde(rename = "workspace/didChangeWatchedFiles")]
+    didChangeWatchedFiles {
+        params: languageserver_types::DidChangeWatchedFilesParams,
+    },
     #[serde(rename = "textDocument/hover")]
     hover {
         id: usize,
@@ -36,11 +63,106 @@ pub enum LSPCommand {
         id: usize,
         params: languageserver_types::CompletionParams,
     },
+    #[serde(rename = "textDocument/signatureHelp")]
+    signatureHelp {
+        id: usize,
+        params: languageserver_types::TextDocumentPositionParams,
+    },
+    #[serde(rename = "textDocument/codeAction")]
+    codeAction {
+        id: usize,
+        params: languageserver_types::CodeActionParams,
+    },
+    #[serde(rename = "textDocument/inlayHint")]
+    inlayHint {
+        id: usize,
+        params: inlay_hints::InlayHintParams,
+    },
+    #[serde(rename = "textDocument/formatting")]
+    formatting {
+        id: usize,
+        params: languageserver_types::DocumentFormattingParams,
+    },
+    #[serde(rename = "textDocument/rangeFormatting")]
+    rangeFormatting {
+        id: usize,
+        params: languageserver_types::DocumentRangeFormattingParams,
+    },
+    #[serde(rename = "textDocument/semanticTokens/full")]
+    semanticTokens {
+        id: usize,
+        params: SemanticTokensParams,
+    },
+    #[serde(rename = "textDocument/semanticTokens/full/delta")]
+    semanticTokensDelta {
+        id: usize,
+        params: SemanticTokensDeltaParams,
+    },
+    #[serde(rename = "textDocument/diagnostic")]
+    diagnostic {
+        id: usize,
+        params: diagnostics::DocumentDiagnosticParams,
+    },
+    #[serde(rename = "textDocument/documentSymbol")]
+    documentSymbol {
+        id: usize,
+        params: languageserver_types::DocumentSymbolParams,
+    },
+    #[serde(rename = "textDocument/codeLens")]
+    codeLens {
+        id: usize,
+        params: languageserver_types::CodeLensParams,
+    },
+    #[serde(rename = "workspace/executeCommand")]
+    executeCommand {
+        id: usize,
+        params: languageserver_types::ExecuteCommandParams,
+    },
+    #[serde(rename = "textDocument/prepareCallHierarchy")]
+    prepareCallHierarchy {
+        id: usize,
+        params: languageserver_types::TextDocumentPositionParams,
+    },
+    #[serde(rename = "callHierarchy/incomingCalls")]
+    incomingCalls {
+        id: usize,
+        params: call_hierarchy::CallHierarchyCallsParams,
+    },
+    #[serde(rename = "callHierarchy/outgoingCalls")]
+    outgoingCalls {
+        id: usize,
+        params: call_hierarchy::CallHierarchyCallsParams,
+    },
+    #[serde(rename = "textDocument/foldingRange")]
+    foldingRange {
+        id: usize,
+        params: languageserver_types::FoldingRangeParams,
+    },
+    #[serde(rename = "textDocument/selectionRange")]
+    selectionRange {
+        id: usize,
+        params: selection_ranges::SelectionRangeParams,
+    },
+    #[serde(rename = "workspace/symbol")]
+    workspaceSymbol {
+        id: usize,
+        params: languageserver_types::WorkspaceSymbolParams,
+    },
     #[serde(rename = "textDocument/definition")]
     definition {
         id: usize,
         params: languageserver_types::TextDocumentPositionParams,
     },
+    #[serde(rename = "textDocument/typeDefinition")]
+    typeDefinition {
+        id: usize,
+        params: languageserver_types::TextDocumentPositionParams,
+    },
+    #[serde(rename = "textDocument/documentHighlight")]
+    documentHighlight {
+        id: usize,
+        params: languageserver_types::TextDocumentPositionParams,
+    },
     #[serde(rename = "textDocument/references")]
     references {
         id: usize,
@@ -51,6 +173,11 @@ pub enum LSPCommand {
         id: usize,
         params: languageserver_types::RenameParams,
     },
+    #[serde(rename = "textDocument/prepareRename")]
+    prepareRename {
+        id: usize,
+        params: languageserver_types::TextDocumentPositionParams,
+    },
     #[serde(rename = "$/cancelRequest")]
     cancelRequest {
         params: languageserver_types::CancelParams,
@@ -80,6 +207,63 @@ impl<T> JsonRPCResponse<T> {
     }
 }
 
+/// A JSON 2.0 RPC error response, for requests we could not answer. The
+/// id is `None` if we couldn't even tell which request it was.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonRPCErrorResponse {
+    jsonrpc: String,
+    pub id: Option<usize>,
+    pub error: JsonRPCError,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonRPCError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl JsonRPCErrorResponse {
+    pub fn new(id: Option<usize>, code: i64, message: String) -> Self {
+        JsonRPCErrorResponse {
+            jsonrpc: "2.0".into(),
+            id,
+            error: JsonRPCError { code, message },
+        }
+    }
+}
+
+// Error codes from the JSON-RPC and LSP specs.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_CANCELLED: i64 = -32800;
+const REQUEST_FAILED: i64 = -32803;
+
+/// The command behind the "Run" code lens; its argument is the URL of
+/// the file whose `main` to run.
+const RUN_COMMAND: &str = "lark.run";
+
+/// A wrapper for requests from us to the IDE (eg. to register for
+/// notifications). These must follow the JSON 2.0 RPC spec
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonRPCRequest<T> {
+    jsonrpc: String,
+    pub id: usize,
+    pub method: String,
+    pub params: T,
+}
+impl<T> JsonRPCRequest<T> {
+    pub fn new(id: usize, method: String, params: T) -> Self {
+        JsonRPCRequest {
+            jsonrpc: "2.0".into(),
+            id,
+            method,
+            params,
+        }
+    }
+}
+
 /// A wrapper for proactive notifications to the IDE (eg. diagnostics). These must
 /// follow the JSON 2.0 RPC spec
 #[derive(Debug, Serialize, Deserialize)]
@@ -98,24 +282,111 @@ impl<T> JsonRPCNotification<T> {
     }
 }
 
+/// The capabilities we announce in response to `initialize`: those that
+/// `languageserver-types` knows about, plus newer ones that it doesn't.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerCapabilities {
+    #[serde(flatten)]
+    base: languageserver_types::ServerCapabilities,
+    semantic_tokens_provider: semantic_tokens::SemanticTokensOptions,
+    inlay_hint_provider: bool,
+    selection_range_provider: bool,
+    call_hierarchy_provider: bool,
+    diagnostic_provider: diagnostics::DiagnosticOptions,
+    /// The encoding we picked from those the IDE offered.
+    position_encoding: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct InitializeResult {
+    capabilities: ServerCapabilities,
+}
+
+/// Writes a message to the IDE. Both the server and the responder send
+/// messages, so each one is written while holding the lock on stdout.
+fn send_message<T: Serialize>(message: &T) {
+    let message_raw = serde_json::to_string(message).unwrap();
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let _ = write!(
+        stdout,
+        "Content-Length: {}\r\n\r\n{}",
+        message_raw.len(),
+        message_raw
+    );
+    let _ = stdout.flush();
+}
+
 /// Helper function to do the work of sending a result back to the IDE
 fn send_response<T: Serialize>(id: usize, result: T) {
-    let response = JsonRPCResponse::new(id, result);
-    let response_raw = serde_json::to_string(&response).unwrap();
+    send_message(&JsonRPCResponse::new(id, result));
+}
 
-    print!("Content-Length: {}\r\n\r\n", response_raw.len());
-    print!("{}", response_raw);
-    let _ = io::stdout().flush();
+/// Helper function to tell the IDE that we could not answer a request
+fn send_error(id: Option<usize>, code: i64, message: String) {
+    send_message(&JsonRPCErrorResponse::new(id, code, message));
 }
 
 /// Helper function to send a proactive notification back to the IDE
 fn send_notification<T: Serialize>(method: String, notice: T) {
-    let response = JsonRPCNotification::new(method, notice);
-    let response_raw = serde_json::to_string(&response).unwrap();
+    send_message(&JsonRPCNotification::new(method, notice));
+}
+
+/// The folders of the workspace the IDE opened: its workspace folders
+/// if it has any, otherwise its root.
+fn workspace_roots(params: &languageserver_types::InitializeParams) -> Vec<Url> {
+    if let Some(folders) = &params.workspace_folders {
+        return folders.iter().map(|folder| folder.uri.clone()).collect();
+    }
 
-    print!("Content-Length: {}\r\n\r\n", response_raw.len());
-    print!("{}", response_raw);
-    let _ = io::stdout().flush();
+    params
+        .root_uri
+        .clone()
+        .or_else(|| {
+            let root_path = params.root_path.as_ref()?;
+            Url::from_file_path(root_path).ok()
+        })
+        .into_iter()
+        .collect()
+}
+
+/// Asks the IDE to tell us when `.lark` files change on disk.
+fn register_file_watcher(id: usize) {
+    let options = languageserver_types::DidChangeWatchedFilesRegistrationOptions {
+        watchers: vec![languageserver_types::FileSystemWatcher {
+            glob_pattern: "**/*.lark".into(),
+            kind: None,
+        }],
+    };
+    let registration = languageserver_types::Registration {
+        id: "lark-watched-files".into(),
+        method: "workspace/didChangeWatchedFiles".into(),
+        register_options: Some(serde_json::to_value(options).unwrap()),
+    };
+
+    send_message(&JsonRPCRequest::new(
+        id,
+        "client/registerCapability".into(),
+        languageserver_types::RegistrationParams {
+            registrations: vec![registration],
+        },
+    ));
+}
+
+/// Picks the first of the position encodings the IDE lists in its
+/// `initialize` request that we support. IDEs that don't list any
+/// only understand UTF-16.
+fn negotiate_position_encoding(message: &serde_json::Value) -> PositionEncoding {
+    message["params"]["capabilities"]["general"]["positionEncodings"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|name| name.as_str())
+        .filter_map(PositionEncoding::from_name)
+        .next()
+        .unwrap_or(PositionEncoding::Utf16)
 }
 
 /// The LSP service is split into two parts:
@@ -124,7 +395,16 @@ fn send_notification<T: Serialize>(method: String, notice: T) {
 /// The server sends messages *to* the task manager for work that
 /// needs to be done. The responder receives messages *from* the
 /// task manager for work that has been accomplished.
-pub struct LspResponder;
+#[derive(Default)]
+pub struct LspResponder {
+    /// The last semantic tokens we sent for each file, and the id of
+    /// that result, so that we can send just the changes next time.
+    semantic_tokens: HashMap<Url, (String, Vec<u32>)>,
+
+    /// Likewise, the last diagnostics the IDE pulled for each file.
+    pulled_diagnostics: HashMap<Url, (String, Vec<(languageserver_types::Range, String)>)>,
+    next_result_id: usize,
+}
 
 impl Actor for LspResponder {
     type InMessage = LspResponse;
@@ -134,12 +414,15 @@ impl Actor for LspResponder {
     /// manner.
     fn receive_messages(&mut self, messages: &mut VecDeque<Self::InMessage>) {
         match messages.pop_front().unwrap() {
-            LspResponse::Type(id, ty) => {
+            LspResponse::Hover(id, range, markdown) => {
                 let result = languageserver_types::Hover {
-                    contents: languageserver_types::HoverContents::Scalar(
-                        languageserver_types::MarkedString::from_markdown(ty),
+                    contents: languageserver_types::HoverContents::Markup(
+                        languageserver_types::MarkupContent {
+                            kind: languageserver_types::MarkupKind::Markdown,
+                            value: markdown,
+                        },
                     ),
-                    range: None,
+                    range: Some(range),
                 };
 
                 send_response(id, result);
@@ -179,17 +462,38 @@ impl Actor for LspResponder {
 
                 send_response(id, result);
             }
+            LspResponse::TextEdits(id, edits) => {
+                let result: Vec<_> = edits
+                    .into_iter()
+                    .map(|(range, new_text)| languageserver_types::TextEdit { range, new_text })
+                    .collect();
+
+                send_response(id, result);
+            }
             LspResponse::Nothing(id) => {
                 send_response(id, ());
             }
+            LspResponse::Cancelled(id) => {
+                send_error(Some(id), REQUEST_CANCELLED, "request cancelled".into());
+            }
+            LspResponse::Failed(id, message) => {
+                send_error(Some(id), REQUEST_FAILED, message);
+            }
+            LspResponse::RenameRange(id, range, placeholder) => {
+                let result = languageserver_types::PrepareRenameResponse::RangeWithPlaceholder {
+                    range,
+                    placeholder,
+                };
+
+                send_response(id, result);
+            }
             LspResponse::Completions(id, completions) => {
                 let mut completion_items = vec![];
 
-                for completion in completions {
-                    completion_items.push(languageserver_types::CompletionItem::new_simple(
-                        completion.0,
-                        completion.1,
-                    ));
+                for (label, detail, kind) in completions {
+                    let mut item = languageserver_types::CompletionItem::new_simple(label, detail);
+                    item.kind = Some(kind);
+                    completion_items.push(item);
                 }
 
                 let result = languageserver_types::CompletionList {
@@ -199,172 +503,695 @@ impl Actor for LspResponder {
 
                 send_response(id, result);
             }
-            LspResponse::Initialized(id) => {
-                let result = languageserver_types::InitializeResult {
-                    capabilities: languageserver_types::ServerCapabilities {
-                        text_document_sync: Some(
-                            languageserver_types::TextDocumentSyncCapability::Kind(
-                                languageserver_types::TextDocumentSyncKind::Incremental,
+            LspResponse::SignatureHelp(id, signature_help) => {
+                send_response(id, signature_help);
+            }
+            LspResponse::CodeLenses(id, url, lenses) => {
+                let result: Vec<_> = lenses
+                    .into_iter()
+                    .map(|lens| {
+                        let command = match lens.kind {
+                            CodeLensKind::RunMain => languageserver_types::Command::new(
+                                "Run".to_string(),
+                                RUN_COMMAND.to_string(),
+                                Some(vec![serde_json::Value::String(url.to_string())]),
                             ),
-                        ),
-                        hover_provider: Some(true),
-                        /*
-                        completion_provider: Some(languageserver_types::CompletionOptions {
-                            resolve_provider: Some(true),
-                            trigger_characters: Some(vec![".".into()]),
-                        }),
-                        */
-                        completion_provider: None,
-                        signature_help_provider: None,
-                        definition_provider: Some(true),
-                        type_definition_provider: None,
-                        implementation_provider: None,
-                        references_provider: Some(true),
-                        document_highlight_provider: None,
-                        document_symbol_provider: None,
-                        workspace_symbol_provider: None,
-                        code_action_provider: None,
-                        code_lens_provider: None,
-                        document_formatting_provider: None,
-                        document_range_formatting_provider: None,
-                        document_on_type_formatting_provider: None,
-                        rename_provider: Some(
-                            languageserver_types::RenameProviderCapability::Simple(true),
-                        ),
-                        color_provider: None,
-                        folding_range_provider: None,
-                        execute_command_provider: None,
-                        workspace: None,
-                    },
+                            CodeLensKind::References(references) => {
+                                let title = match references.len() {
+                                    1 => "1 reference".to_string(),
+                                    count => format!("{} references", count),
+                                };
+                                let locations: Vec<_> = references
+                                    .into_iter()
+                                    .filter_map(|(file, range)| {
+                                        let uri = Url::parse(&file).ok()?;
+                                        Some(languageserver_types::Location { uri, range })
+                                    })
+                                    .collect();
+
+                                // The editor shows the references itself.
+                                languageserver_types::Command::new(
+                                    title,
+                                    "editor.action.showReferences".to_string(),
+                                    Some(vec![
+                                        serde_json::Value::String(url.to_string()),
+                                        serde_json::to_value(&lens.range.start).unwrap(),
+                                        serde_json::to_value(&locations).unwrap(),
+                                    ]),
+                                )
+                            }
+                        };
+
+                        languageserver_types::CodeLens {
+                            range: lens.range,
+                            command: Some(command),
+                            data: None,
+                        }
+                    })
+                    .collect();
+
+                send_response(id, result);
+            }
+            LspResponse::RunOutput(line) => {
+                let notice = languageserver_types::LogMessageParams {
+                    typ: languageserver_types::MessageType::Info,
+                    message: line,
                 };
 
+                send_notification("window/logMessage".into(), notice);
+            }
+            LspResponse::CallHierarchyItems(id, items) => {
+                let result: Vec<call_hierarchy::CallHierarchyItem> =
+                    items.into_iter().map(Into::into).collect();
                 send_response(id, result);
             }
-            LspResponse::Diagnostics(url, diagnostics) => {
-                let lsp_diagnostics: Vec<languageserver_types::Diagnostic> = diagnostics
-                    .iter()
-                    .map(|(range, diag)| {
-                        languageserver_types::Diagnostic::new_simple(*range, diag.clone())
+            LspResponse::IncomingCalls(id, calls) => {
+                let result: Vec<_> = calls
+                    .into_iter()
+                    .map(
+                        |(item, from_ranges)| call_hierarchy::CallHierarchyIncomingCall {
+                            from: item.into(),
+                            from_ranges,
+                        },
+                    )
+                    .collect();
+                send_response(id, result);
+            }
+            LspResponse::OutgoingCalls(id, calls) => {
+                let result: Vec<_> = calls
+                    .into_iter()
+                    .map(
+                        |(item, from_ranges)| call_hierarchy::CallHierarchyOutgoingCall {
+                            to: item.into(),
+                            from_ranges,
+                        },
+                    )
+                    .collect();
+                send_response(id, result);
+            }
+            LspResponse::FoldingRanges(id, folds) => {
+                send_response(id, folds);
+            }
+            LspResponse::SelectionRanges(id, ranges) => {
+                let result: Vec<_> = ranges
+                    .into_iter()
+                    .filter_map(selection_ranges::SelectionRange::nested)
+                    .collect();
+                send_response(id, result);
+            }
+            LspResponse::InlayHints(id, hints) => {
+                let result: Vec<inlay_hints::InlayHint> =
+                    hints.into_iter().map(Into::into).collect();
+                send_response(id, result);
+            }
+            LspResponse::QuickFixes(id, url, fixes) => {
+                let actions: Vec<_> = fixes
+                    .into_iter()
+                    .map(|fix| {
+                        let (range, label) = fix.diagnostic;
+                        let edits = fix
+                            .edits
+                            .into_iter()
+                            .map(|(range, new_text)| languageserver_types::TextEdit {
+                                range,
+                                new_text,
+                            })
+                            .collect();
+                        let mut changes = HashMap::new();
+                        changes.insert(url.clone(), edits);
+
+                        languageserver_types::CodeAction {
+                            title: fix.title,
+                            kind: Some(code_action_kind::QUICKFIX.to_string()),
+                            diagnostics: Some(vec![languageserver_types::Diagnostic::new_simple(
+                                range, label,
+                            )]),
+                            edit: Some(languageserver_types::WorkspaceEdit {
+                                changes: Some(changes),
+                                document_changes: None,
+                            }),
+                            command: None,
+                        }
                     })
                     .collect();
 
+                send_response(id, CodeActionResponse::Actions(actions));
+            }
+            LspResponse::SemanticTokens(id, url, tokens, previous_result_id) => {
+                let data = semantic_tokens::encode(&tokens);
+                let result_id = self.next_result_id.to_string();
+                self.next_result_id += 1;
+
+                match self.semantic_tokens.get(&url) {
+                    Some((last_result_id, last_data))
+                        if previous_result_id.as_ref() == Some(last_result_id) =>
+                    {
+                        let result = semantic_tokens::SemanticTokensDelta {
+                            result_id: result_id.clone(),
+                            edits: semantic_tokens::diff(last_data, &data),
+                        };
+                        send_response(id, result);
+                    }
+                    _ => {
+                        let result = semantic_tokens::SemanticTokens {
+                            result_id: result_id.clone(),
+                            data: data.clone(),
+                        };
+                        send_response(id, result);
+                    }
+                }
+
+                self.semantic_tokens.insert(url, (result_id, data));
+            }
+            LspResponse::DocumentHighlights(id, highlights) => {
+                send_response(id, highlights);
+            }
+            LspResponse::DocumentSymbols(id, symbols) => {
+                let result = languageserver_types::DocumentSymbolResponse::Nested(symbols);
+
+                send_response(id, result);
+            }
+            LspResponse::WorkspaceSymbols(id, symbols) => {
+                send_response(id, symbols);
+            }
+            LspResponse::Initialized(id, position_encoding) => {
+                let result = InitializeResult {
+                    capabilities: ServerCapabilities {
+                        base: languageserver_types::ServerCapabilities {
+                            text_document_sync: Some(
+                                languageserver_types::TextDocumentSyncCapability::Options(
+                                    languageserver_types::TextDocumentSyncOptions {
+                                        open_close: Some(true),
+                                        change: Some(
+                                            languageserver_types::TextDocumentSyncKind::Incremental,
+                                        ),
+                                        ..Default::default()
+                                    },
+                                ),
+                            ),
+                            hover_provider: Some(true),
+                            completion_provider: Some(languageserver_types::CompletionOptions {
+                                resolve_provider: Some(false),
+                                trigger_characters: Some(vec![".".into()]),
+                            }),
+                            signature_help_provider: Some(
+                                languageserver_types::SignatureHelpOptions {
+                                    trigger_characters: Some(vec!["(".into(), ",".into()]),
+                                },
+                            ),
+                            definition_provider: Some(true),
+                            type_definition_provider: Some(
+                                TypeDefinitionProviderCapability::Simple(true),
+                            ),
+                            implementation_provider: None,
+                            references_provider: Some(true),
+                            document_highlight_provider: Some(true),
+                            document_symbol_provider: Some(true),
+                            workspace_symbol_provider: Some(true),
+                            code_action_provider: Some(CodeActionProviderCapability::Options(
+                                CodeActionOptions {
+                                    code_action_kinds: Some(vec![
+                                        code_action_kind::QUICKFIX.to_string()
+                                    ]),
+                                },
+                            )),
+                            code_lens_provider: Some(languageserver_types::CodeLensOptions {
+                                resolve_provider: Some(false),
+                            }),
+                            document_formatting_provider: Some(true),
+                            document_range_formatting_provider: Some(true),
+                            document_on_type_formatting_provider: None,
+                            rename_provider: Some(
+                                languageserver_types::RenameProviderCapability::Options(
+                                    languageserver_types::RenameOptions {
+                                        prepare_provider: Some(true),
+                                    },
+                                ),
+                            ),
+                            color_provider: None,
+                            folding_range_provider: Some(FoldingRangeProviderCapability::Simple(
+                                true,
+                            )),
+                            execute_command_provider: Some(
+                                languageserver_types::ExecuteCommandOptions {
+                                    commands: vec![RUN_COMMAND.to_string()],
+                                },
+                            ),
+                            workspace: None,
+                        },
+                        semantic_tokens_provider: Default::default(),
+                        inlay_hint_provider: true,
+                        selection_range_provider: true,
+                        call_hierarchy_provider: true,
+                        diagnostic_provider: Default::default(),
+                        position_encoding: position_encoding.name(),
+                    },
+                };
+
+                send_response(id, result);
+            }
+            LspResponse::Diagnostics(url, diagnostics) => {
                 let notice = languageserver_types::PublishDiagnosticsParams {
                     uri: url,
-                    diagnostics: lsp_diagnostics,
+                    diagnostics: diagnostics::to_lsp(&diagnostics),
                 };
 
                 send_notification("textDocument/publishDiagnostics".into(), notice);
             }
+            LspResponse::PulledDiagnostics(id, url, diagnostics, previous_result_id) => {
+                match self.pulled_diagnostics.get(&url) {
+                    Some((last_result_id, last_diagnostics))
+                        if previous_result_id.as_ref() == Some(last_result_id)
+                            && *last_diagnostics == diagnostics =>
+                    {
+                        let result = diagnostics::UnchangedDocumentDiagnosticReport::new(
+                            last_result_id.clone(),
+                        );
+                        send_response(id, result);
+                    }
+                    _ => {
+                        let result_id = self.next_result_id.to_string();
+                        self.next_result_id += 1;
+
+                        let result = diagnostics::FullDocumentDiagnosticReport::new(
+                            result_id.clone(),
+                            diagnostics::to_lsp(&diagnostics),
+                        );
+                        send_response(id, result);
+
+                        self.pulled_diagnostics
+                            .insert(url, (result_id, diagnostics));
+                    }
+                }
+            }
         }
     }
 }
 
+/// Reads the next message from the IDE: some headers, each on its own
+/// line, then an empty line and a body of `Content-Length` bytes.
+/// Returns `None` once the IDE closes its end. An error of kind
+/// `InvalidData` means this message was unreadable, but the next one
+/// can still be found; after any other error, it can't.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut content_length = None;
+
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            match content_length {
+                Some(_) => break,
+                // A stray line break between messages.
+                None => continue,
+            }
+        }
+
+        // The only other header is `Content-Type`, and the only content
+        // type there is is JSON in UTF-8.
+        let mut parts = header.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            // Without the length, we can't tell where the body ends
+            // and the next message starts.
+            let length = value.parse::<usize>().map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("bad Content-Length `{}`: {}", value, err),
+                )
+            })?;
+            content_length = Some(length);
+        }
+    }
+
+    let mut body = vec![0u8; content_length.unwrap()];
+    input.read_exact(&mut body)?;
+
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
 /// The workhorse function for handling incoming requests from the IDE. This will
 /// take instructions from stdin sent by the IDE and then send them to the appropriate
-/// system.
-pub fn lsp_serve(send_to_query_channel: Sender<QueryRequest>) {
+/// system. Returns once the IDE tells us to exit, with the exit code for
+/// the server: 0 if the IDE asked us to shut down first, 1 otherwise.
+pub fn lsp_serve(send_to_query_channel: Sender<QueryRequest>) -> i32 {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut shutting_down = false;
+
+    // Whether the IDE lets us ask to be told about changes on disk.
+    let mut can_watch_files = false;
+
+    // The id of our next request to the IDE.
+    let mut next_request_id = 0;
+
     loop {
-        let mut input = String::new();
-        match io::stdin().read_line(&mut input) {
-            Ok(_) => {
-                let content_length_items: Vec<&str> = input.split(' ').collect();
-                if content_length_items[0] == "Content-Length:" {
-                    let num_bytes: usize = content_length_items[1].trim().parse().unwrap();
-                    let mut buffer = vec![0u8; num_bytes + 2];
-                    let _ = io::stdin().read_exact(&mut buffer);
-
-                    let buffer_string = String::from_utf8(buffer).unwrap();
-
-                    let command = serde_json::from_str::<LSPCommand>(&buffer_string);
-
-                    match command {
-                        Ok(LSPCommand::initialize { id, .. }) => {
-                            let _ = send_to_query_channel.send(QueryRequest::Initialize(id));
-                        }
-                        Ok(LSPCommand::initialized) => {
-                            //eprintln!("Initialized received");
-                        }
-                        Ok(LSPCommand::didOpen { params }) => {
-                            //eprintln!("didOpen: {:#?}", params);
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => return 1,
+            Err(error) => {
+                eprintln!("error reading message: {}", error);
 
-                            let _ = send_to_query_channel.send(QueryRequest::OpenFile(
-                                params.text_document.uri.clone(),
-                                params.text_document.text.clone(),
-                            ));
-                        }
-                        Ok(LSPCommand::didChange { params }) => {
-                            //eprintln!("didChange: {:#?}", params);
-
-                            let changes = params
-                                .content_changes
-                                .iter()
-                                .map(|x| (x.range.unwrap(), x.text.clone()))
-                                .collect();
-
-                            let _ = send_to_query_channel.send(QueryRequest::EditFile(
-                                params.text_document.uri.clone(),
-                                changes,
-                            ));
-                        }
-                        Ok(LSPCommand::hover { id, params }) => {
-                            //eprintln!("hover: id={} {:#?}", id, params);
-
-                            let _ = send_to_query_channel.send(QueryRequest::TypeAtPosition(
-                                id,
-                                params.text_document.uri.clone(),
-                                params.position.clone(),
-                            ));
-                        }
-                        Ok(LSPCommand::definition { id, params }) => {
-                            let _ = send_to_query_channel.send(QueryRequest::DefinitionAtPosition(
-                                id,
-                                params.text_document.uri.clone(),
-                                params.position.clone(),
-                            ));
-                        }
-                        Ok(LSPCommand::references { id, params }) => {
-                            let _ = send_to_query_channel.send(QueryRequest::ReferencesAtPosition(
-                                id,
-                                params.text_document.uri.clone(),
-                                params.position.clone(),
-                                true,
-                            ));
-                        }
-                        Ok(LSPCommand::rename { id, params }) => {
-                            let _ = send_to_query_channel.send(QueryRequest::RenameAtPosition(
-                                id,
-                                params.text_document.uri.clone(),
-                                params.position.clone(),
-                                params.new_name.clone(),
-                            ));
-                        }
-                        Ok(LSPCommand::completion { .. }) => {
-                            //eprintln!("completion: id={} {:#?}", id, params);
-                        }
-                        Ok(LSPCommand::completionItemResolve { .. }) => {
-                            //Note: this is here in case we need it, though it looks like it's only used
-                            //for more expensive computations on a completion (like fetching the docs)
-                            //eprintln!("resolve completion item: id={} {:#?}", id, params);
-                        }
-                        Ok(LSPCommand::cancelRequest {
-                            params: languageserver_types::CancelParams { id },
-                        }) => match id {
-                            languageserver_types::NumberOrString::Number(_num) => {
-                                //eprintln!("cancelling item: id={}", num);
-                                /* FIXME FIXME: removing cancelling for the time being
-                                let _ = send_to_manager_channel
-                                    .send(MsgToManager::Cancel(num as usize));
-                                */
-                            }
-                            _ => unimplemented!(
-                                "Non-number cancellation IDs not currently supported"
-                            ),
-                        },
-                        Err(e) => eprintln!("Error handling command: {:?}", e),
+                // We skipped the bad message, and can go on with the
+                // next one.
+                if error.kind() == io::ErrorKind::InvalidData {
+                    continue;
+                }
+                return 1;
+            }
+        };
+
+        let message: serde_json::Value = match serde_json::from_str(&message) {
+            Ok(message) => message,
+            Err(error) => {
+                send_error(None, PARSE_ERROR, error.to_string());
+                continue;
+            }
+        };
+
+        // Notifications have no id, and get no response even if we
+        // don't understand them.
+        let id = message
+            .get("id")
+            .and_then(|id| id.as_u64())
+            .map(|id| id as usize);
+
+        // Messages without a method are the IDE's responses to our
+        // requests, which need no answer.
+        if message.get("method").is_none() {
+            continue;
+        }
+
+        // `languageserver-types` predates position encodings, so we
+        // read the ones the IDE offers from the raw message.
+        let position_encoding = negotiate_position_encoding(&message);
+
+        let command = match serde_json::from_value::<LSPCommand>(message) {
+            Ok(command) => command,
+            Err(error) => {
+                if id.is_some() {
+                    let message = error.to_string();
+                    let code = if message.starts_with("unknown variant") {
+                        METHOD_NOT_FOUND
+                    } else {
+                        INVALID_PARAMS
+                    };
+                    send_error(id, code, message);
+                }
+                continue;
+            }
+        };
+
+        if shutting_down {
+            match command {
+                LSPCommand::exit => return 0,
+                _ => {
+                    if id.is_some() {
+                        send_error(id, INVALID_REQUEST, "the server is shutting down".into());
+                    }
+                    continue;
+                }
+            }
+        }
+
+        match command {
+            LSPCommand::initialize { id, params } => {
+                can_watch_files = params
+                    .capabilities
+                    .workspace
+                    .as_ref()
+                    .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+                    .and_then(|watched_files| watched_files.dynamic_registration)
+                    .unwrap_or(false);
+
+                let _ = send_to_query_channel.send(QueryRequest::Initialize(id, position_encoding));
+                let _ = send_to_query_channel
+                    .send(QueryRequest::LoadWorkspace(workspace_roots(&params)));
+            }
+            LSPCommand::shutdown { id } => {
+                shutting_down = true;
+                let _ = send_to_query_channel.send(QueryRequest::Shutdown(id));
+            }
+            LSPCommand::exit => {
+                // We weren't asked to shut down first.
+                return 1;
+            }
+            LSPCommand::initialized => {
+                if can_watch_files {
+                    register_file_watcher(next_request_id);
+                    next_request_id += 1;
+                }
+            }
+            LSPCommand::didChangeWatchedFiles { params } => {
+                let changes = params
+                    .changes
+                    .into_iter()
+                    .map(|change| (change.uri, change.typ))
+                    .collect();
+
+                let _ = send_to_query_channel.send(QueryRequest::WatchedFilesChanged(changes));
+            }
+            LSPCommand::didOpen { params } => {
+                //eprintln!("didOpen: {:#?}", params);
+
+                let _ = send_to_query_channel.send(QueryRequest::OpenFile(
+                    params.text_document.uri.clone(),
+                    params.text_document.text.clone(),
+                ));
+            }
+            LSPCommand::didChange { params } => {
+                //eprintln!("didChange: {:#?}", params);
+
+                let changes = params
+                    .content_changes
+                    .iter()
+                    .map(|x| (x.range, x.text.clone()))
+                    .collect();
+
+                let _ = send_to_query_channel.send(QueryRequest::EditFile(
+                    params.text_document.uri.clone(),
+                    changes,
+                ));
+            }
+            LSPCommand::didClose { params } => {
+                let _ = send_to_query_channel
+                    .send(QueryRequest::CloseFile(params.text_document.uri.clone()));
+            }
+            LSPCommand::didSave { .. } => {
+                // We already have the saved contents from `didChange`.
+            }
+            LSPCommand::hover { id, params } => {
+                //eprintln!("hover: id={} {:#?}", id, params);
+
+                let _ = send_to_query_channel.send(QueryRequest::TypeAtPosition(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.position.clone(),
+                ));
+            }
+            LSPCommand::definition { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::DefinitionAtPosition(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.position.clone(),
+                ));
+            }
+            LSPCommand::typeDefinition { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::TypeDefinitionAtPosition(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.position.clone(),
+                ));
+            }
+            LSPCommand::documentHighlight { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::DocumentHighlights(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.position.clone(),
+                ));
+            }
+            LSPCommand::references { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::ReferencesAtPosition(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.position.clone(),
+                    true,
+                ));
+            }
+            LSPCommand::rename { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::RenameAtPosition(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.position.clone(),
+                    params.new_name.clone(),
+                ));
+            }
+            LSPCommand::prepareRename { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::PrepareRename(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.position.clone(),
+                ));
+            }
+            LSPCommand::completion { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::CompletionsAtPosition(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.position.clone(),
+                ));
+            }
+            LSPCommand::signatureHelp { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::SignatureHelpAtPosition(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.position.clone(),
+                ));
+            }
+            LSPCommand::codeAction { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::CodeActions(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.range,
+                ));
+            }
+            LSPCommand::inlayHint { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::InlayHints(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.range,
+                ));
+            }
+            LSPCommand::formatting { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::Format(
+                    id,
+                    params.text_document.uri.clone(),
+                    None,
+                ));
+            }
+            LSPCommand::rangeFormatting { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::Format(
+                    id,
+                    params.text_document.uri.clone(),
+                    Some(params.range),
+                ));
+            }
+            LSPCommand::semanticTokens { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::SemanticTokens(
+                    id,
+                    params.text_document.uri.clone(),
+                    None,
+                ));
+            }
+            LSPCommand::semanticTokensDelta { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::SemanticTokens(
+                    id,
+                    params.text_document.uri.clone(),
+                    Some(params.previous_result_id.clone()),
+                ));
+            }
+            LSPCommand::diagnostic { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::PullDiagnostics(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.previous_result_id.clone(),
+                ));
+            }
+            LSPCommand::documentSymbol { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::DocumentSymbols(
+                    id,
+                    params.text_document.uri.clone(),
+                ));
+            }
+            LSPCommand::codeLens { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::CodeLenses(
+                    id,
+                    params.text_document.uri.clone(),
+                ));
+            }
+            LSPCommand::executeCommand { id, params } => {
+                let url = params
+                    .arguments
+                    .get(0)
+                    .and_then(|argument| argument.as_str())
+                    .and_then(|argument| Url::parse(argument).ok());
+                match url {
+                    Some(url) if params.command == RUN_COMMAND => {
+                        let _ = send_to_query_channel.send(QueryRequest::RunMain(id, url));
                     }
+                    _ => send_error(
+                        Some(id),
+                        INVALID_PARAMS,
+                        format!("cannot execute `{}`", params.command),
+                    ),
                 }
             }
-            Err(error) => eprintln!("error: {}", error),
+            LSPCommand::prepareCallHierarchy { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::PrepareCallHierarchy(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.position,
+                ));
+            }
+            LSPCommand::incomingCalls { id, params } => match Url::parse(&params.item.uri) {
+                Ok(url) => {
+                    let _ = send_to_query_channel.send(QueryRequest::IncomingCalls(
+                        id,
+                        url,
+                        params.item.selection_range.start,
+                    ));
+                }
+                Err(error) => send_error(Some(id), INVALID_PARAMS, error.to_string()),
+            },
+            LSPCommand::outgoingCalls { id, params } => match Url::parse(&params.item.uri) {
+                Ok(url) => {
+                    let _ = send_to_query_channel.send(QueryRequest::OutgoingCalls(
+                        id,
+                        url,
+                        params.item.selection_range.start,
+                    ));
+                }
+                Err(error) => send_error(Some(id), INVALID_PARAMS, error.to_string()),
+            },
+            LSPCommand::foldingRange { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::FoldingRanges(
+                    id,
+                    params.text_document.uri.clone(),
+                ));
+            }
+            LSPCommand::selectionRange { id, params } => {
+                let _ = send_to_query_channel.send(QueryRequest::SelectionRanges(
+                    id,
+                    params.text_document.uri.clone(),
+                    params.positions.clone(),
+                ));
+            }
+            LSPCommand::workspaceSymbol { id, params } => {
+                let _ = send_to_query_channel
+                    .send(QueryRequest::WorkspaceSymbols(id, params.query.clone()));
+            }
+            LSPCommand::completionItemResolve { .. } => {
+                //Note: this is here in case we need it, though it looks like it's only used
+                //for more expensive computations on a completion (like fetching the docs)
+                //eprintln!("resolve completion item: id={} {:#?}", id, params);
+            }
+            LSPCommand::cancelRequest {
+                params: languageserver_types::CancelParams { id },
+            } => match id {
+                languageserver_types::NumberOrString::Number(num) => {
+                    let _ = send_to_query_channel.send(QueryRequest::Cancel(num as usize));
+                }
+                languageserver_types::NumberOrString::String(_) => {
+                    // We only accept requests with numeric ids, so
+                    // there is nothing to cancel.
+                }
+            },
         }
     }
 }
@@ -0,0 +1,33 @@
+//! Selection ranges ("expand selection"). These are newer than the
+//! version of `languageserver-types` we use, so the protocol types are
+//! defined here.
+
+use languageserver_types::{Position, Range, TextDocumentIdentifier};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectionRangeParams {
+    pub text_document: TextDocumentIdentifier,
+    pub positions: Vec<Position>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SelectionRange {
+    pub range: Range,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<Box<SelectionRange>>,
+}
+
+impl SelectionRange {
+    /// Links up `ranges`, which go from innermost to outermost, so that
+    /// each one is the parent of the one before it.
+    pub fn nested(ranges: Vec<Range>) -> Option<SelectionRange> {
+        ranges.into_iter().rev().fold(None, |parent, range| {
+            Some(SelectionRange {
+                range,
+                parent: parent.map(Box::new),
+            })
+        })
+    }
+}
@@ -0,0 +1,178 @@
+//! Semantic tokens (semantic highlighting). These are newer than the
+//! version of `languageserver-types` we use, so the protocol types are
+//! defined here.
+
+use lark_actor::{SemanticToken, SemanticTokenKind};
+use serde::{Deserialize, Serialize};
+
+/// The token types we report; the index in this list is how a token's
+/// type is sent to the IDE.
+const TOKEN_TYPES: &[&str] = &[
+    "struct",
+    "function",
+    "method",
+    "property",
+    "variable",
+    "parameter",
+    "type",
+];
+
+/// The token modifiers we report; each one is a bit in a token's
+/// modifier set.
+const TOKEN_MODIFIERS: &[&str] = &["declaration", "readonly", "defaultLibrary"];
+
+const DECLARATION: u32 = 1 << 0;
+const READONLY: u32 = 1 << 1;
+const DEFAULT_LIBRARY: u32 = 1 << 2;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensLegend {
+    pub token_types: Vec<String>,
+    pub token_modifiers: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SemanticTokensFullOptions {
+    /// The server supports sending just the changes since an earlier
+    /// result.
+    pub delta: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SemanticTokensOptions {
+    pub legend: SemanticTokensLegend,
+    pub range: bool,
+    pub full: SemanticTokensFullOptions,
+}
+
+impl Default for SemanticTokensOptions {
+    fn default() -> Self {
+        SemanticTokensOptions {
+            legend: SemanticTokensLegend {
+                token_types: TOKEN_TYPES.iter().map(|s| s.to_string()).collect(),
+                token_modifiers: TOKEN_MODIFIERS.iter().map(|s| s.to_string()).collect(),
+            },
+            range: false,
+            full: SemanticTokensFullOptions { delta: true },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensParams {
+    pub text_document: languageserver_types::TextDocumentIdentifier,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensDeltaParams {
+    pub text_document: languageserver_types::TextDocumentIdentifier,
+    pub previous_result_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokens {
+    pub result_id: String,
+    pub data: Vec<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensDelta {
+    pub result_id: String,
+    pub edits: Vec<SemanticTokensEdit>,
+}
+
+/// Replace `delete_count` numbers of the previous result, starting at
+/// `start`, with `data`.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensEdit {
+    pub start: u32,
+    pub delete_count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Vec<u32>>,
+}
+
+fn token_type_and_modifiers(kind: SemanticTokenKind) -> (u32, u32) {
+    match kind {
+        SemanticTokenKind::Struct => (0, 0),
+        SemanticTokenKind::Function => (1, 0),
+        SemanticTokenKind::Method => (2, 0),
+        SemanticTokenKind::Field => (3, 0),
+        SemanticTokenKind::Variable => (4, 0),
+        SemanticTokenKind::Parameter => (5, 0),
+        SemanticTokenKind::BuiltinType => (6, DEFAULT_LIBRARY),
+        SemanticTokenKind::BuiltinFunction => (1, DEFAULT_LIBRARY),
+        SemanticTokenKind::BuiltinConstant => (4, READONLY | DEFAULT_LIBRARY),
+    }
+}
+
+/// Encodes tokens the way the protocol wants them: five numbers per
+/// token, with each token's position relative to the one before it.
+/// The tokens must be in order and each on a single line.
+pub fn encode(tokens: &[SemanticToken]) -> Vec<u32> {
+    let mut data = Vec::with_capacity(tokens.len() * 5);
+    let mut previous_line = 0;
+    let mut previous_start = 0;
+
+    for token in tokens {
+        let line = token.range.start.line as u32;
+        let start = token.range.start.character as u32;
+        let length = (token.range.end.character - token.range.start.character) as u32;
+
+        let delta_line = line - previous_line;
+        let delta_start = if delta_line == 0 {
+            start - previous_start
+        } else {
+            start
+        };
+
+        let (token_type, mut modifiers) = token_type_and_modifiers(token.kind);
+        if token.declaration {
+            modifiers |= DECLARATION;
+        }
+
+        data.extend(&[delta_line, delta_start, length, token_type, modifiers]);
+        previous_line = line;
+        previous_start = start;
+    }
+
+    data
+}
+
+/// The edits that turn `previous` into `current`: everything between
+/// their common prefix and common suffix is replaced in a single edit.
+/// Typing only changes the tokens near the cursor, so this is small.
+pub fn diff(previous: &[u32], current: &[u32]) -> Vec<SemanticTokensEdit> {
+    let prefix = previous
+        .iter()
+        .zip(current)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = previous[prefix..]
+        .iter()
+        .rev()
+        .zip(current[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let deleted = &previous[prefix..previous.len() - suffix];
+    let inserted = &current[prefix..current.len() - suffix];
+    if deleted.is_empty() && inserted.is_empty() {
+        return vec![];
+    }
+
+    vec![SemanticTokensEdit {
+        start: prefix as u32,
+        delete_count: deleted.len() as u32,
+        data: if inserted.is_empty() {
+            None
+        } else {
+            Some(inserted.to_vec())
+        },
+    }]
+}
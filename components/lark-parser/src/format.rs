@@ -0,0 +1,263 @@
+//! A formatter for Lark source files. It works on the tokens of the
+//! file rather than on the parsed syntax, so comments survive; what it
+//! changes is the whitespace between tokens (indentation and spacing)
+//! plus the `,` after each field of a multi-line struct.
+
+use crate::lexer::token::LexToken;
+use crate::ParserDatabase;
+
+use lark_error::ErrorReported;
+use lark_span::{FileName, Span};
+use std::ops::Range;
+
+const INDENT: &str = "    ";
+
+/// One line of formatted output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormattedLine {
+    /// The (zero-based) lines of the original text that this line
+    /// replaces. This is more than one line when a block comment or a
+    /// string literal spans several lines.
+    pub lines: Range<usize>,
+
+    /// The formatted text, without a trailing newline; `None` if the
+    /// lines are removed entirely (e.g., a run of blank lines).
+    pub text: Option<String>,
+}
+
+/// Formats `file_name`, giving the replacement for each line. Fails if
+/// the file has syntax errors, since we can't be sure what the tokens
+/// mean then.
+pub fn format_file(
+    db: &impl ParserDatabase,
+    file_name: FileName,
+) -> Result<Vec<FormattedLine>, ErrorReported> {
+    let tokens = db.file_tokens(file_name).into_result()?;
+    db.parsed_file(file_name).into_result()?;
+
+    if let Some(error) = tokens.iter().find(|t| t.value == LexToken::Error) {
+        return Err(ErrorReported::at_span(error.span));
+    }
+
+    let text = db.file_text(file_name);
+    let mut formatter = Formatter {
+        text: &text,
+        delimiters: vec![],
+        struct_pending: false,
+    };
+
+    let mut lines = vec![];
+    let mut current_line = 0;
+    let mut line_start = 0;
+    let mut line_tokens: Vec<(LexToken, Span<FileName>)> = vec![];
+    for token in tokens.iter() {
+        match token.value {
+            LexToken::Whitespace => {}
+            LexToken::Newline => {
+                let text = formatter.format_line(&line_tokens);
+                lines.push(FormattedLine {
+                    lines: line_start..current_line + 1,
+                    text: Some(text),
+                });
+                line_tokens.clear();
+                current_line += 1;
+                line_start = current_line;
+            }
+            _ => {
+                current_line += formatter.token_text(token.span).matches('\n').count();
+                line_tokens.push((token.value, token.span));
+            }
+        }
+    }
+
+    if !line_tokens.is_empty() {
+        let text = formatter.format_line(&line_tokens);
+        lines.push(FormattedLine {
+            lines: line_start..current_line + 1,
+            text: Some(text),
+        });
+    }
+
+    remove_extra_blank_lines(&mut lines);
+
+    Ok(lines)
+}
+
+/// The formatted text of a whole file, given the result of
+/// `format_file`.
+pub fn formatted_text(lines: &[FormattedLine]) -> String {
+    let mut output = String::new();
+    for text in lines.iter().filter_map(|line| line.text.as_ref()) {
+        output.push_str(text);
+        output.push('\n');
+    }
+    output
+}
+
+/// Blank lines are kept only singly, and only between two other lines
+/// that are not the opening or closing of a block.
+fn remove_extra_blank_lines(lines: &mut [FormattedLine]) {
+    let is_blank = |line: &FormattedLine| line.text.as_ref().map_or(false, |t| t.is_empty());
+
+    let mut previous: Option<String> = None;
+    for index in 0..lines.len() {
+        if !is_blank(&lines[index]) {
+            previous = lines[index].text.clone();
+            continue;
+        }
+
+        let next = lines[index + 1..]
+            .iter()
+            .filter_map(|line| line.text.as_ref())
+            .find(|text| !text.is_empty());
+
+        let keep = match (&previous, next) {
+            (Some(previous), Some(next)) => {
+                !previous.is_empty()
+                    && !previous.ends_with('{')
+                    && !previous.ends_with('(')
+                    && !next.trim_start().starts_with('}')
+                    && !next.trim_start().starts_with(')')
+            }
+            _ => false,
+        };
+
+        if keep {
+            previous = Some(String::new());
+        } else {
+            lines[index].text = None;
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Delimiter {
+    Parenthesis,
+    Curly,
+    StructBody,
+}
+
+struct Formatter<'text> {
+    text: &'text str,
+
+    /// The open delimiters at the start of the line being formatted.
+    delimiters: Vec<Delimiter>,
+
+    /// True if we have seen `struct` but not yet the `{` of its body.
+    struct_pending: bool,
+}
+
+impl Formatter<'text> {
+    fn token_text(&self, span: Span<FileName>) -> &'text str {
+        &self.text[span.start().to_usize()..span.end().to_usize()]
+    }
+
+    fn format_line(&mut self, tokens: &[(LexToken, Span<FileName>)]) -> String {
+        if tokens.is_empty() {
+            return String::new();
+        }
+
+        let mut words: Vec<(LexToken, &str)> = tokens
+            .iter()
+            .map(|&(kind, span)| match kind {
+                LexToken::Comment => (kind, self.token_text(span).trim_end()),
+                _ => (kind, self.token_text(span)),
+            })
+            .collect();
+
+        let closers = words
+            .iter()
+            .take_while(|(_, text)| *text == "}" || *text == ")")
+            .count();
+        let depth = self.delimiters.len().saturating_sub(closers);
+
+        if self.delimiters.last() == Some(&Delimiter::StructBody) {
+            add_field_comma(&mut words);
+        }
+
+        for &(kind, text) in &words {
+            match (kind, text) {
+                (LexToken::Identifier, "struct") => self.struct_pending = true,
+                (LexToken::Sigil, "(") => self.delimiters.push(Delimiter::Parenthesis),
+                (LexToken::Sigil, "{") => {
+                    if self.struct_pending {
+                        self.struct_pending = false;
+                        self.delimiters.push(Delimiter::StructBody);
+                    } else {
+                        self.delimiters.push(Delimiter::Curly);
+                    }
+                }
+                (LexToken::Sigil, "}") | (LexToken::Sigil, ")") => {
+                    self.delimiters.pop();
+                }
+                _ => {}
+            }
+        }
+
+        let mut output = INDENT.repeat(depth);
+        for (index, &(kind, text)) in words.iter().enumerate() {
+            if index > 0 {
+                let before = if index > 1 { Some(words[index - 2]) } else { None };
+                if space_between(before, words[index - 1], (kind, text)) {
+                    output.push(' ');
+                }
+            }
+            output.push_str(text);
+        }
+        output
+    }
+}
+
+/// Adds the `,` missing after a field (`name: Type`) in a multi-line
+/// struct body. It goes after the type, before any trailing comment.
+fn add_field_comma(words: &mut Vec<(LexToken, &str)>) {
+    match (words.get(0), words.get(1)) {
+        (Some((LexToken::Identifier, _)), Some((LexToken::Sigil, ":"))) => {}
+        _ => return,
+    }
+
+    let end = words
+        .iter()
+        .rposition(|&(kind, _)| kind != LexToken::Comment)
+        .unwrap();
+    match words[end] {
+        (LexToken::Sigil, ",") | (LexToken::Sigil, "}") | (LexToken::Sigil, "{") => {}
+        _ => words.insert(end + 1, (LexToken::Sigil, ",")),
+    }
+}
+
+/// Keywords that are followed by a space even before `(`.
+const KEYWORDS: &[&str] = &["if", "else", "while", "let", "return", "def", "struct"];
+
+/// Should there be a space between `previous` and `current`? `before`
+/// is the token preceding `previous`, used to recognize unary `-`.
+fn space_between(
+    before: Option<(LexToken, &str)>,
+    previous: (LexToken, &str),
+    current: (LexToken, &str),
+) -> bool {
+    let is_sigil = |(kind, _): (LexToken, &str)| kind == LexToken::Sigil;
+
+    match (previous, current) {
+        ((LexToken::Comment, _), _) | (_, (LexToken::Comment, _)) => true,
+        ((_, "("), _) | (_, (_, ")")) => false,
+        (_, (LexToken::Sigil, ",")) | (_, (LexToken::Sigil, ":")) => false,
+        ((LexToken::Sigil, "."), _) | (_, (LexToken::Sigil, ".")) => false,
+        ((LexToken::Sigil, ","), _) | ((LexToken::Sigil, ":"), _) => true,
+        ((_, "{"), (_, "}")) => false,
+        ((_, "{"), _) | (_, (_, "{")) | ((_, "}"), _) | (_, (_, "}")) => true,
+        (_, (LexToken::Sigil, "(")) => match previous {
+            (LexToken::Identifier, text) => KEYWORDS.contains(&text),
+            (LexToken::Sigil, ")") => false,
+            (LexToken::Sigil, _) => true,
+            _ => false,
+        },
+        ((LexToken::Sigil, "-"), _) if !is_sigil(current) => match before {
+            None => false,
+            Some((_, ")")) => true,
+            Some((LexToken::Identifier, text)) => !KEYWORDS.contains(&text),
+            Some(before) => !is_sigil(before),
+        },
+        _ => true,
+    }
+}
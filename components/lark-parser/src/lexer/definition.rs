@@ -48,18 +48,21 @@ impl LexerDelegateTrait for LexerState {
                     '"' => consume(c).and_transition(StringLiteral),
                     '\n' => LexerNext::sigil(LexToken::Newline),
                     c if c.is_whitespace() => LexerNext::begin(Whitespace),
-                    _ if rest.starts_with("/*") => consume_str("/*").and_push(Comment(1)),
                     _ => consume(c).and_emit(LexToken::Error).and_remain(),
                 },
             },
 
-            LexerState::Slash => match c {
-                None => reconsume()
-                    .and_emit(LexToken::Sigil)
-                    .and_transition(LexerState::Top),
-                Some('/') => reconsume().and_transition(LexerState::EolComment),
-                Some(c) => consume(c).and_transition(LexerState::Sigil),
-            },
+            // `begin` does not consume, so `rest` still starts with the
+            // first `/` here.
+            LexerState::Slash => {
+                if rest.starts_with("//") {
+                    reconsume().and_transition(LexerState::EolComment)
+                } else if rest.starts_with("/*") {
+                    consume_str("/*").and_transition(LexerState::Comment(1))
+                } else {
+                    consume('/').and_transition(LexerState::Sigil)
+                }
+            }
 
             LexerState::Sigil => match c {
                 None => reconsume()
@@ -175,7 +178,7 @@ impl LexerDelegateTrait for LexerState {
                 None => reconsume()
                     .and_emit(LexToken::Comment)
                     .and_transition(LexerState::Top),
-                Some('\n') => consume('\n')
+                Some('\n') => reconsume()
                     .and_emit(LexToken::Comment)
                     .and_transition(LexerState::Top),
                 Some(c) => consume(c).and_remain(),
@@ -68,6 +68,8 @@ fn test_quicklex() -> Result<(), Span<CurrentFile>> {
             0122344444 Sigil Whitespace Integer Whitespace Integer
             ////foo bar baz
             000000000000000 Comment
+            a / b /* c */ d
+            012345666666678 Identifier Whitespace Sigil Whitespace Identifier Whitespace Comment Whitespace Identifier
             "##,
     );
 
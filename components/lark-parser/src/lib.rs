@@ -5,7 +5,6 @@
 #![feature(try_blocks)]
 #![allow(dead_code)]
 
-use crate::lexer::token::LexToken;
 use crate::macros::EntityMacroDefinition;
 use crate::syntax::entity::ParsedEntity;
 use lark_collections::{FxIndexMap, Seq};
@@ -23,6 +22,7 @@ use lark_span::ByteIndex;
 use lark_span::FileName;
 use lark_span::IntoFileName;
 use lark_span::Location;
+use lark_span::PositionEncoding;
 use lark_span::Span;
 use lark_span::Spanned;
 use lark_string::GlobalIdentifier;
@@ -34,6 +34,7 @@ use lark_ty::declaration::DeclarationTables;
 use std::sync::Arc;
 
 pub mod current_file;
+mod format;
 mod ir;
 mod lexer;
 pub mod macros;
@@ -43,7 +44,10 @@ mod scope;
 pub mod syntax;
 mod type_conversion;
 
+pub use self::format::{format_file, formatted_text, FormattedLine};
 pub use self::ir::ParsedFile;
+pub use self::lexer::token::LexToken;
+pub use self::scope::LANG_ITEM_NAMES;
 
 #[salsa::query_group(ParserStorage)]
 pub trait ParserDatabase:
@@ -55,6 +59,12 @@ pub trait ParserDatabase:
     #[salsa::input]
     fn file_text(&self, id: FileName) -> Text;
 
+    /// How the columns of a `Location` (and the columns given to
+    /// `byte_index`) are counted: characters, unless the language
+    /// server agrees something else with the editor.
+    #[salsa::input]
+    fn position_encoding(&self) -> PositionEncoding;
+
     #[salsa::invoke(query_definitions::entity_span)]
     fn entity_span(&self, entity: Entity) -> Span<FileName>;
 
@@ -72,7 +82,8 @@ pub trait ParserDatabase:
     fn location(&self, id: FileName, index: ByteIndex) -> Location;
 
     /// Given a (zero-based) line number `line` and column within
-    /// the line, gives a byte-index into the file's text.
+    /// the line, gives a byte-index into the file's text. Positions
+    /// past the end of a line (or of the file) are clamped to it.
     #[salsa::invoke(query_definitions::byte_index)]
     fn byte_index(&self, id: FileName, line: u64, column: u64) -> ByteIndex;
 
@@ -160,18 +171,37 @@ pub enum HoverTargetKind {
 pub trait ParserDatabaseExt: ParserDatabase {
     fn init_parser_db(&mut self) {
         self.set_file_names(Default::default());
+        self.set_position_encoding(PositionEncoding::Utf32);
     }
 
     fn add_file(&mut self, path: impl IntoFileName, contents: impl Into<Text>) {
         let file_name = path.into_file_name(&self);
 
         let mut file_names = self.file_names();
-        file_names.extend(Some(file_name));
+        if !file_names.contains(&file_name) {
+            file_names.extend(Some(file_name));
+            self.set_file_names(file_names);
+        }
 
-        self.set_file_names(file_names);
         self.set_file_text(file_name, contents.into());
     }
 
+    /// Removes a file from the project, so its entities are no longer
+    /// visible to the other files.
+    fn remove_file(&mut self, path: impl IntoFileName) {
+        let file_name = path.into_file_name(&self);
+
+        let file_names: Seq<_> = self
+            .file_names()
+            .iter()
+            .cloned()
+            .filter(|&other| other != file_name)
+            .collect();
+
+        self.set_file_names(file_names);
+        self.set_file_text(file_name, Text::from(String::new()));
+    }
+
     /// Returns the "top-level" entities defined in the given file --
     /// does not descend to visit the children of those entities etc.
     fn top_level_entities_in_file(&self, file: impl IntoFileName) -> Seq<Entity> {
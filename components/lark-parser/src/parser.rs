@@ -6,7 +6,7 @@ use crate::syntax::Syntax;
 use lark_collections::{FxIndexMap, Seq};
 use lark_debug_with::DebugWith;
 use lark_entity::EntityTables;
-use lark_error::{Diagnostic, ErrorReported, WithError};
+use lark_error::{Diagnostic, DiagnosticKind, ErrorReported, WithError};
 use lark_span::{FileName, Span, Spanned};
 use lark_string::{GlobalIdentifier, GlobalIdentifierTables, Text};
 use std::sync::Arc;
@@ -297,6 +297,19 @@ impl Parser<'parse> {
     ) -> ErrorReported {
         report_error(&mut self.errors, message, span)
     }
+
+    /// Report an error with the given message and kind at the given
+    /// span.
+    crate fn report_error_of_kind(
+        &mut self,
+        message: impl Into<String>,
+        kind: DiagnosticKind,
+        span: Span<FileName>,
+    ) -> ErrorReported {
+        let diagnostic = crate::diagnostic(message, span).with_kind(kind);
+        self.errors.push(diagnostic);
+        ErrorReported::at_diagnostic(self.errors.last().unwrap())
+    }
 }
 
 impl AsRef<GlobalIdentifierTables> for Parser<'_> {
@@ -182,9 +182,11 @@ crate fn location(db: &impl ParserDatabase, id: FileName, index: ByteIndex) -> L
             // Found something in the middle.
             let line_start = line_offsets[line];
 
-            // count utf-8 characters to find column
+            // measure the text before us on the line to find column
             let text: &str = &db.file_text(id);
-            let column = text[line_start..index.to_usize()].chars().count();
+            let column = db
+                .position_encoding()
+                .len(&text[line_start..index.to_usize()]);
 
             Location::new(line, column, index)
         }
@@ -192,11 +194,22 @@ crate fn location(db: &impl ParserDatabase, id: FileName, index: ByteIndex) -> L
 }
 
 crate fn byte_index(db: &impl ParserDatabase, id: FileName, line: u64, column: u64) -> ByteIndex {
-    let line = line as usize;
-    let column = column as usize;
+    let text: &str = &db.file_text(id);
     let line_offsets = db.line_offsets(id);
-    let line_start = line_offsets[line];
-    ByteIndex::from(line_start + column)
+    let line_start = match line_offsets.get(line as usize) {
+        Some(&line_start) => line_start,
+        None => return ByteIndex::from(text.len()),
+    };
+
+    // The line, without its terminating newline.
+    let line_end = line_offsets
+        .get(line as usize + 1)
+        .cloned()
+        .unwrap_or(text.len());
+    let line_text = text[line_start..line_end].trim_end_matches(|c| c == '\n' || c == '\r');
+
+    let offset = db.position_encoding().byte_offset(line_text, column as usize);
+    ByteIndex::from(line_start + offset)
 }
 
 crate fn descendant_entities(db: &impl ParserDatabase, root: Entity) -> Seq<Entity> {
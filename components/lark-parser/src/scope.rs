@@ -6,6 +6,18 @@ use lark_intern::Intern;
 use lark_intern::Untern;
 use lark_string::GlobalIdentifier;
 
+/// The names that are implicitly in scope in every file, and the lang
+/// items they refer to.
+pub const LANG_ITEM_NAMES: &[(&str, LangItem)] = &[
+    ("bool", LangItem::Boolean),
+    ("int", LangItem::Int),
+    ("uint", LangItem::Uint),
+    ("false", LangItem::False),
+    ("true", LangItem::True),
+    ("String", LangItem::String),
+    ("debug", LangItem::Debug),
+];
+
 crate fn resolve_name(
     db: &impl ParserDatabase,
     scope: Entity,
@@ -28,31 +40,11 @@ crate fn resolve_name(
                 .next()
                 .or_else(|| {
                     // Implicit root scope:
-                    let bool_id = "bool".intern(db);
-                    let int_id = "int".intern(db);
-                    let uint_id = "uint".intern(db);
-                    let false_id = "false".intern(db);
-                    let true_id = "true".intern(db);
-                    let debug_id = "debug".intern(db);
-                    let string_id = "String".intern(db);
-
-                    if name == bool_id {
-                        Some(EntityData::LangItem(LangItem::Boolean).intern(db))
-                    } else if name == int_id {
-                        Some(EntityData::LangItem(LangItem::Int).intern(db))
-                    } else if name == uint_id {
-                        Some(EntityData::LangItem(LangItem::Uint).intern(db))
-                    } else if name == false_id {
-                        Some(EntityData::LangItem(LangItem::False).intern(db))
-                    } else if name == true_id {
-                        Some(EntityData::LangItem(LangItem::True).intern(db))
-                    } else if name == string_id {
-                        Some(EntityData::LangItem(LangItem::String).intern(db))
-                    } else if name == debug_id {
-                        Some(EntityData::LangItem(LangItem::Debug).intern(db))
-                    } else {
-                        None
-                    }
+                    LANG_ITEM_NAMES
+                        .iter()
+                        .filter(|(text, _)| name == text.intern(db))
+                        .map(|&(_, lang_item)| EntityData::LangItem(lang_item).intern(db))
+                        .next()
                 })
         }
 
@@ -40,6 +40,10 @@ impl Syntax<'parse> for Block<'me, 'parse> {
 
         let start_span = parser.peek_span();
         let statements = parser.expect(self.definition())?;
+        self.scope
+            .fn_body_tables
+            .blocks
+            .push(start_span.extended_until_end_of(parser.last_span()));
 
         if statements.is_empty() {
             // FIXME -- it'd be better if `Delimited` gave back a
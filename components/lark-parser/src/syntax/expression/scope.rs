@@ -3,6 +3,7 @@ use crate::ParserDatabase;
 use lark_collections::FxIndexMap;
 use lark_debug_with::DebugWith;
 use lark_entity::Entity;
+use lark_error::DiagnosticKind;
 use lark_hir as hir;
 use lark_intern::{Intern, Untern};
 use lark_span::FileName;
@@ -66,18 +67,23 @@ impl ExpressionScope<'parse> {
         span: Span<FileName>,
         data: hir::ErrorData,
     ) -> hir::Expression {
-        let message = match data {
-            hir::ErrorData::Misc => "error".to_string(),
-            hir::ErrorData::Unimplemented => "unimplemented".to_string(),
-            hir::ErrorData::CanOnlyConstructStructs => {
-                "can only supply named arguments when constructing structs".to_string()
-            }
+        let (message, kind) = match data {
+            hir::ErrorData::Misc => ("error".to_string(), DiagnosticKind::Other),
+            hir::ErrorData::Unimplemented => ("unimplemented".to_string(), DiagnosticKind::Other),
+            hir::ErrorData::CanOnlyConstructStructs => (
+                "can only supply named arguments when constructing structs".to_string(),
+                DiagnosticKind::Other,
+            ),
             hir::ErrorData::UnknownIdentifier { text } => {
-                format!("unknown identifier `{}`", text.untern(&self.db))
+                let name = text.untern(&self.db).to_string();
+                (
+                    format!("unknown identifier `{}`", name),
+                    DiagnosticKind::UnknownIdentifier { name },
+                )
             }
         };
 
-        parser.report_error(message, span);
+        parser.report_error_of_kind(message, kind, span);
 
         self.already_reported_error_expression(span, data)
     }
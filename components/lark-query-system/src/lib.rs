@@ -1,19 +1,30 @@
 use language_reporting as l_r;
-use lark_actor::{Actor, LspResponse, QueryRequest};
+use languageserver_types::FileChangeType;
+use languageserver_types::{
+    Location, ParameterInformation, ParameterLabel, Range, SignatureHelp, SignatureInformation,
+    SymbolInformation,
+};
+use lark_actor::{Actor, LspResponse, QueryRequest, TaskId};
 use lark_entity::EntityTables;
 use lark_intern::{Intern, Untern};
 use lark_parser::{ParserDatabase, ParserDatabaseExt};
 use lark_pretty_print::PrettyPrintDatabase;
-use lark_span::{ByteIndex, FileName, Span};
+use lark_span::{ByteIndex, FileName, IntoFileName, Span};
 use lark_string::{GlobalIdentifier, GlobalIdentifierTables, Text};
 use salsa::{Database, ParallelDatabase, Snapshot};
-use std::collections::VecDeque;
+use std::any::Any;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use url::Url;
 
 pub mod ls_ops;
-use self::ls_ops::{Cancelled, LsDatabase};
+pub mod workspace;
+use self::ls_ops::{Cancelable, Cancelled, LsDatabase};
 
 #[salsa::database(lark_parser::ParserStorage, lark_type_check::TypeCheckStorage)]
 pub struct LarkDatabase {
@@ -23,6 +34,10 @@ pub struct LarkDatabase {
     declaration_tables: Arc<lark_ty::declaration::DeclarationTables>,
     base_inferred_tables: Arc<lark_ty::base_inferred::BaseInferredTables>,
     full_inferred_tables: Arc<lark_ty::full_inferred::FullInferredTables>,
+
+    /// Set when the IDE cancels the request this database (a snapshot)
+    /// was created to answer.
+    request_cancelled: Option<Arc<AtomicBool>>,
 }
 
 impl std::fmt::Debug for LarkDatabase {
@@ -52,6 +67,7 @@ impl Default for LarkDatabase {
             declaration_tables: Default::default(),
             base_inferred_tables: Default::default(),
             full_inferred_tables: Default::default(),
+            request_cancelled: None,
         };
         db.init_parser_db();
         db
@@ -66,6 +82,21 @@ impl Database for LarkDatabase {
 
 impl ParallelDatabase for LarkDatabase {
     fn snapshot(&self) -> Snapshot<Self> {
+        self.snapshot_with_cancellation(self.request_cancelled.clone())
+    }
+}
+
+impl LarkDatabase {
+    /// A snapshot for answering a single request; once `cancelled` is
+    /// set, `check_for_cancellation` reports that it was cancelled.
+    pub fn snapshot_for_request(&self, cancelled: Arc<AtomicBool>) -> Snapshot<Self> {
+        self.snapshot_with_cancellation(Some(cancelled))
+    }
+
+    fn snapshot_with_cancellation(
+        &self,
+        request_cancelled: Option<Arc<AtomicBool>>,
+    ) -> Snapshot<Self> {
         Snapshot::new(LarkDatabase {
             runtime: self.runtime.snapshot(self),
             item_id_tables: self.item_id_tables.clone(),
@@ -73,13 +104,21 @@ impl ParallelDatabase for LarkDatabase {
             declaration_tables: self.declaration_tables.clone(),
             base_inferred_tables: self.base_inferred_tables.clone(),
             full_inferred_tables: self.full_inferred_tables.clone(),
+            request_cancelled,
         })
     }
 }
 
 impl PrettyPrintDatabase for LarkDatabase {}
 
-impl LsDatabase for LarkDatabase {}
+impl LsDatabase for LarkDatabase {
+    fn is_request_cancelled(&self) -> bool {
+        match &self.request_cancelled {
+            Some(cancelled) => cancelled.load(Ordering::SeqCst),
+            None => false,
+        }
+    }
+}
 
 impl AsRef<EntityTables> for LarkDatabase {
     fn as_ref(&self) -> &EntityTables {
@@ -161,10 +200,40 @@ impl l_r::ReportingFiles for &LarkDatabase {
     }
 }
 
+/// Runs the `main` function of a file, passing each line it prints to
+/// the callback. The evaluator is built on top of the query system, so
+/// whoever creates the `QuerySystem` supplies it.
+pub type Runner = Arc<dyn Fn(&LarkDatabase, FileName, Box<dyn FnMut(String) + Send>) + Send + Sync>;
+
+/// How long edits have to stop coming in before we check for errors,
+/// so that we don't check after every keystroke.
+const ERROR_CHECK_DELAY: Duration = Duration::from_millis(150);
+
 pub struct QuerySystem {
     send_channel: Sender<LspResponse>,
     lark_db: LarkDatabase,
     needs_error_check: bool,
+
+    /// When we last changed the program.
+    last_mutation: Instant,
+
+    /// The diagnostics we last published for each file, so that we
+    /// only publish those that change.
+    published_diagnostics: Arc<Mutex<HashMap<Url, Vec<(Range, String)>>>>,
+
+    /// How to run programs for the "Run" code lens, if we can.
+    runner: Option<Runner>,
+
+    /// The files the IDE has open. Their contents come from the IDE,
+    /// not from disk.
+    open_files: HashSet<Url>,
+
+    /// The workspace folders we loaded files from.
+    workspace_roots: Vec<PathBuf>,
+
+    /// The requests we have started answering, each with the flag
+    /// that cancels it.
+    in_flight: HashMap<TaskId, Arc<AtomicBool>>,
 }
 
 impl QuerySystem {
@@ -173,6 +242,19 @@ impl QuerySystem {
             send_channel,
             lark_db: LarkDatabase::default(),
             needs_error_check: false,
+            last_mutation: Instant::now(),
+            published_diagnostics: Default::default(),
+            runner: None,
+            open_files: HashSet::new(),
+            workspace_roots: vec![],
+            in_flight: HashMap::new(),
+        }
+    }
+
+    pub fn with_runner(self, runner: Runner) -> QuerySystem {
+        QuerySystem {
+            runner: Some(runner),
+            ..self
         }
     }
 }
@@ -183,6 +265,20 @@ impl Actor for QuerySystem {
     fn receive_messages(&mut self, messages: &mut VecDeque<Self::InMessage>) {
         log::info!("receive_messages({} messages pending)", messages.len());
 
+        // Cancellations jump the queue: a request that hasn't started yet
+        // is dropped, and one that is running is told to stop.
+        let (cancellations, rest): (VecDeque<_>, VecDeque<_>) =
+            messages.drain(..).partition(|message| match message {
+                QueryRequest::Cancel(_) => true,
+                _ => false,
+            });
+        *messages = rest;
+        for cancellation in cancellations {
+            if let QueryRequest::Cancel(task_id) = cancellation {
+                self.cancel(task_id, messages);
+            }
+        }
+
         // Find the last mutation in our list. Up until that point, we need to process *only*
         // mutations.
         if let Some(last_mutation) = messages.iter().rposition(|message| message.is_mutation()) {
@@ -194,6 +290,7 @@ impl Actor for QuerySystem {
 
             // After each mutation, we need to perform an error-check at some point.
             self.needs_error_check = true;
+            self.last_mutation = Instant::now();
         }
 
         // OK, all mutations are processed. Now we can process the next non-mutation (if any).
@@ -202,38 +299,106 @@ impl Actor for QuerySystem {
             self.process_message(message);
         }
 
-        // If there are no more pending messages, we can go ahead and
-        // start checking for errors.  Otherwise, return, and we'll be
-        // called again.
-        if messages.is_empty() && self.needs_error_check {
+        // If there are no more pending messages, and there have been
+        // none for a little while, we can go ahead and start checking
+        // for errors. Otherwise, return, and we'll be called again
+        // (see `wake_up_at`).
+        if messages.is_empty()
+            && self.needs_error_check
+            && self.last_mutation.elapsed() >= ERROR_CHECK_DELAY
+        {
             self.check_for_errors_and_report();
         }
     }
+
+    fn wake_up_at(&self) -> Option<Instant> {
+        if self.needs_error_check {
+            Some(self.last_mutation + ERROR_CHECK_DELAY)
+        } else {
+            None
+        }
+    }
 }
 
 impl QuerySystem {
+    fn cancel(&mut self, task_id: TaskId, messages: &mut VecDeque<QueryRequest>) {
+        if let Some(cancelled) = self.in_flight.remove(&task_id) {
+            cancelled.store(true, Ordering::SeqCst);
+        }
+
+        if let Some(index) = messages
+            .iter()
+            .position(|message| message.task_id() == Some(task_id))
+        {
+            messages.remove(index);
+            send(self.send_channel.clone(), LspResponse::Cancelled(task_id));
+        }
+    }
+
+    /// A snapshot of the database for answering `task_id`, which can
+    /// be cancelled until the answer is sent.
+    fn snapshot_for_request(&mut self, task_id: TaskId) -> Snapshot<LarkDatabase> {
+        // Forget requests that have finished: their snapshots, which
+        // held the other reference to the flag, are gone.
+        self.in_flight
+            .retain(|_, cancelled| Arc::strong_count(cancelled) > 1);
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.in_flight.insert(task_id, cancelled.clone());
+        self.lark_db.snapshot_for_request(cancelled)
+    }
+
+    /// Loads the file at `url` from disk, unless the IDE has it open.
+    /// Returns false if the file can't be read.
+    fn load_from_disk(&mut self, url: &Url) -> bool {
+        if self.open_files.contains(url) {
+            return true;
+        }
+
+        match workspace::read_lark_file(url) {
+            Some(contents) => {
+                self.lark_db.add_file(url.as_str(), contents);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes a file from the program, clearing its diagnostics, which
+    /// no longer get updated.
+    fn forget_file(&mut self, url: Url) {
+        self.lark_db.remove_file(url.as_str());
+
+        self.published_diagnostics.lock().unwrap().remove(&url);
+        send(
+            self.send_channel.clone(),
+            LspResponse::Diagnostics(url, vec![]),
+        );
+    }
+
+    /// Checks each file for errors, and publishes the diagnostics of
+    /// those whose errors are not the ones we last published.
     pub fn check_for_errors_and_report(&mut self) {
         self.needs_error_check = false;
         std::thread::spawn({
             let db = self.lark_db.snapshot();
             let send_channel = self.send_channel.clone();
+            let published_diagnostics = self.published_diagnostics.clone();
             move || {
-                match db.errors_for_project() {
-                    Ok(errors) => {
-                        // loop over hashmap and send messages
-                        for (key, value) in errors {
-                            let send_channel = send_channel.clone();
-                            let url = Url::parse(&key).unwrap();
-                            let ranges_with_default =
-                                value.iter().map(|x| (x.range, x.label.clone())).collect();
-                            send(
-                                send_channel,
-                                LspResponse::Diagnostics(url, ranges_with_default),
-                            );
-                        }
-                    }
-                    Err(Cancelled) => {
-                        // Ignore
+                for &file_name in &*db.file_names() {
+                    let diagnostics = match diagnostics_for_file(&db, file_name) {
+                        Ok(diagnostics) => diagnostics,
+                        Err(Cancelled) => return,
+                    };
+
+                    let url = Url::parse(&file_name.id.untern(&*db)).unwrap();
+                    let mut published_diagnostics = published_diagnostics.lock().unwrap();
+                    if published_diagnostics.get(&url) != Some(&diagnostics) {
+                        published_diagnostics.insert(url.clone(), diagnostics.clone());
+                        send(
+                            send_channel.clone(),
+                            LspResponse::Diagnostics(url, diagnostics),
+                        );
                     }
                 }
             }
@@ -245,9 +410,29 @@ impl QuerySystem {
         log::info!("process_message(message={:#?})", message);
 
         match message {
-            QueryRequest::Initialize(task_id) => {
+            QueryRequest::Cancel(task_id) => {
+                self.cancel(task_id, &mut VecDeque::new());
+            }
+
+            QueryRequest::Initialize(task_id, position_encoding) => {
+                self.lark_db.set_position_encoding(position_encoding);
+
+                let send_channel = self.send_channel.clone();
+                send(
+                    send_channel,
+                    LspResponse::Initialized(task_id, position_encoding),
+                );
+            }
+
+            QueryRequest::Shutdown(task_id) => {
+                // Nobody is waiting for the answers to requests still
+                // in flight any more.
+                for (_, cancelled) in self.in_flight.drain() {
+                    cancelled.store(true, Ordering::SeqCst);
+                }
+
                 let send_channel = self.send_channel.clone();
-                send(send_channel, LspResponse::Initialized(task_id));
+                send(send_channel, LspResponse::Nothing(task_id));
             }
 
             QueryRequest::OpenFile(url, contents) => {
@@ -256,6 +441,7 @@ impl QuerySystem {
                 // Process sets on the same thread -- this not only gives them priority,
                 // it ensures an overall ordering to edits.
                 self.lark_db.add_file(url.as_str(), text);
+                self.open_files.insert(url);
             }
 
             QueryRequest::EditFile(url, changes) => {
@@ -264,40 +450,126 @@ impl QuerySystem {
                 let path_id = self.lark_db.intern_string(url.as_str());
                 let file_name = FileName { id: path_id };
 
-                let text = self.lark_db.file_text(file_name);
-                let mut current_contents = text.to_string();
-
-                for change in changes {
-                    let start_position = change.0.start;
-                    let start_offset = self.lark_db.byte_index(
-                        file_name,
-                        start_position.line,
-                        start_position.character,
-                    );
-
-                    let end_position = change.0.end;
-                    let end_offset = self.lark_db.byte_index(
-                        file_name,
-                        end_position.line,
-                        end_position.character,
-                    );
-
-                    unsafe {
-                        let vec = current_contents.as_mut_vec();
-                        vec.drain(start_offset.to_usize()..end_offset.to_usize());
+                // Each change is relative to the text after the ones
+                // before it, so apply them one at a time.
+                for (range, new_text) in changes {
+                    let contents = match range {
+                        // No range: the new text replaces everything.
+                        None => new_text,
+
+                        Some(range) => {
+                            let start_offset = self.lark_db.byte_index(
+                                file_name,
+                                range.start.line,
+                                range.start.character,
+                            );
+                            let end_offset = self.lark_db.byte_index(
+                                file_name,
+                                range.end.line,
+                                range.end.character,
+                            );
+
+                            let mut contents = self.lark_db.file_text(file_name).to_string();
+                            contents.replace_range(
+                                start_offset.to_usize()..end_offset.to_usize(),
+                                &new_text,
+                            );
+                            contents
+                        }
+                    };
+
+                    self.lark_db
+                        .query_mut(lark_parser::FileTextQuery)
+                        .set(file_name, Text::from(contents));
+                }
+            }
+            QueryRequest::CloseFile(url) => {
+                self.open_files.remove(&url);
+
+                // A file of the workspace goes back to what is on disk;
+                // any other file is forgotten.
+                let in_workspace = url.to_file_path().ok().map_or(false, |path| {
+                    self.workspace_roots
+                        .iter()
+                        .any(|root| path.starts_with(root))
+                });
+                if !in_workspace || !self.load_from_disk(&url) {
+                    self.forget_file(url);
+                }
+            }
+            QueryRequest::LoadWorkspace(roots) => {
+                for root in roots {
+                    let root = match root.to_file_path() {
+                        Ok(root) => root,
+                        Err(()) => {
+                            log::warn!("workspace folder {} is not a local path", root);
+                            continue;
+                        }
+                    };
+
+                    for path in workspace::lark_files(&root) {
+                        if let Ok(url) = Url::from_file_path(&path) {
+                            self.load_from_disk(&url);
+                        }
+                    }
+
+                    self.workspace_roots.push(root);
+                }
+            }
+            QueryRequest::WatchedFilesChanged(changes) => {
+                for (url, change) in changes {
+                    // The IDE's contents of an open file are newer than
+                    // the ones on disk.
+                    if self.open_files.contains(&url) {
+                        continue;
                     }
 
-                    current_contents.insert_str(start_offset.to_usize(), &change.1);
+                    match change {
+                        FileChangeType::Created | FileChangeType::Changed => {
+                            self.load_from_disk(&url);
+                        }
+                        FileChangeType::Deleted => {
+                            self.forget_file(url);
+                        }
+                    }
                 }
+            }
+            QueryRequest::PullDiagnostics(task_id, url, previous_result_id) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
 
-                let text = Text::from(current_contents);
-                self.lark_db
-                    .query_mut(lark_parser::FileTextQuery)
-                    .set(file_name, text);
+                        let file_name = url.as_str().into_file_name(&*db);
+                        let diagnostics = if db.file_names().contains(&file_name) {
+                            diagnostics_for_file(&db, file_name)
+                        } else {
+                            Ok(vec![])
+                        };
+
+                        match diagnostics {
+                            Ok(diagnostics) => {
+                                send(
+                                    send_channel,
+                                    LspResponse::PulledDiagnostics(
+                                        task_id,
+                                        url,
+                                        diagnostics,
+                                        previous_result_id,
+                                    ),
+                                );
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
             }
             QueryRequest::RenameAtPosition(task_id, url, position, new_name) => {
                 std::thread::spawn({
-                    let db = self.lark_db.snapshot();
+                    let db = self.snapshot_for_request(task_id);
                     let send_channel = self.send_channel.clone();
                     move || {
                         let _killme = KillTheProcess;
@@ -307,15 +579,45 @@ impl QuerySystem {
                             position,
                             &new_name,
                         ) {
-                            Ok(v) => {
+                            Ok(Ok(v)) => {
                                 let result = v
                                     .iter()
                                     .map(|(x, y, z)| (Url::parse(x).unwrap(), *y, z.clone()))
                                     .collect();
                                 send(send_channel, LspResponse::WorkspaceEdits(task_id, result));
                             }
-                            _ => {
-                                send(send_channel, LspResponse::Nothing(task_id));
+                            Ok(Err(error)) => {
+                                send(
+                                    send_channel,
+                                    LspResponse::Failed(task_id, error.to_string()),
+                                );
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::PrepareRename(task_id, url, position) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.prepare_rename(url.as_str(), position) {
+                            Ok(Ok((range, name))) => {
+                                send(send_channel, LspResponse::RenameRange(task_id, range, name));
+                            }
+                            Ok(Err(error)) => {
+                                send(
+                                    send_channel,
+                                    LspResponse::Failed(task_id, error.to_string()),
+                                );
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
                             }
                         }
                     }
@@ -323,7 +625,7 @@ impl QuerySystem {
             }
             QueryRequest::ReferencesAtPosition(task_id, url, position, _include_declaration) => {
                 std::thread::spawn({
-                    let db = self.lark_db.snapshot();
+                    let db = self.snapshot_for_request(task_id);
                     let send_channel = self.send_channel.clone();
                     move || {
                         let _killme = KillTheProcess;
@@ -336,16 +638,362 @@ impl QuerySystem {
                                     .collect();
                                 send(send_channel, LspResponse::Ranges(task_id, result));
                             }
-                            _ => {
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::CompletionsAtPosition(task_id, url, position) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.completions_at_position(url.as_str(), position) {
+                            Ok(completions) => {
+                                send(send_channel, LspResponse::Completions(task_id, completions));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::SignatureHelpAtPosition(task_id, url, position) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.signature_help_at_position(url.as_str(), position) {
+                            Ok(Some((label, parameters, active_parameter))) => {
+                                let parameters = parameters
+                                    .into_iter()
+                                    .map(|parameter| ParameterInformation {
+                                        label: ParameterLabel::Simple(parameter),
+                                        documentation: None,
+                                    })
+                                    .collect();
+                                let result = SignatureHelp {
+                                    signatures: vec![SignatureInformation {
+                                        label,
+                                        documentation: None,
+                                        parameters: Some(parameters),
+                                    }],
+                                    active_signature: Some(0),
+                                    active_parameter: Some(active_parameter as u64),
+                                };
+                                send(send_channel, LspResponse::SignatureHelp(task_id, result));
+                            }
+                            Ok(None) => {
                                 send(send_channel, LspResponse::Nothing(task_id));
                             }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::CodeActions(task_id, url, range) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.quick_fixes(url.as_str(), range) {
+                            Ok(fixes) => {
+                                send(send_channel, LspResponse::QuickFixes(task_id, url, fixes));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::InlayHints(task_id, url, range) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.inlay_hints(url.as_str(), range) {
+                            Ok(hints) => {
+                                send(send_channel, LspResponse::InlayHints(task_id, hints));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::Format(task_id, url, range) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.formatting_edits(url.as_str(), range) {
+                            Ok(Some(edits)) => {
+                                send(send_channel, LspResponse::TextEdits(task_id, edits));
+                            }
+                            Ok(None) => {
+                                send(send_channel, LspResponse::Nothing(task_id));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::SemanticTokens(task_id, url, previous_result_id) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.semantic_tokens(url.as_str()) {
+                            Ok(tokens) => {
+                                send(
+                                    send_channel,
+                                    LspResponse::SemanticTokens(
+                                        task_id,
+                                        url,
+                                        tokens,
+                                        previous_result_id,
+                                    ),
+                                );
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::DocumentSymbols(task_id, url) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.document_symbols(url.as_str()) {
+                            Ok(symbols) => {
+                                send(send_channel, LspResponse::DocumentSymbols(task_id, symbols));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::PrepareCallHierarchy(task_id, url, position) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.call_hierarchy_items(url.as_str(), position) {
+                            Ok(result) => {
+                                send(
+                                    send_channel,
+                                    LspResponse::CallHierarchyItems(task_id, result),
+                                );
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::IncomingCalls(task_id, url, position) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.incoming_calls_at_position(url.as_str(), position) {
+                            Ok(result) => {
+                                send(send_channel, LspResponse::IncomingCalls(task_id, result));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::OutgoingCalls(task_id, url, position) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.outgoing_calls_at_position(url.as_str(), position) {
+                            Ok(result) => {
+                                send(send_channel, LspResponse::OutgoingCalls(task_id, result));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::CodeLenses(task_id, url) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.code_lenses(url.as_str()) {
+                            Ok(lenses) => {
+                                send(send_channel, LspResponse::CodeLenses(task_id, url, lenses));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::RunMain(task_id, url) => match self.runner.clone() {
+                Some(runner) => {
+                    std::thread::spawn({
+                        let db = self.snapshot_for_request(task_id);
+                        let send_channel = self.send_channel.clone();
+                        move || {
+                            let _killme = KillTheProcess;
+
+                            let file_name = url.as_str().into_file_name(&*db);
+                            let output_channel = send_channel.clone();
+
+                            // A bug in the evaluator, or something it
+                            // doesn't support yet, shouldn't take the
+                            // language server down with the program.
+                            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                                runner(
+                                    &*db,
+                                    file_name,
+                                    Box::new(move |line| {
+                                        send(output_channel.clone(), LspResponse::RunOutput(line))
+                                    }),
+                                )
+                            }));
+
+                            match result {
+                                Ok(()) => send(send_channel, LspResponse::Nothing(task_id)),
+                                Err(payload) => {
+                                    let response = if payload.is::<Cancelled>() {
+                                        LspResponse::Cancelled(task_id)
+                                    } else {
+                                        LspResponse::Failed(
+                                            task_id,
+                                            format!(
+                                                "the program failed: {}",
+                                                panic_message(&*payload)
+                                            ),
+                                        )
+                                    };
+                                    send(send_channel, response);
+                                }
+                            }
+                        }
+                    });
+                }
+                None => send(self.send_channel.clone(), LspResponse::Nothing(task_id)),
+            },
+            QueryRequest::FoldingRanges(task_id, url) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.folding_ranges(url.as_str()) {
+                            Ok(folds) => {
+                                send(send_channel, LspResponse::FoldingRanges(task_id, folds));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::SelectionRanges(task_id, url, positions) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.selection_ranges(url.as_str(), &positions) {
+                            Ok(ranges) => {
+                                send(send_channel, LspResponse::SelectionRanges(task_id, ranges));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::WorkspaceSymbols(task_id, query) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.workspace_symbols(&query) {
+                            Ok(symbols) => {
+                                let result = symbols
+                                    .into_iter()
+                                    .map(|(name, kind, file, range, container_name)| {
+                                        SymbolInformation {
+                                            name,
+                                            kind,
+                                            deprecated: None,
+                                            location: Location {
+                                                uri: Url::parse(&file).unwrap(),
+                                                range,
+                                            },
+                                            container_name,
+                                        }
+                                    })
+                                    .collect();
+                                send(send_channel, LspResponse::WorkspaceSymbols(task_id, result));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
                         }
                     }
                 });
             }
             QueryRequest::DefinitionAtPosition(task_id, url, position) => {
                 std::thread::spawn({
-                    let db = self.lark_db.snapshot();
+                    let db = self.snapshot_for_request(task_id);
                     let send_channel = self.send_channel.clone();
                     move || {
                         let _killme = KillTheProcess;
@@ -357,35 +1005,78 @@ impl QuerySystem {
                                     LspResponse::Range(task_id, Url::parse(&v.0).unwrap(), v.1),
                                 );
                             }
-                            _ => {
+                            Ok(None) => {
                                 send(send_channel, LspResponse::Nothing(task_id));
                             }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
                         }
                     }
                 });
             }
-            QueryRequest::TypeAtPosition(task_id, url, position) => {
+            QueryRequest::TypeDefinitionAtPosition(task_id, url, position) => {
                 std::thread::spawn({
-                    let db = self.lark_db.snapshot();
+                    let db = self.snapshot_for_request(task_id);
                     let send_channel = self.send_channel.clone();
                     move || {
                         let _killme = KillTheProcess;
 
-                        match db.hover_text_at_position(url.as_str(), position) {
+                        match db.type_definition_at_position(url.as_str(), position) {
                             Ok(Some(v)) => {
-                                send(send_channel, LspResponse::Type(task_id, v.to_string()));
+                                send(
+                                    send_channel,
+                                    LspResponse::Range(task_id, Url::parse(&v.0).unwrap(), v.1),
+                                );
                             }
                             Ok(None) => {
-                                // FIXME what to send here to indicate "no hover"?
-                                send(send_channel, LspResponse::Type(task_id, "".to_string()));
+                                send(send_channel, LspResponse::Nothing(task_id));
                             }
                             Err(Cancelled) => {
-                                // Not sure what to send here, if anything.
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::DocumentHighlights(task_id, url, position) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.document_highlights_at_position(url.as_str(), position) {
+                            Ok(highlights) => {
                                 send(
                                     send_channel,
-                                    LspResponse::Type(task_id, format!("<cancelled>")),
+                                    LspResponse::DocumentHighlights(task_id, highlights),
                                 );
                             }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
+                        }
+                    }
+                });
+            }
+            QueryRequest::TypeAtPosition(task_id, url, position) => {
+                std::thread::spawn({
+                    let db = self.snapshot_for_request(task_id);
+                    let send_channel = self.send_channel.clone();
+                    move || {
+                        let _killme = KillTheProcess;
+
+                        match db.hover_text_at_position(url.as_str(), position) {
+                            Ok(Some((range, markdown))) => {
+                                send(send_channel, LspResponse::Hover(task_id, range, markdown));
+                            }
+                            Ok(None) => {
+                                send(send_channel, LspResponse::Nothing(task_id));
+                            }
+                            Err(Cancelled) => {
+                                send(send_channel, LspResponse::Cancelled(task_id));
+                            }
                         }
                     }
                 });
@@ -396,6 +1087,18 @@ impl QuerySystem {
     }
 }
 
+/// The diagnostics for a file, as we send them to the IDE.
+fn diagnostics_for_file(
+    db: &LarkDatabase,
+    file_name: FileName,
+) -> Cancelable<Vec<(Range, String)>> {
+    Ok(db
+        .ranged_errors_for_file(file_name)?
+        .into_iter()
+        .map(|diagnostic| (diagnostic.range, diagnostic.label))
+        .collect())
+}
+
 fn send(channel: Sender<LspResponse>, message: LspResponse) {
     match channel.send(message) {
         Ok(..) => {}
@@ -417,3 +1120,14 @@ impl Drop for KillTheProcess {
         }
     }
 }
+
+/// The message a panic was started with, if it was given one.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown error"
+    }
+}
@@ -4,24 +4,78 @@
 //! (e.g. `&uri`) that wouldn't be possible otherwise, which is
 //! convenient.
 
-use languageserver_types::{Position, Range};
-use lark_entity::{Entity, EntityData, ItemKind, MemberKind};
-use lark_error::Diagnostic;
+use languageserver_types::{
+    CompletionItemKind, DocumentHighlight, DocumentHighlightKind, DocumentSymbol, FoldingRange,
+    FoldingRangeKind, Position, Range, SymbolKind,
+};
+use lark_actor::{
+    CallHierarchyItem, CodeLens, CodeLensKind, InlayHint, QuickFix, SemanticToken,
+    SemanticTokenKind,
+};
+use lark_entity::{Entity, EntityData, ItemKind, LangItem, MemberKind};
+use lark_error::{Diagnostic, DiagnosticKind};
 use lark_intern::{Intern, Untern};
-use lark_parser::HoverTargetKind;
+use lark_parser::{HoverTargetKind, LexToken, LANG_ITEM_NAMES};
 use lark_pretty_print::PrettyPrint;
 use lark_span::{ByteIndex, FileName, IntoFileName, Span};
+use lark_ty::PermKind;
 use std::collections::HashMap;
 
 #[derive(Debug)]
 pub struct RangedDiagnostic {
     pub label: String,
     pub range: Range,
+    pub kind: DiagnosticKind,
 }
 
 impl RangedDiagnostic {
     pub fn new(label: String, range: Range) -> RangedDiagnostic {
-        RangedDiagnostic { label, range }
+        RangedDiagnostic {
+            label,
+            range,
+            kind: DiagnosticKind::Other,
+        }
+    }
+
+    pub fn with_kind(self, kind: DiagnosticKind) -> RangedDiagnostic {
+        RangedDiagnostic { kind, ..self }
+    }
+}
+
+/// Something the user can rename.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RenameTarget {
+    /// An item, field or method.
+    Entity(Entity),
+
+    /// A variable (or parameter) of the given function.
+    Variable(Entity, lark_hir::Variable),
+}
+
+/// Why a rename can't be done; shown to the user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RenameError {
+    /// There is no name at the position, or nothing we can rename.
+    NothingToRename,
+
+    /// The name is part of the language, like `debug` or `let`.
+    BuiltIn(String),
+
+    /// The new name is not an identifier, or is a keyword.
+    InvalidName(String),
+
+    /// The new name would clash with another, for the given reason.
+    Conflict(String),
+}
+
+impl std::fmt::Display for RenameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenameError::NothingToRename => write!(f, "there is nothing to rename here"),
+            RenameError::BuiltIn(name) => write!(f, "`{}` is built in and can't be renamed", name),
+            RenameError::InvalidName(name) => write!(f, "`{}` is not a valid name", name),
+            RenameError::Conflict(reason) => write!(f, "can't rename: {}", reason),
+        }
     }
 }
 
@@ -30,8 +84,13 @@ pub struct Cancelled;
 pub type Cancelable<T> = Result<T, Cancelled>;
 
 pub trait LsDatabase: lark_type_check::TypeCheckDatabase + salsa::Database {
+    /// True if the IDE no longer wants the answer we are computing.
+    fn is_request_cancelled(&self) -> bool {
+        false
+    }
+
     fn check_for_cancellation(&self) -> Cancelable<()> {
-        if self.salsa_runtime().is_current_revision_canceled() {
+        if self.salsa_runtime().is_current_revision_canceled() || self.is_request_cancelled() {
             Err(Cancelled)
         } else {
             Ok(())
@@ -43,95 +102,35 @@ pub trait LsDatabase: lark_type_check::TypeCheckDatabase + salsa::Database {
         let mut file_errors = HashMap::new();
 
         for &input_file in &*input_files {
-            self.check_for_cancellation()?;
-
-            // Check file for syntax errors
-            let mut errors = vec![];
-            let _ = self
-                .parsed_file(input_file)
-                .accumulate_errors_into(&mut errors);
-
-            // Next, check entities in file for type-safety
-            let file_entity = EntityData::InputFile { file: input_file }.intern(self);
-            for &entity in self.descendant_entities(file_entity).iter() {
-                self.accumulate_errors_for_entity(entity, &mut errors)?;
-            }
-
-            let error_ranges = errors
-                .iter()
-                .map(|x| RangedDiagnostic::new(x.label.clone(), self.range(x.span)))
-                .collect();
-
+            let error_ranges = self.ranged_errors_for_file(input_file)?;
             file_errors.insert(input_file.id.untern(self).to_string(), error_ranges);
         }
 
         Ok(file_errors)
     }
 
+    fn errors_for_file(&self, input_file: FileName) -> Cancelable<Vec<Diagnostic>> {
+        self.check_for_cancellation()?;
+
+        Ok(self.file_diagnostics(input_file).to_vec())
+    }
+
+    /// The errors in `input_file`, with their ranges; these are what we
+    /// publish for the file.
+    fn ranged_errors_for_file(&self, input_file: FileName) -> Cancelable<Vec<RangedDiagnostic>> {
+        Ok(self
+            .errors_for_file(input_file)?
+            .into_iter()
+            .map(|x| RangedDiagnostic::new(x.label, self.range(x.span)).with_kind(x.kind))
+            .collect())
+    }
+
     fn range(&self, span: Span<FileName>) -> languageserver_types::Range {
         let left = self.location(span.file(), span.start()).as_position();
         let right = self.location(span.file(), span.end()).as_position();
         languageserver_types::Range::new(left, right)
     }
 
-    fn accumulate_errors_for_entity(
-        &self,
-        entity: Entity,
-        errors: &mut Vec<Diagnostic>,
-    ) -> Cancelable<()> {
-        self.check_for_cancellation()?;
-
-        match entity.untern(self) {
-            EntityData::InputFile { .. } => {}
-            EntityData::LangItem(_) => {}
-            EntityData::Error(_) => {}
-            EntityData::ItemName {
-                kind: ItemKind::Struct,
-                ..
-            } => {
-                let _ = self
-                    .generic_declarations(entity)
-                    .accumulate_errors_into(errors);
-                let _ = self.ty(entity).accumulate_errors_into(errors);
-            }
-            EntityData::MemberName {
-                kind: MemberKind::Field,
-                ..
-            } => {
-                let _ = self
-                    .generic_declarations(entity)
-                    .accumulate_errors_into(errors);
-                let _ = self.ty(entity).accumulate_errors_into(errors);
-            }
-            EntityData::ItemName {
-                kind: ItemKind::Function,
-                ..
-            } => {
-                let _ = self
-                    .generic_declarations(entity)
-                    .accumulate_errors_into(errors);
-                let _ = self.ty(entity).accumulate_errors_into(errors);
-                let _ = self.signature(entity).accumulate_errors_into(errors);
-                let _ = self.fn_body(entity).accumulate_errors_into(errors);
-                let _ = self.full_type_check(entity).accumulate_errors_into(errors);
-            }
-            EntityData::MemberName {
-                kind: MemberKind::Method,
-                ..
-            } => {
-                let _ = self
-                    .generic_declarations(entity)
-                    .accumulate_errors_into(errors);
-                let _ = self.ty(entity).accumulate_errors_into(errors);
-                let _ = self.signature(entity).accumulate_errors_into(errors);
-                let _ = self.fn_body(entity).accumulate_errors_into(errors);
-                let _ = self.full_type_check(entity).accumulate_errors_into(errors);
-            }
-        }
-
-        Ok(())
-    }
-
     fn find_all_references_to_definition(&self, definition_entity: Entity) -> Vec<(String, Range)> {
         let input_files = self.file_names();
         let mut uses = vec![];
@@ -233,20 +232,332 @@ pub trait LsDatabase: lark_type_check::TypeCheckDatabase + salsa::Database {
         uses
     }
 
+    /// The edits that rename whatever is at `position` to `new_name`,
+    /// or why that can't be done without changing what the program
+    /// means.
     fn rename_all_references_at_position(
         &self,
         url: &str,
         position: Position,
         new_name: &str,
-    ) -> Cancelable<Vec<(String, Range, String)>> {
+    ) -> Cancelable<Result<Vec<(String, Range, String)>, RenameError>> {
         self.check_for_cancellation()?;
 
+        let target = match self.rename_target_at_position(url, position)? {
+            Ok((_, target)) => target,
+            Err(error) => return Ok(Err(error)),
+        };
+        if let Err(error) = self.check_rename(target, new_name)? {
+            return Ok(Err(error));
+        }
+
         let references = self.find_all_references_at_position(url, position)?;
 
-        Ok(references
+        Ok(Ok(references
             .into_iter()
             .map(|(x, y)| (x, y, new_name.to_string()))
-            .collect())
+            .collect()))
+    }
+
+    /// The range of the name that a rename at `position` would change,
+    /// and the name itself.
+    fn prepare_rename(
+        &self,
+        url: &str,
+        position: Position,
+    ) -> Cancelable<Result<(Range, String), RenameError>> {
+        Ok(self
+            .rename_target_at_position(url, position)?
+            .map(|(span, target)| (self.range(span), self.rename_target_name(target))))
+    }
+
+    /// The identifier at `position` and what it names, if that is
+    /// something the user wrote (and so can rename).
+    fn rename_target_at_position(
+        &self,
+        url: &str,
+        position: Position,
+    ) -> Cancelable<Result<(Span<FileName>, RenameTarget), RenameError>> {
+        let file_name = url.into_file_name(self);
+        let byte_index = self.position_to_byte_index(url, position);
+        let text = self.file_text(file_name);
+        let tokens = self.file_tokens(file_name).into_value();
+        let token = tokens.iter().find(|token| {
+            token.value == LexToken::Identifier
+                && token.span.start() <= byte_index
+                && byte_index <= token.span.end()
+        });
+        let span = match token {
+            Some(token) => token.span,
+            None => return Ok(Err(RenameError::NothingToRename)),
+        };
+        let name = &text[span];
+        if KEYWORDS.contains(&name) {
+            return Ok(Err(RenameError::BuiltIn(name.to_string())));
+        }
+
+        self.check_for_cancellation()?;
+
+        for target in self.hover_targets(file_name, byte_index).iter().rev() {
+            let target = match target.kind {
+                HoverTargetKind::Entity(entity) => RenameTarget::Entity(entity),
+                HoverTargetKind::MetaIndex(fn_entity, lark_hir::MetaIndex::Variable(variable)) => {
+                    RenameTarget::Variable(fn_entity, variable)
+                }
+                HoverTargetKind::MetaIndex(fn_entity, lark_hir::MetaIndex::Place(place)) => {
+                    match self.fn_body(fn_entity).into_value().tables[place] {
+                        lark_hir::PlaceData::Entity(entity) => RenameTarget::Entity(entity),
+                        lark_hir::PlaceData::Variable(variable) => {
+                            RenameTarget::Variable(fn_entity, variable)
+                        }
+                        lark_hir::PlaceData::Field { name, .. } => {
+                            let results = self.full_type_check(fn_entity).into_value();
+                            match results.entities.get(&name.into()) {
+                                Some(&entity) => RenameTarget::Entity(entity),
+                                None => continue,
+                            }
+                        }
+                        lark_hir::PlaceData::Temporary(_) => continue,
+                    }
+                }
+                HoverTargetKind::MetaIndex(
+                    fn_entity,
+                    lark_hir::MetaIndex::Identifier(identifier),
+                ) => {
+                    let results = self.full_type_check(fn_entity).into_value();
+                    match results.entities.get(&identifier.into()) {
+                        Some(&entity) => RenameTarget::Entity(entity),
+                        None => continue,
+                    }
+                }
+                HoverTargetKind::MetaIndex(..) => continue,
+            };
+
+            if let RenameTarget::Entity(entity) = target {
+                match entity.untern(self) {
+                    EntityData::LangItem(_) => {
+                        return Ok(Err(RenameError::BuiltIn(name.to_string())));
+                    }
+                    EntityData::ItemName { .. } | EntityData::MemberName { .. } => {}
+                    EntityData::Error(_) | EntityData::InputFile { .. } => continue,
+                }
+            }
+
+            // Targets further out (e.g., the function we are in) are
+            // not what the user pointed at.
+            if self.rename_target_name(target) == name {
+                return Ok(Ok((span, target)));
+            }
+        }
+
+        Ok(Err(RenameError::NothingToRename))
+    }
+
+    fn rename_target_name(&self, target: RenameTarget) -> String {
+        match target {
+            RenameTarget::Entity(entity) => match entity.untern(self) {
+                EntityData::ItemName { id, .. } | EntityData::MemberName { id, .. } => {
+                    id.untern(self).to_string()
+                }
+                _ => String::new(),
+            },
+            RenameTarget::Variable(fn_entity, variable) => {
+                let fn_body = self.fn_body(fn_entity).into_value();
+                let name = fn_body.tables[variable].name;
+                fn_body.tables[name].text.untern(self).to_string()
+            }
+        }
+    }
+
+    /// Checks that `new_name` is a name, and that giving it to `target`
+    /// doesn't change what any name in the program refers to.
+    fn check_rename(
+        &self,
+        target: RenameTarget,
+        new_name: &str,
+    ) -> Cancelable<Result<(), RenameError>> {
+        if self.rename_target_name(target) == new_name {
+            return Ok(Ok(()));
+        }
+
+        if !is_identifier(new_name) || KEYWORDS.contains(&new_name) {
+            return Ok(Err(RenameError::InvalidName(new_name.to_string())));
+        }
+
+        if LANG_ITEM_NAMES.iter().any(|&(name, _)| name == new_name) {
+            return Ok(Err(RenameError::Conflict(format!(
+                "`{}` is a built-in name",
+                new_name
+            ))));
+        }
+
+        let conflict = match target {
+            RenameTarget::Entity(entity) => self.entity_rename_conflict(entity, new_name)?,
+            RenameTarget::Variable(fn_entity, variable) => {
+                self.variable_rename_conflict(fn_entity, variable, new_name)
+            }
+        };
+
+        Ok(match conflict {
+            Some(conflict) => Err(RenameError::Conflict(conflict)),
+            None => Ok(()),
+        })
+    }
+
+    fn entity_rename_conflict(&self, entity: Entity, new_name: &str) -> Cancelable<Option<String>> {
+        let new_id = new_name.intern(self);
+        let entity_data = entity.untern(self);
+        let item_kind = |kind: ItemKind| match kind {
+            ItemKind::Struct => "struct",
+            ItemKind::Function => "function",
+        };
+
+        // Another item in the same file, or member of the same struct.
+        if let Some(base) = entity_data.parent() {
+            for &sibling in self.child_entities(base).iter() {
+                match sibling.untern(self) {
+                    EntityData::ItemName { kind, id, .. } if id == new_id => {
+                        return Ok(Some(format!(
+                            "there is already a {} named `{}`",
+                            item_kind(kind),
+                            new_name
+                        )));
+                    }
+                    EntityData::MemberName { kind, id, .. } if id == new_id => {
+                        let kind = match kind {
+                            MemberKind::Field => "field",
+                            MemberKind::Method => "method",
+                        };
+                        return Ok(Some(format!(
+                            "`{}` already has a {} named `{}`",
+                            self.rename_target_name(RenameTarget::Entity(base)),
+                            kind,
+                            new_name
+                        )));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let EntityData::ItemName { base, .. } = entity_data {
+            // Every file's items end up in the one crate we build, so
+            // the new name must not be taken in another file either.
+            for &input_file in &*self.file_names() {
+                let file_entity = EntityData::InputFile { file: input_file }.intern(self);
+                if file_entity == base {
+                    continue;
+                }
+
+                if let Some(other) = self.resolve_name(file_entity, new_id) {
+                    if let EntityData::ItemName { kind, .. } = other.untern(self) {
+                        return Ok(Some(format!(
+                            "there is already a {} named `{}` in `{}`",
+                            item_kind(kind),
+                            new_name,
+                            input_file.id.untern(self)
+                        )));
+                    }
+                }
+            }
+
+            // Wherever the item is used, the new name must not already
+            // refer to something else, nor be hidden by a variable.
+            for &input_file in &*self.file_names() {
+                let file_entity = EntityData::InputFile { file: input_file }.intern(self);
+                for &fn_entity in self.descendant_entities(file_entity).iter() {
+                    self.check_for_cancellation()?;
+
+                    if !fn_entity.untern(self).has_fn_body() {
+                        continue;
+                    }
+
+                    let fn_body = self.fn_body(fn_entity).into_value();
+                    for (place, place_data) in fn_body.tables.places.iter_enumerated() {
+                        if *place_data != lark_hir::PlaceData::Entity(entity) {
+                            continue;
+                        }
+
+                        match self.resolve_name(fn_entity, new_id) {
+                            Some(other) if other != entity => {
+                                return Ok(Some(format!(
+                                    "`{}` already refers to something else in `{}`",
+                                    new_name,
+                                    self.rename_target_name(RenameTarget::Entity(fn_entity)),
+                                )));
+                            }
+                            _ => {}
+                        }
+
+                        let index = fn_body.span(place).start();
+                        let hidden = variables_in_scope(&fn_body, index)
+                            .into_iter()
+                            .any(|variable| variable_name(&fn_body, variable) == new_id);
+                        if hidden {
+                            return Ok(Some(format!(
+                                "it is used where the variable `{}` in `{}` would hide it",
+                                new_name,
+                                self.rename_target_name(RenameTarget::Entity(fn_entity)),
+                            )));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn variable_rename_conflict(
+        &self,
+        fn_entity: Entity,
+        variable: lark_hir::Variable,
+        new_name: &str,
+    ) -> Option<String> {
+        let new_id = new_name.intern(self);
+        let fn_body = self.fn_body(fn_entity).into_value();
+
+        // Where the variable is declared or used, another variable with
+        // the new name must not be visible.
+        let mut uses = vec![fn_body.span(variable).start()];
+        for (place, place_data) in fn_body.tables.places.iter_enumerated() {
+            if *place_data == lark_hir::PlaceData::Variable(variable) {
+                uses.push(fn_body.span(place).start());
+            }
+        }
+        for index in uses {
+            let clash = variables_in_scope(&fn_body, index)
+                .into_iter()
+                .any(|other| other != variable && variable_name(&fn_body, other) == new_id);
+            if clash {
+                return Some(format!("there is already a variable named `{}`", new_name));
+            }
+        }
+
+        // Anything else with the new name, used where the variable is
+        // visible, would refer to the variable instead.
+        for (place, place_data) in fn_body.tables.places.iter_enumerated() {
+            let referent_id = match *place_data {
+                lark_hir::PlaceData::Variable(other) if other != variable => {
+                    variable_name(&fn_body, other)
+                }
+                lark_hir::PlaceData::Entity(entity) => match entity.untern(self) {
+                    EntityData::ItemName { id, .. } => id,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            let index = fn_body.span(place).start();
+            if referent_id == new_id && variables_in_scope(&fn_body, index).contains(&variable) {
+                return Some(format!(
+                    "`{}` is used where the variable is visible, and would refer to it",
+                    new_name
+                ));
+            }
+        }
+
+        None
     }
 
     fn find_all_references_at_position(
@@ -321,6 +632,124 @@ pub trait LsDatabase: lark_type_check::TypeCheckDatabase + salsa::Database {
         }
     }
 
+    /// The references in the file `url` to whatever is at `position`,
+    /// for highlighting. Assignments are writes; everything else is a
+    /// read.
+    fn document_highlights_at_position(
+        &self,
+        url: &str,
+        position: Position,
+    ) -> Cancelable<Vec<DocumentHighlight>> {
+        let references = self.find_all_references_at_position(url, position)?;
+
+        // The places assigned to in this file. For a field, that's the
+        // name of the field.
+        let file_name = url.into_file_name(self);
+        let file_entity = EntityData::InputFile { file: file_name }.intern(self);
+        let mut writes = vec![];
+        for &entity in self.descendant_entities(file_entity).iter() {
+            self.check_for_cancellation()?;
+
+            if !entity.untern(self).has_fn_body() {
+                continue;
+            }
+
+            let fn_body = self.fn_body(entity).into_value();
+            for expression_data in fn_body.tables.expressions.iter() {
+                if let lark_hir::ExpressionData::Assignment { place, .. } = *expression_data {
+                    writes.push(self.range(fn_body.span(place)));
+                    if let lark_hir::PlaceData::Field { name, .. } = fn_body.tables[place] {
+                        writes.push(self.range(fn_body.span(name)));
+                    }
+                }
+            }
+        }
+
+        let mut highlights: Vec<_> = references
+            .into_iter()
+            .filter(|(file, _)| file == url)
+            .map(|(_, range)| DocumentHighlight {
+                range,
+                kind: Some(if writes.contains(&range) {
+                    DocumentHighlightKind::Write
+                } else {
+                    DocumentHighlightKind::Read
+                }),
+            })
+            .collect();
+        highlights.sort_by_key(|highlight| highlight.range.start);
+        highlights.dedup_by_key(|highlight| highlight.range);
+
+        Ok(highlights)
+    }
+
+    /// The `struct` that is the type of whatever is at `position`
+    /// (e.g., a variable or a field): its file and the range of its
+    /// name.
+    fn type_definition_at_position(
+        &self,
+        url: &str,
+        position: Position,
+    ) -> Cancelable<Option<(String, Range)>> {
+        let url_file_name = url.into_file_name(self);
+        let byte_index = self.position_to_byte_index(url, position);
+        let targets = self.hover_targets(url_file_name, byte_index);
+        self.check_for_cancellation()?;
+
+        Ok(targets
+            .iter()
+            .rev()
+            .filter_map(|target| {
+                let type_entity = match target.kind {
+                    HoverTargetKind::Entity(entity) => match entity.untern(self) {
+                        EntityData::MemberName {
+                            kind: MemberKind::Field,
+                            ..
+                        } => self.declared_type_entity(entity)?,
+                        _ => return None,
+                    },
+
+                    HoverTargetKind::MetaIndex(entity, mi) => {
+                        let results = self.full_type_check(entity).into_value();
+                        match results.opt_ty(mi) {
+                            Some(ty) => match ty.base.untern(self).kind {
+                                lark_ty::BaseKind::Named(type_entity) => type_entity,
+                                _ => return None,
+                            },
+                            // Field names don't have a type of their
+                            // own; use the declared type of the field.
+                            None => self.declared_type_entity(*results.entities.get(&mi)?)?,
+                        }
+                    }
+                };
+
+                match type_entity.untern(self) {
+                    EntityData::ItemName {
+                        kind: ItemKind::Struct,
+                        ..
+                    } => {
+                        let span = self.characteristic_entity_span(type_entity);
+                        let filename = span.file().id.untern(self).to_string();
+                        Some((filename, self.range(span)))
+                    }
+                    _ => None,
+                }
+            })
+            .next())
+    }
+
+    /// The entity named by the declared type of `entity` (e.g. the
+    /// struct that is the type of a field).
+    fn declared_type_entity(&self, entity: Entity) -> Option<Entity> {
+        match self.ty(entity).into_value().base.untern(self) {
+            lark_ty::BoundVarOr::Known(lark_ty::BaseData {
+                kind: lark_ty::BaseKind::Named(type_entity),
+                ..
+            }) => Some(type_entity),
+            _ => None,
+        }
+    }
+
     fn get_entity_span_if_possible(
         &self,
         entity: Entity,
@@ -440,64 +869,1663 @@ pub trait LsDatabase: lark_type_check::TypeCheckDatabase + salsa::Database {
             .next())
     }
 
-    /// Returns the hover text to display for a given position (if
-    /// any).
-    fn hover_text_at_position(&self, url: &str, position: Position) -> Cancelable<Option<String>> {
+    /// Returns the hover to display for a given position (if any): a
+    /// markdown description of the thing there, and its range.
+    fn hover_text_at_position(
+        &self,
+        url: &str,
+        position: Position,
+    ) -> Cancelable<Option<(Range, String)>> {
         let url_file_name = url.into_file_name(self);
         let byte_index = self.position_to_byte_index(url, position);
         let targets = self.hover_targets(url_file_name, byte_index);
-        self.check_for_cancellation()?;
 
-        Ok(targets
-            .iter()
-            .rev()
-            .filter_map(|target| match target.kind {
-                HoverTargetKind::Entity(entity) => match entity.untern(self) {
-                    EntityData::InputFile { .. }
-                    | EntityData::LangItem(_)
-                    | EntityData::Error(_) => None,
-                    EntityData::ItemName {
-                        kind: ItemKind::Struct,
-                        ..
-                    } => Some(format!("struct {}", entity.pretty_print(self))),
-                    EntityData::ItemName {
-                        kind: ItemKind::Function,
-                        ..
-                    } => Some(format!("def {}", entity.pretty_print(self))),
-                    _ => Some(entity.pretty_print(self)),
-                },
+        for target in targets.iter().rev() {
+            self.check_for_cancellation()?;
 
-                HoverTargetKind::MetaIndex(entity, mi) => {
-                    let fn_body_types = self.full_type_check(entity).into_value();
+            let markdown = match target.kind {
+                HoverTargetKind::Entity(entity) => self.entity_hover(entity),
+                HoverTargetKind::MetaIndex(entity, mi) => self.meta_index_hover(entity, mi),
+            };
+            if let Some(markdown) = markdown {
+                return Ok(Some((self.range(target.span), markdown)));
+            }
+        }
 
-                    match mi {
-                        lark_hir::MetaIndex::Identifier(identifier) => {
-                            if let Some(target_entity) =
-                                fn_body_types.entities.get(&identifier.into())
-                            {
-                                Some(format!(
-                                    "{}",
-                                    self.ty(*target_entity).value.pretty_print(self),
-                                ))
-                            } else {
-                                None
-                            }
-                        }
-                        _ => {
-                            if let Some(ty) = fn_body_types.opt_ty(mi) {
-                                Some(format!("{}", ty.pretty_print(self),))
-                            } else {
-                                None
-                            }
-                        }
+        Ok(None)
+    }
+
+    /// The declaration of `entity`, as it would be written: the whole
+    /// definition of a struct, the signature of a function.
+    fn entity_hover(&self, entity: Entity) -> Option<String> {
+        let declaration = match entity.untern(self) {
+            EntityData::ItemName {
+                kind: ItemKind::Struct,
+                id,
+                ..
+            } => {
+                let mut declaration = format!("struct {} {{\n", id.untern(self));
+                for member in self.members(entity).ok()?.iter() {
+                    if member.kind == MemberKind::Field {
+                        declaration.push_str(&format!(
+                            "    {}: {},\n",
+                            member.name.untern(self),
+                            member.entity.pretty_print(self)
+                        ));
                     }
                 }
-            })
-            .next())
-    }
+                declaration.push('}');
+                declaration
+            }
 
-    fn position_to_byte_index(&self, url: &str, position: Position) -> ByteIndex {
-        let url_id = url.intern(self);
-        self.byte_index(FileName { id: url_id }, position.line, position.character)
-    }
+            EntityData::ItemName {
+                kind: ItemKind::Function,
+                id,
+                ..
+            }
+            | EntityData::MemberName {
+                kind: MemberKind::Method,
+                id,
+                ..
+            } => {
+                let signature = self.signature(entity).into_value().ok()?;
+                let fn_body = self.fn_body(entity).into_value();
+                let arguments: Vec<_> = match &fn_body.arguments {
+                    Ok(arguments) => arguments.iter(&fn_body).collect(),
+                    Err(_) => return None,
+                };
+                let parameters: Vec<_> = arguments
+                    .iter()
+                    .zip(signature.inputs.iter())
+                    .map(|(&variable, ty)| {
+                        format!(
+                            "{}: {}",
+                            variable_name(&fn_body, variable).untern(self),
+                            ty.pretty_print(self)
+                        )
+                    })
+                    .collect();
+                format!(
+                    "def {}({}) -> {}",
+                    id.untern(self),
+                    parameters.join(", "),
+                    signature.output.pretty_print(self)
+                )
+            }
+
+            EntityData::MemberName {
+                kind: MemberKind::Field,
+                id,
+                ..
+            } => format!("{}: {}", id.untern(self), entity.pretty_print(self)),
+
+            EntityData::InputFile { .. } | EntityData::LangItem(_) | EntityData::Error(_) => {
+                return None;
+            }
+        };
+
+        let mut markdown = code_block(&declaration);
+        if let EntityData::MemberName { base, .. } = entity.untern(self) {
+            markdown.push_str(&format!("\n\nmember of `{}`", base.pretty_print(self)));
+        }
+        Some(markdown)
+    }
+
+    /// Describes something in the body of `fn_entity`: its inferred
+    /// type and, for a variable or place, the permission we have to
+    /// it. Names of items, fields and methods get their declaration
+    /// instead.
+    fn meta_index_hover(&self, fn_entity: Entity, mi: lark_hir::MetaIndex) -> Option<String> {
+        let fn_body = self.fn_body(fn_entity).into_value();
+        let results = self.full_type_check(fn_entity).into_value();
+
+        let referent = match mi {
+            lark_hir::MetaIndex::Identifier(identifier) => {
+                results.entities.get(&identifier.into()).cloned()
+            }
+            lark_hir::MetaIndex::Place(place) => match fn_body.tables[place] {
+                lark_hir::PlaceData::Entity(entity) => Some(entity),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(markdown) = referent.and_then(|entity| self.entity_hover(entity)) {
+            return Some(markdown);
+        }
+
+        let ty = results.opt_ty(mi)?;
+        let ty_text = ty.pretty_print(self);
+        let name = match mi {
+            lark_hir::MetaIndex::Variable(variable) => Some(variable_name(&fn_body, variable)),
+            lark_hir::MetaIndex::Place(place) => match fn_body.tables[place] {
+                lark_hir::PlaceData::Variable(variable) => Some(variable_name(&fn_body, variable)),
+                lark_hir::PlaceData::Field { name, .. } => Some(fn_body.tables[name].text),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let mut markdown = match name {
+            Some(name) => code_block(&format!("{}: {}", name.untern(self), ty_text)),
+            None => code_block(&ty_text),
+        };
+        if let lark_hir::MetaIndex::Variable(_) | lark_hir::MetaIndex::Place(_) = mi {
+            let permission = match ty.perm {
+                PermKind::Own => "owned",
+                PermKind::Share => "shared",
+                PermKind::Borrow => "borrowed",
+            };
+            markdown.push_str(&format!("\n\npermission: {}", permission));
+        }
+        Some(markdown)
+    }
+
+    /// Returns the completions to offer at a given position. After a
+    /// `.`, these are the fields and methods of the receiver; anywhere
+    /// else, the variables in scope, the items in the project and the
+    /// built-in names. Each completion is a label, a detail (e.g. the
+    /// type) and the kind of thing it is.
+    fn completions_at_position(
+        &self,
+        url: &str,
+        position: Position,
+    ) -> Cancelable<Vec<(String, String, CompletionItemKind)>> {
+        let file_name = url.into_file_name(self);
+        let text = self.file_text(file_name);
+        let cursor = self
+            .position_to_byte_index(url, position)
+            .to_usize()
+            .min(text.len());
+
+        // The part of the identifier typed so far, if any.
+        let prefix_start = text[..cursor]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| c.is_alphanumeric() || c == '_')
+            .last()
+            .map(|(index, _)| index)
+            .unwrap_or(cursor);
+        let prefix = &text[prefix_start..cursor];
+        let before_prefix = text[..prefix_start].trim_end();
+
+        let fn_entity = self.fn_entity_at(file_name, ByteIndex::from(cursor));
+        self.check_for_cancellation()?;
+
+        let mut completions = vec![];
+
+        if before_prefix.ends_with('.') {
+            let receiver_text = before_prefix[..before_prefix.len() - 1].trim_end();
+            let receiver =
+                fn_entity.and_then(|entity| self.completion_receiver(entity, receiver_text));
+
+            if let Some(Ok(members)) = receiver.map(|receiver| self.members(receiver)) {
+                for member in members.iter() {
+                    let kind = match member.kind {
+                        MemberKind::Field => CompletionItemKind::Field,
+                        MemberKind::Method => CompletionItemKind::Method,
+                    };
+                    completions.push((
+                        member.name.untern(self).to_string(),
+                        self.entity_detail(member.entity),
+                        kind,
+                    ));
+                }
+            }
+        } else {
+            // Each name completes to the innermost thing it refers to:
+            // a variable hides the variables it shadows, and the items
+            // and built-in names it is named like.
+            let mut seen = std::collections::HashSet::new();
+
+            if let Some(entity) = fn_entity {
+                let fn_body = self.fn_body(entity).into_value();
+                let results = self.full_type_check(entity).into_value();
+
+                // Variables are in source order, so the last one of
+                // each name is the one in scope.
+                let mut variables = vec![];
+                for variable in variables_in_scope(&fn_body, ByteIndex::from(cursor))
+                    .into_iter()
+                    .rev()
+                {
+                    let name = fn_body.tables[fn_body.tables[variable].name].text;
+                    let name = name.untern(self).to_string();
+                    if !seen.insert(name.clone()) {
+                        continue;
+                    }
+                    let detail = match results.opt_ty(variable) {
+                        Some(ty) => ty.pretty_print(self),
+                        None => String::new(),
+                    };
+                    variables.push((name, detail, CompletionItemKind::Variable));
+                }
+                completions.extend(variables.into_iter().rev());
+            }
+
+            for &input_file in &*self.file_names() {
+                self.check_for_cancellation()?;
+
+                for &entity in self.top_level_entities_in_file(input_file).iter() {
+                    let (id, kind) = match entity.untern(self) {
+                        EntityData::ItemName {
+                            kind: ItemKind::Struct,
+                            id,
+                            ..
+                        } => (id, CompletionItemKind::Struct),
+                        EntityData::ItemName {
+                            kind: ItemKind::Function,
+                            id,
+                            ..
+                        } => (id, CompletionItemKind::Function),
+                        _ => continue,
+                    };
+                    let name = id.untern(self).to_string();
+                    if seen.insert(name.clone()) {
+                        completions.push((name, self.entity_detail(entity), kind));
+                    }
+                }
+            }
+
+            for &(name, lang_item) in LANG_ITEM_NAMES {
+                let (detail, kind) = match lang_item {
+                    LangItem::True | LangItem::False => ("bool", CompletionItemKind::Constant),
+                    LangItem::Debug => ("def debug(...)", CompletionItemKind::Function),
+                    _ => ("built-in type", CompletionItemKind::Struct),
+                };
+                if seen.insert(name.to_string()) {
+                    completions.push((name.to_string(), detail.to_string(), kind));
+                }
+            }
+        }
+
+        completions.retain(|(label, ..)| label.starts_with(prefix));
+
+        Ok(completions)
+    }
+
+    /// The innermost function or method whose body contains `index`.
+    fn fn_entity_at(&self, file: FileName, index: ByteIndex) -> Option<Entity> {
+        let file_entity = EntityData::InputFile { file }.intern(self);
+        self.descendant_entities(file_entity)
+            .iter()
+            .cloned()
+            .filter(|entity| entity.untern(self).has_fn_body())
+            .filter(|&entity| {
+                let span = self.entity_span(entity);
+                span.start() <= index && index <= span.end()
+            })
+            .last()
+    }
+
+    /// The struct whose members should be offered after
+    /// `receiver_text.`, inside the body of `entity`.
+    fn completion_receiver(&self, entity: Entity, receiver_text: &str) -> Option<Entity> {
+        let fn_body = self.fn_body(entity).into_value();
+        let results = self.full_type_check(entity).into_value();
+        let receiver_end = ByteIndex::from(receiver_text.len());
+
+        // Prefer the outermost expression that ends right before the
+        // `.`; if the incomplete member access kept the parser from
+        // producing one, fall back to a variable of that name.
+        let receiver_ty = fn_body
+            .tables
+            .expressions
+            .indices()
+            .filter_map(|expression| {
+                let span = fn_body.tables.spans.get(&expression.into())?;
+                if span.end() == receiver_end {
+                    Some((span.start(), expression))
+                } else {
+                    None
+                }
+            })
+            .min()
+            .and_then(|(_, expression)| results.opt_ty(expression))
+            .or_else(|| {
+                let name = receiver_text
+                    .rsplit(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .next()?;
+                variables_in_scope(&fn_body, receiver_end)
+                    .into_iter()
+                    .filter(|&variable| {
+                        fn_body.tables[fn_body.tables[variable].name]
+                            .text
+                            .untern(self)
+                            == name
+                    })
+                    .last()
+                    .and_then(|variable| results.opt_ty(variable))
+            })?;
+
+        match receiver_ty.base.untern(self).kind {
+            lark_ty::BaseKind::Named(entity) => match entity.untern(self) {
+                EntityData::ItemName {
+                    kind: ItemKind::Struct,
+                    ..
+                } => Some(entity),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// A short description of an entity (e.g. its signature), shown
+    /// next to completions and symbols.
+    fn entity_detail(&self, entity: Entity) -> String {
+        match entity.untern(self) {
+            EntityData::ItemName {
+                kind: ItemKind::Struct,
+                ..
+            } => format!("struct {}", entity.pretty_print(self)),
+            EntityData::ItemName {
+                kind: ItemKind::Function,
+                id,
+                ..
+            }
+            | EntityData::MemberName {
+                kind: MemberKind::Method,
+                id,
+                ..
+            } => {
+                // Pretty-printing needs a valid signature.
+                if self.signature(entity).value.is_ok() {
+                    format!("def {}", entity.pretty_print(self))
+                } else {
+                    format!("def {}", id.untern(self))
+                }
+            }
+            _ => entity.pretty_print(self),
+        }
+    }
+
+    /// Signature help for the call whose parentheses contain the cursor:
+    /// the label of the callee (e.g. `foo(x: uint) -> bool`), the labels
+    /// of its parameters (each a substring of the callee's label) and
+    /// the index of the parameter being typed. Struct constructors take
+    /// their fields as parameters.
+    fn signature_help_at_position(
+        &self,
+        url: &str,
+        position: Position,
+    ) -> Cancelable<Option<(String, Vec<String>, usize)>> {
+        let file_name = url.into_file_name(self);
+        let text = self.file_text(file_name);
+        let cursor = self
+            .position_to_byte_index(url, position)
+            .to_usize()
+            .min(text.len());
+
+        let (open_paren, argument_start, mut active_parameter) =
+            match enclosing_call(&text[..cursor]) {
+                Some(call) => call,
+                None => return Ok(None),
+            };
+
+        // The callee is the identifier right before the `(`, which may
+        // be a method called on some receiver.
+        let callee_text = text[..open_paren].trim_end();
+        let name_start = callee_text
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| c.is_alphanumeric() || c == '_')
+            .last()
+            .map(|(index, _)| index)
+            .unwrap_or(callee_text.len());
+        let name = &callee_text[name_start..];
+        if name.is_empty() {
+            return Ok(None);
+        }
+        let before_name = callee_text[..name_start].trim_end();
+        let name_id = name.intern(self);
+
+        self.check_for_cancellation()?;
+
+        let callee = if before_name.ends_with('.') {
+            let receiver_text = before_name[..before_name.len() - 1].trim_end();
+            self.fn_entity_at(file_name, ByteIndex::from(cursor))
+                .and_then(|entity| self.completion_receiver(entity, receiver_text))
+                .and_then(|receiver| self.member_entity(receiver, MemberKind::Method, name_id))
+        } else {
+            let file_entity = EntityData::InputFile { file: file_name }.intern(self);
+            self.resolve_name(file_entity, name_id)
+        };
+        let callee = match callee {
+            Some(callee) => callee,
+            None => return Ok(None),
+        };
+
+        let (parameters, output) = match callee.untern(self) {
+            EntityData::ItemName {
+                kind: ItemKind::Struct,
+                ..
+            } => {
+                let fields: Vec<_> = match self.members(callee) {
+                    Ok(members) => members
+                        .iter()
+                        .filter(|member| member.kind == MemberKind::Field)
+                        .cloned()
+                        .collect(),
+                    Err(_) => return Ok(None),
+                };
+
+                // Fields are given by name, so in any order: if the
+                // current argument names one, that is the active one.
+                let argument = text[argument_start..cursor].trim_start();
+                if let Some(colon) = argument.find(':') {
+                    let field_name = argument[..colon].trim_end();
+                    if let Some(index) = fields
+                        .iter()
+                        .position(|field| field.name.untern(self) == field_name)
+                    {
+                        active_parameter = index;
+                    }
+                }
+
+                let parameters = fields
+                    .iter()
+                    .map(|field| {
+                        format!(
+                            "{}: {}",
+                            field.name.untern(self),
+                            field.entity.pretty_print(self)
+                        )
+                    })
+                    .collect();
+                (parameters, None)
+            }
+
+            EntityData::ItemName {
+                kind: ItemKind::Function,
+                ..
+            }
+            | EntityData::MemberName {
+                kind: MemberKind::Method,
+                ..
+            } => {
+                let signature = match self.signature(callee).into_value() {
+                    Ok(signature) => signature,
+                    Err(_) => return Ok(None),
+                };
+                let fn_body = self.fn_body(callee).into_value();
+                let names: Vec<_> = match &fn_body.arguments {
+                    Ok(arguments) => arguments
+                        .iter(&fn_body)
+                        .map(|variable| fn_body.tables[fn_body.tables[variable].name].text)
+                        .collect(),
+                    Err(_) => return Ok(None),
+                };
+
+                // A method's `self` is the receiver, not one of the
+                // arguments in the parentheses.
+                let skip = if before_name.ends_with('.') { 1 } else { 0 };
+                let parameters = names
+                    .iter()
+                    .zip(signature.inputs.iter())
+                    .skip(skip)
+                    .map(|(name, ty)| format!("{}: {}", name.untern(self), ty.pretty_print(self)))
+                    .collect();
+                (parameters, Some(signature.output.pretty_print(self)))
+            }
+
+            _ => return Ok(None),
+        };
+
+        let mut label = format!("{}({})", name, parameters.join(", "));
+        if let Some(output) = output {
+            label.push_str(" -> ");
+            label.push_str(&output);
+        }
+
+        Ok(Some((label, parameters, active_parameter)))
+    }
+
+    /// The identifiers in a file that we know something about, for
+    /// semantic highlighting, in source order: what each one refers to
+    /// and whether it is the declaration of that thing.
+    fn semantic_tokens(&self, url: &str) -> Cancelable<Vec<SemanticToken>> {
+        let file_name = url.into_file_name(self);
+        let file_entity = EntityData::InputFile { file: file_name }.intern(self);
+        let text = self.file_text(file_name);
+
+        // What we learn from the entities and their bodies, keyed by the
+        // span of the identifier.
+        let mut classified: HashMap<Span<FileName>, (SemanticTokenKind, bool)> = HashMap::new();
+
+        for &entity in self.descendant_entities(file_entity).iter() {
+            self.check_for_cancellation()?;
+
+            if let Some(kind) = self.semantic_token_kind(entity) {
+                classified.insert(self.characteristic_entity_span(entity), (kind, true));
+            }
+
+            if !entity.untern(self).has_fn_body() {
+                continue;
+            }
+
+            let fn_body = self.fn_body(entity).into_value();
+            let results = self.full_type_check(entity).into_value();
+            let arguments: Vec<_> = match &fn_body.arguments {
+                Ok(arguments) => arguments.iter(&fn_body).collect(),
+                Err(_) => vec![],
+            };
+            let variable_kind = |variable| {
+                if arguments.contains(&variable) {
+                    SemanticTokenKind::Parameter
+                } else {
+                    SemanticTokenKind::Variable
+                }
+            };
+
+            for variable in fn_body.tables.variables.indices() {
+                // A method's `self` has the span of the method name,
+                // which is already classified as the method.
+                if let Some(&span) = fn_body.tables.spans.get(&variable.into()) {
+                    classified
+                        .entry(span)
+                        .or_insert((variable_kind(variable), true));
+                }
+            }
+
+            for (place, data) in fn_body.tables.places.iter_enumerated() {
+                let kind = match *data {
+                    lark_hir::PlaceData::Variable(variable) => Some(variable_kind(variable)),
+                    lark_hir::PlaceData::Entity(entity) => self.semantic_token_kind(entity),
+                    lark_hir::PlaceData::Field { .. } | lark_hir::PlaceData::Temporary(_) => None,
+                };
+                if let (Some(kind), Some(&span)) = (kind, fn_body.tables.spans.get(&place.into())) {
+                    classified.insert(span, (kind, false));
+                }
+            }
+
+            // Field and method names, as resolved by the type checker.
+            for (meta_index, &target) in &results.entities {
+                if let (Some(kind), Some(&span)) = (
+                    self.semantic_token_kind(target),
+                    fn_body.tables.spans.get(meta_index),
+                ) {
+                    classified.insert(span, (kind, false));
+                }
+            }
+        }
+
+        let mut tokens = vec![];
+        for token in self.file_tokens(file_name).into_value().iter() {
+            if token.value != LexToken::Identifier {
+                continue;
+            }
+
+            // Anything else that resolves to a type is a type annotation.
+            let classification = classified.get(&token.span).cloned().or_else(|| {
+                let name = text[token.span].intern(self);
+                let entity = self.resolve_name(file_entity, name)?;
+                match self.semantic_token_kind(entity)? {
+                    kind @ SemanticTokenKind::Struct | kind @ SemanticTokenKind::BuiltinType => {
+                        Some((kind, false))
+                    }
+                    _ => None,
+                }
+            });
+
+            if let Some((kind, declaration)) = classification {
+                tokens.push(SemanticToken {
+                    range: self.range(token.span),
+                    kind,
+                    declaration,
+                });
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    /// How to highlight a reference to `entity`, if at all.
+    fn semantic_token_kind(&self, entity: Entity) -> Option<SemanticTokenKind> {
+        match entity.untern(self) {
+            EntityData::ItemName {
+                kind: ItemKind::Struct,
+                ..
+            } => Some(SemanticTokenKind::Struct),
+            EntityData::ItemName {
+                kind: ItemKind::Function,
+                ..
+            } => Some(SemanticTokenKind::Function),
+            EntityData::MemberName {
+                kind: MemberKind::Field,
+                ..
+            } => Some(SemanticTokenKind::Field),
+            EntityData::MemberName {
+                kind: MemberKind::Method,
+                ..
+            } => Some(SemanticTokenKind::Method),
+            EntityData::LangItem(LangItem::Boolean)
+            | EntityData::LangItem(LangItem::Int)
+            | EntityData::LangItem(LangItem::Uint)
+            | EntityData::LangItem(LangItem::String) => Some(SemanticTokenKind::BuiltinType),
+            EntityData::LangItem(LangItem::True) | EntityData::LangItem(LangItem::False) => {
+                Some(SemanticTokenKind::BuiltinConstant)
+            }
+            EntityData::LangItem(LangItem::Debug) => Some(SemanticTokenKind::BuiltinFunction),
+            EntityData::LangItem(LangItem::Tuple(_))
+            | EntityData::InputFile { .. }
+            | EntityData::Error(_) => None,
+        }
+    }
+
+    /// The symbols defined in a file, as a tree: structs contain their
+    /// fields and methods.
+    fn document_symbols(&self, url: &str) -> Cancelable<Vec<DocumentSymbol>> {
+        let file_name = url.into_file_name(self);
+        let mut symbols = vec![];
+
+        for &entity in self.top_level_entities_in_file(file_name).iter() {
+            self.check_for_cancellation()?;
+
+            if let Some(mut symbol) = self.entity_symbol(entity) {
+                let children: Vec<_> = self
+                    .child_entities(entity)
+                    .iter()
+                    .filter_map(|&child| self.entity_symbol(child))
+                    .collect();
+                if !children.is_empty() {
+                    symbol.children = Some(children);
+                }
+                symbols.push(symbol);
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    /// The symbols, across all files, whose names fuzzily match `query`,
+    /// best matches first. Each symbol is a name, a kind, the file and
+    /// range it is defined in, and the name of its containing struct.
+    fn workspace_symbols(
+        &self,
+        query: &str,
+    ) -> Cancelable<Vec<(String, SymbolKind, String, Range, Option<String>)>> {
+        let mut matches = vec![];
+
+        for &input_file in &*self.file_names() {
+            self.check_for_cancellation()?;
+
+            let file_name = input_file.id.untern(self).to_string();
+            for &entity in self.top_level_entities_in_file(input_file).iter() {
+                let item = match self.entity_symbol(entity) {
+                    Some(item) => item,
+                    None => continue,
+                };
+
+                let members = self
+                    .child_entities(entity)
+                    .iter()
+                    .filter_map(|&child| self.entity_symbol(child))
+                    .map(|member| (member, Some(item.name.clone())))
+                    .collect::<Vec<_>>();
+
+                for (symbol, container) in Some((item, None)).into_iter().chain(members) {
+                    if let Some(score) = fuzzy_match_score(query, &symbol.name) {
+                        matches.push((
+                            score,
+                            (
+                                symbol.name,
+                                symbol.kind,
+                                file_name.clone(),
+                                symbol.range,
+                                container,
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        matches.sort_by(|(score_a, symbol_a), (score_b, symbol_b)| {
+            score_b
+                .cmp(score_a)
+                .then_with(|| symbol_a.0.cmp(&symbol_b.0))
+        });
+
+        Ok(matches.into_iter().map(|(_, symbol)| symbol).collect())
+    }
+
+    /// The symbol for a struct, function, field or method (without any
+    /// children).
+    fn entity_symbol(&self, entity: Entity) -> Option<DocumentSymbol> {
+        let (id, kind) = match entity.untern(self) {
+            EntityData::ItemName {
+                kind: ItemKind::Struct,
+                id,
+                ..
+            } => (id, SymbolKind::Struct),
+            EntityData::ItemName {
+                kind: ItemKind::Function,
+                id,
+                ..
+            } => (id, SymbolKind::Function),
+            EntityData::MemberName {
+                kind: MemberKind::Field,
+                id,
+                ..
+            } => (id, SymbolKind::Field),
+            EntityData::MemberName {
+                kind: MemberKind::Method,
+                id,
+                ..
+            } => (id, SymbolKind::Method),
+            _ => return None,
+        };
+
+        let detail = match kind {
+            SymbolKind::Struct => None,
+            _ => Some(self.entity_detail(entity)),
+        };
+
+        Some(DocumentSymbol {
+            name: id.untern(self).to_string(),
+            detail,
+            kind,
+            deprecated: None,
+            range: self.range(self.entity_span(entity)),
+            selection_range: self.range(self.characteristic_entity_span(entity)),
+            children: None,
+        })
+    }
+
+    /// Inlay hints for the part of a file in `range`: the type of each
+    /// `let` binding and each call argument, along with the permission
+    /// (own, share or borrow) inferred for the initializer or argument.
+    fn inlay_hints(&self, url: &str, range: Range) -> Cancelable<Vec<InlayHint>> {
+        let file_name = url.into_file_name(self);
+        let file_entity = EntityData::InputFile { file: file_name }.intern(self);
+        let mut hints = vec![];
+
+        for &entity in self.descendant_entities(file_entity).iter() {
+            self.check_for_cancellation()?;
+
+            if !entity.untern(self).has_fn_body() {
+                continue;
+            }
+
+            let fn_body = self.fn_body(entity).into_value();
+            let results = self.full_type_check(entity).into_value();
+            let mut hint = |index: lark_hir::MetaIndex,
+                            expression: Option<lark_hir::Expression>| {
+                let span = match fn_body.tables.spans.get(&index) {
+                    Some(span) => span,
+                    None => return,
+                };
+                let ty = match results.opt_ty(index) {
+                    Some(ty) => ty,
+                    None => return,
+                };
+                let perm = expression
+                    .and_then(|expression| results.access_permissions.get(&expression).cloned());
+                if let Some(label) = self.inlay_hint_label(ty, perm) {
+                    hints.push((span.end(), label));
+                }
+            };
+
+            for expression in fn_body.tables.expressions.indices() {
+                match fn_body.tables[expression] {
+                    lark_hir::ExpressionData::Let {
+                        variable,
+                        initializer,
+                        ..
+                    } => hint(variable.into(), initializer),
+
+                    lark_hir::ExpressionData::Call { arguments, .. } => {
+                        for argument in arguments.iter(&fn_body) {
+                            hint(argument.into(), Some(argument));
+                        }
+                    }
+
+                    // The first argument is the receiver.
+                    lark_hir::ExpressionData::MethodCall { arguments, .. } => {
+                        for argument in arguments.iter(&fn_body).skip(1) {
+                            hint(argument.into(), Some(argument));
+                        }
+                    }
+
+                    _ => {}
+                }
+            }
+        }
+
+        hints.sort_by_key(|&(index, _)| index);
+
+        Ok(hints
+            .into_iter()
+            .map(|(index, label)| InlayHint {
+                position: self.location(file_name, index).as_position(),
+                label,
+            })
+            .filter(|hint| range.start <= hint.position && hint.position <= range.end)
+            .collect())
+    }
+
+    /// The label of an inlay hint: `: perm type`, or just `: type` if
+    /// there is no permission to show.
+    fn inlay_hint_label(
+        &self,
+        ty: lark_ty::Ty<lark_ty::full_inferred::FullInferred>,
+        perm: Option<lark_ty::PermKind>,
+    ) -> Option<String> {
+        let base = ty.base.untern(self);
+        if let lark_ty::BaseKind::Error = base.kind {
+            return None;
+        }
+
+        let perm = match perm {
+            Some(lark_ty::PermKind::Own) => "own ",
+            Some(lark_ty::PermKind::Share) => "share ",
+            Some(lark_ty::PermKind::Borrow) => "borrow ",
+            None => "",
+        };
+
+        Some(format!(": {}{}", perm, base.pretty_print(self)))
+    }
+
+    /// Code lenses for a file: "Run" above each `main` function, and
+    /// the references to each struct and function.
+    fn code_lenses(&self, url: &str) -> Cancelable<Vec<CodeLens>> {
+        let file_name = url.into_file_name(self);
+        let main_name = "main".intern(self);
+        let mut lenses = vec![];
+
+        for &entity in self.top_level_entities_in_file(file_name).iter() {
+            self.check_for_cancellation()?;
+
+            let range = self.range(self.characteristic_entity_span(entity));
+            match entity.untern(self) {
+                EntityData::ItemName {
+                    kind: ItemKind::Function,
+                    id,
+                    ..
+                } if id == main_name => {
+                    lenses.push(CodeLens {
+                        range,
+                        kind: CodeLensKind::RunMain,
+                    });
+                }
+                EntityData::ItemName { .. } => {}
+                _ => continue,
+            }
+
+            let references = self.find_all_references_to_definition(entity);
+            lenses.push(CodeLens {
+                range,
+                kind: CodeLensKind::References(references),
+            });
+        }
+
+        Ok(lenses)
+    }
+
+    /// The function or method at `position`: where it is defined, or
+    /// a call to it.
+    fn callable_at_position(&self, url: &str, position: Position) -> Option<Entity> {
+        let file_name = url.into_file_name(self);
+        let byte_index = self.position_to_byte_index(url, position);
+
+        self.hover_targets(file_name, byte_index)
+            .iter()
+            .rev()
+            .filter_map(|target| match target.kind {
+                HoverTargetKind::Entity(entity) => Some(entity),
+                HoverTargetKind::MetaIndex(entity, lark_hir::MetaIndex::Place(place)) => {
+                    match self.fn_body(entity).into_value().tables[place] {
+                        lark_hir::PlaceData::Entity(entity) => Some(entity),
+                        _ => None,
+                    }
+                }
+                HoverTargetKind::MetaIndex(entity, mi) => {
+                    let results = self.full_type_check(entity).into_value();
+                    results.entities.get(&mi).cloned()
+                }
+            })
+            .find(|entity| entity.untern(self).has_fn_body())
+    }
+
+    fn call_hierarchy_item(&self, entity: Entity) -> Option<CallHierarchyItem> {
+        let symbol = self.entity_symbol(entity)?;
+        let file = self.entity_span(entity).file().id.untern(self).to_string();
+
+        Some(CallHierarchyItem {
+            name: symbol.name,
+            kind: symbol.kind,
+            detail: symbol.detail,
+            file,
+            range: symbol.range,
+            selection_range: symbol.selection_range,
+        })
+    }
+
+    /// The function or method at `position`, as a call hierarchy item.
+    fn call_hierarchy_items(
+        &self,
+        url: &str,
+        position: Position,
+    ) -> Cancelable<Vec<CallHierarchyItem>> {
+        let entity = self.callable_at_position(url, position);
+        self.check_for_cancellation()?;
+
+        Ok(entity
+            .and_then(|entity| self.call_hierarchy_item(entity))
+            .into_iter()
+            .collect())
+    }
+
+    /// The functions and methods that call the one at `position`, each
+    /// with the ranges of its calls to it.
+    fn incoming_calls_at_position(
+        &self,
+        url: &str,
+        position: Position,
+    ) -> Cancelable<Vec<(CallHierarchyItem, Vec<Range>)>> {
+        let callee = match self.callable_at_position(url, position) {
+            Some(callee) => callee,
+            None => return Ok(vec![]),
+        };
+        self.check_for_cancellation()?;
+
+        let calls = self
+            .incoming_calls()
+            .get(&callee)
+            .cloned()
+            .unwrap_or_default();
+        self.check_for_cancellation()?;
+
+        Ok(self.group_calls(&calls, |call| call.caller))
+    }
+
+    /// The functions and methods called by the one at `position`, each
+    /// with the ranges of the calls to it.
+    fn outgoing_calls_at_position(
+        &self,
+        url: &str,
+        position: Position,
+    ) -> Cancelable<Vec<(CallHierarchyItem, Vec<Range>)>> {
+        let caller = match self.callable_at_position(url, position) {
+            Some(caller) => caller,
+            None => return Ok(vec![]),
+        };
+        self.check_for_cancellation()?;
+
+        let calls = self.outgoing_calls(caller);
+        Ok(self.group_calls(&calls, |call| call.callee))
+    }
+
+    /// Groups `calls` by the entity `key` picks out, with the range of
+    /// each call (in the caller's body).
+    fn group_calls(
+        &self,
+        calls: &[lark_type_check::Call],
+        key: impl Fn(&lark_type_check::Call) -> Entity,
+    ) -> Vec<(CallHierarchyItem, Vec<Range>)> {
+        let mut groups: Vec<(Entity, Vec<Range>)> = vec![];
+        for call in calls {
+            let span = self.fn_body(call.caller).into_value().span(call.expression);
+            let range = self.range(span);
+            match groups.iter_mut().find(|(entity, _)| *entity == key(call)) {
+                Some((_, ranges)) => ranges.push(range),
+                None => groups.push((key(call), vec![range])),
+            }
+        }
+
+        groups
+            .into_iter()
+            .filter_map(|(entity, ranges)| Some((self.call_hierarchy_item(entity)?, ranges)))
+            .collect()
+    }
+
+    /// The ranges of lines the IDE can fold: each item (including struct
+    /// bodies), each `{ ... }` block in a function body (the body itself,
+    /// `if` branches and so on) and each run of comments on lines of
+    /// their own.
+    fn folding_ranges(&self, url: &str) -> Cancelable<Vec<FoldingRange>> {
+        let file_name = url.into_file_name(self);
+        let file_entity = EntityData::InputFile { file: file_name }.intern(self);
+        let line = |index: ByteIndex| self.location(file_name, index).line as u64;
+        let mut folds = vec![];
+
+        for &entity in self.descendant_entities(file_entity).iter() {
+            self.check_for_cancellation()?;
+
+            if entity == file_entity {
+                continue;
+            }
+
+            let span = self.entity_span(entity);
+            folds.push((line(span.start()), line(span.end()), None));
+
+            if entity.untern(self).has_fn_body() {
+                let fn_body = self.fn_body(entity).into_value();
+                for block in &fn_body.tables.blocks {
+                    folds.push((line(block.start()), line(block.end()), None));
+                }
+            }
+        }
+
+        let tokens = self.file_tokens(file_name).into_value();
+        let mut comments: Option<(u64, u64)> = None;
+        let mut line_has_code = false;
+        for token in tokens.iter() {
+            let (start, end) = (token.span.start(), token.span.end());
+            match token.value {
+                LexToken::Whitespace => continue,
+                LexToken::Newline => {
+                    line_has_code = false;
+                    continue;
+                }
+                LexToken::Comment if !line_has_code => {
+                    let (first, last) = (line(start), line(end));
+                    comments = match comments {
+                        Some((run_first, run_last)) if run_last + 1 >= first => {
+                            Some((run_first, last))
+                        }
+                        Some((run_first, run_last)) => {
+                            folds.push((run_first, run_last, Some(FoldingRangeKind::Comment)));
+                            Some((first, last))
+                        }
+                        None => Some((first, last)),
+                    };
+                    continue;
+                }
+                _ => {}
+            }
+            line_has_code = true;
+        }
+        if let Some((run_first, run_last)) = comments {
+            folds.push((run_first, run_last, Some(FoldingRangeKind::Comment)));
+        }
+
+        folds.retain(|&(start_line, end_line, _)| start_line < end_line);
+        folds.sort_by_key(|&(start_line, end_line, _)| (start_line, end_line));
+        folds.dedup_by_key(|&mut (start_line, end_line, _)| (start_line, end_line));
+
+        Ok(folds
+            .into_iter()
+            .map(|(start_line, end_line, kind)| FoldingRange {
+                start_line,
+                start_character: None,
+                end_line,
+                end_character: None,
+                kind,
+            })
+            .collect())
+    }
+
+    /// For each of `positions`, the ranges that "expand selection" in
+    /// the IDE steps through, innermost first: the token there, the HIR
+    /// expressions (and places, variables and so on) around it, and then
+    /// the enclosing entities, ending with the whole file.
+    fn selection_ranges(&self, url: &str, positions: &[Position]) -> Cancelable<Vec<Vec<Range>>> {
+        let file_name = url.into_file_name(self);
+        let file_entity = EntityData::InputFile { file: file_name }.intern(self);
+        let tokens = self.file_tokens(file_name).into_value();
+        let mut result = vec![];
+
+        for position in positions {
+            self.check_for_cancellation()?;
+
+            let index = self.byte_index(file_name, position.line, position.character);
+            let around = |span: Span<FileName>| span.start() <= index && index <= span.end();
+            let mut spans = vec![];
+
+            let mut candidates = tokens
+                .iter()
+                .filter(|token| match token.value {
+                    LexToken::Whitespace | LexToken::Newline => false,
+                    _ => around(token.span),
+                })
+                .map(|token| token.span);
+            if let Some(span) = candidates.next() {
+                // Between two tokens, prefer the one that starts here.
+                spans.push(
+                    candidates
+                        .find(|span| span.start() == index)
+                        .unwrap_or(span),
+                );
+            }
+
+            if let Some(entity) = self.fn_entity_at(file_name, index) {
+                let fn_body = self.fn_body(entity).into_value();
+                spans.extend(
+                    fn_body
+                        .tables
+                        .spans
+                        .values()
+                        .cloned()
+                        .filter(|&s| around(s)),
+                );
+            }
+
+            for &entity in self.descendant_entities(file_entity).iter() {
+                let span = self.entity_span(entity);
+                if around(span) {
+                    spans.push(span);
+                }
+            }
+
+            // Each range has to contain the one before it.
+            spans.sort_by_key(|span| (span.len(), std::cmp::Reverse(span.start())));
+            let mut ranges: Vec<Range> = vec![];
+            let mut previous: Option<Span<FileName>> = None;
+            for span in spans {
+                if let Some(previous) = previous {
+                    if span.start() > previous.start()
+                        || span.end() < previous.end()
+                        || span == previous
+                    {
+                        continue;
+                    }
+                }
+                ranges.push(self.range(span));
+                previous = Some(span);
+            }
+            result.push(ranges);
+        }
+
+        Ok(result)
+    }
+
+    /// Edits that format the file `url`, or, given a range, just the
+    /// lines that overlap it. `None` if the file has syntax errors.
+    fn formatting_edits(
+        &self,
+        url: &str,
+        range: Option<Range>,
+    ) -> Cancelable<Option<Vec<(Range, String)>>> {
+        let file_name = url.into_file_name(self);
+        self.check_for_cancellation()?;
+
+        let lines = match lark_parser::format_file(self, file_name) {
+            Ok(lines) => lines,
+            Err(_) => return Ok(None),
+        };
+        let text = self.file_text(file_name);
+
+        let range = match range {
+            Some(range) => range,
+            None => {
+                let formatted = lark_parser::formatted_text(&lines);
+                if formatted == &text[..] {
+                    return Ok(Some(vec![]));
+                }
+
+                let end = self
+                    .location(file_name, ByteIndex::from(text.len()))
+                    .as_position();
+                let whole_file = Range::new(Position::new(0, 0), end);
+                return Ok(Some(vec![(whole_file, formatted)]));
+            }
+        };
+
+        let line_offsets = self.line_offsets(file_name);
+        let offset = |line: usize| line_offsets.get(line).cloned().unwrap_or(text.len());
+        let mut edits = vec![];
+        for line in lines {
+            let (start, end) = (line.lines.start as u64, line.lines.end as u64);
+            if end <= range.start.line || start > range.end.line {
+                continue;
+            }
+
+            let original = &text[offset(line.lines.start)..offset(line.lines.end)];
+            let replacement = match line.text {
+                Some(text) => text + "\n",
+                None => String::new(),
+            };
+            if original != replacement {
+                let line_range = Range::new(Position::new(start, 0), Position::new(end, 0));
+                edits.push((line_range, replacement));
+            }
+        }
+
+        Ok(Some(edits))
+    }
+
+    /// Fixes for the diagnostics in a file that overlap `range`. What
+    /// we can offer depends on the kind of each diagnostic.
+    fn quick_fixes(&self, url: &str, range: Range) -> Cancelable<Vec<QuickFix>> {
+        let file_name = url.into_file_name(self);
+        let text = self.file_text(file_name);
+        let mut fixes = vec![];
+
+        for diagnostic in self.errors_for_file(file_name)? {
+            let diagnostic_range = self.range(diagnostic.span);
+            if diagnostic_range.end < range.start || range.end < diagnostic_range.start {
+                continue;
+            }
+
+            let mut fix = |title: String, edits: Vec<(Range, String)>| {
+                fixes.push(QuickFix {
+                    title,
+                    diagnostic: (diagnostic_range, diagnostic.label.clone()),
+                    edits,
+                });
+            };
+
+            match &diagnostic.kind {
+                DiagnosticKind::Other => {}
+
+                DiagnosticKind::UnknownIdentifier { name } => {
+                    for similar in self.similar_names(file_name, diagnostic.span.start(), name)? {
+                        fix(
+                            format!("Change to `{}`", similar),
+                            vec![(diagnostic_range, similar)],
+                        );
+                    }
+
+                    // New items go at the end of the file, after a blank
+                    // line.
+                    let end = self
+                        .location(file_name, ByteIndex::from(text.len()))
+                        .as_position();
+                    let end = Range::new(end, end);
+                    let separator = if text.is_empty() || text.ends_with("\n\n") {
+                        ""
+                    } else if text.ends_with('\n') {
+                        "\n"
+                    } else {
+                        "\n\n"
+                    };
+                    fix(
+                        format!("Create struct `{}`", name),
+                        vec![(end, format!("{}struct {} {{\n}}\n", separator, name))],
+                    );
+                    fix(
+                        format!("Create function `{}`", name),
+                        vec![(end, format!("{}def {}() {{\n}}\n", separator, name))],
+                    );
+                }
+
+                DiagnosticKind::MissingField { field } => {
+                    let start = diagnostic.span.start().to_usize();
+                    if let Some((index, separator)) = argument_insertion(&text, start) {
+                        let position = self
+                            .location(file_name, ByteIndex::from(index))
+                            .as_position();
+                        fix(
+                            format!("Add missing field `{}`", field),
+                            vec![(
+                                Range::new(position, position),
+                                format!("{}{}: {}", separator, field, field),
+                            )],
+                        );
+                    }
+                }
+
+                &DiagnosticKind::MismatchedArgumentCount { expected, found } => {
+                    let parameters = match self.callee_parameter_names(file_name, diagnostic.span) {
+                        Some(parameters) => parameters,
+                        None => continue,
+                    };
+                    if found >= expected || parameters.len() != expected {
+                        continue;
+                    }
+
+                    let start = diagnostic.span.start().to_usize();
+                    if let Some((index, separator)) = argument_insertion(&text, start) {
+                        let position = self
+                            .location(file_name, ByteIndex::from(index))
+                            .as_position();
+                        let missing = &parameters[found..];
+                        let names: Vec<_> =
+                            missing.iter().map(|name| format!("`{}`", name)).collect();
+                        let title = if missing.len() == 1 {
+                            format!("Add missing argument {}", names[0])
+                        } else {
+                            format!("Add missing arguments {}", names.join(", "))
+                        };
+                        fix(
+                            title,
+                            vec![(
+                                Range::new(position, position),
+                                format!("{}{}", separator, missing.join(", ")),
+                            )],
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(fixes)
+    }
+
+    /// The names visible at `index` that are spelled almost like
+    /// `name`, closest first.
+    fn similar_names(
+        &self,
+        file: FileName,
+        index: ByteIndex,
+        name: &str,
+    ) -> Cancelable<Vec<String>> {
+        let fn_entity = self.fn_entity_at(file, index);
+        let scope = fn_entity.unwrap_or_else(|| EntityData::InputFile { file }.intern(self));
+        let mut candidates = vec![];
+
+        if let Some(fn_entity) = fn_entity {
+            let fn_body = self.fn_body(fn_entity).into_value();
+            for variable in variables_in_scope(&fn_body, index) {
+                let variable_name = fn_body.tables[fn_body.tables[variable].name].text;
+                candidates.push(variable_name.untern(self).to_string());
+            }
+        }
+
+        for &input_file in &*self.file_names() {
+            self.check_for_cancellation()?;
+
+            for &entity in self.top_level_entities_in_file(input_file).iter() {
+                if let EntityData::ItemName { id, .. } = entity.untern(self) {
+                    if self.resolve_name(scope, id) == Some(entity) {
+                        candidates.push(id.untern(self).to_string());
+                    }
+                }
+            }
+        }
+
+        candidates.extend(LANG_ITEM_NAMES.iter().map(|&(name, _)| name.to_string()));
+
+        // Allow about one typo for every three characters.
+        let max_distance = std::cmp::max(1, name.len() / 3);
+        let mut similar: Vec<_> = candidates
+            .into_iter()
+            .filter(|candidate| candidate != name)
+            .map(|candidate| (edit_distance(name, &candidate), candidate))
+            .filter(|&(distance, _)| distance <= max_distance)
+            .collect();
+        similar.sort();
+        similar.dedup();
+
+        Ok(similar
+            .into_iter()
+            .map(|(_, candidate)| candidate)
+            .take(3)
+            .collect())
+    }
+
+    /// The names of the parameters of the function or method called
+    /// where `span` starts (not counting a method's `self`).
+    fn callee_parameter_names(&self, file: FileName, span: Span<FileName>) -> Option<Vec<String>> {
+        let fn_entity = self.fn_entity_at(file, span.start())?;
+        let fn_body = self.fn_body(fn_entity).into_value();
+        let results = self.full_type_check(fn_entity).into_value();
+
+        // The type checker records what the callee's name refers to,
+        // and that name is where the span of a call starts.
+        let callee = results
+            .entities
+            .iter()
+            .filter(|(index, _)| {
+                fn_body.tables.spans.get(index).map(|span| span.start()) == Some(span.start())
+            })
+            .map(|(_, &entity)| entity)
+            .find(|entity| entity.untern(self).has_fn_body())?;
+
+        let callee_body = self.fn_body(callee).into_value();
+        let arguments = callee_body.arguments.as_ref().ok()?;
+        let skip = match callee.untern(self) {
+            EntityData::MemberName {
+                kind: MemberKind::Method,
+                ..
+            } => 1,
+            _ => 0,
+        };
+
+        Some(
+            arguments
+                .iter(&callee_body)
+                .skip(skip)
+                .map(|variable| {
+                    let name = callee_body.tables[callee_body.tables[variable].name].text;
+                    name.untern(self).to_string()
+                })
+                .collect(),
+        )
+    }
+
+    fn position_to_byte_index(&self, url: &str, position: Position) -> ByteIndex {
+        let url_id = url.intern(self);
+        self.byte_index(FileName { id: url_id }, position.line, position.character)
+    }
+}
+
+/// Some Lark code, as a markdown code block.
+fn code_block(code: &str) -> String {
+    format!("```lark\n{}\n```", code)
+}
+
+/// Words the parser gives a meaning to, which can't be used as names.
+const KEYWORDS: &[&str] = &["def", "else", "if", "let", "self", "struct"];
+
+/// True if `name` lexes as a single identifier.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn variable_name(
+    fn_body: &lark_hir::FnBody,
+    variable: lark_hir::Variable,
+) -> lark_string::GlobalIdentifier {
+    fn_body.tables[fn_body.tables[variable].name].text
+}
+
+/// The variables visible at `index` in `fn_body`, in the order they are
+/// declared (so later ones shadow earlier ones with the same name).
+fn variables_in_scope(fn_body: &lark_hir::FnBody, index: ByteIndex) -> Vec<lark_hir::Variable> {
+    let mut variables: Vec<_> = match &fn_body.arguments {
+        Ok(arguments) => arguments.iter(fn_body).collect(),
+        Err(_) => vec![],
+    };
+
+    for expression in fn_body.tables.expressions.indices() {
+        if let lark_hir::ExpressionData::Let { variable, body, .. } = fn_body.tables[expression] {
+            if let Some(span) = fn_body.tables.spans.get(&expression.into()) {
+                if span.end() <= index && index <= expression_extent(fn_body, body) {
+                    variables.push(variable);
+                }
+            }
+        }
+    }
+
+    variables.sort_by_key(|&variable| fn_body.span(variable).start());
+    variables
+}
+
+/// The end of the text covered by `expression` and everything nested in
+/// it. (The span of a block's statements only covers the first one.)
+fn expression_extent(fn_body: &lark_hir::FnBody, expression: lark_hir::Expression) -> ByteIndex {
+    child_expressions(fn_body, expression).into_iter().fold(
+        fn_body
+            .tables
+            .spans
+            .get(&expression.into())
+            .map(|span| span.end())
+            .unwrap_or(ByteIndex::from(0)),
+        |end, child| end.max(expression_extent(fn_body, child)),
+    )
+}
+
+/// The expressions directly nested in `expression`.
+fn child_expressions(
+    fn_body: &lark_hir::FnBody,
+    expression: lark_hir::Expression,
+) -> Vec<lark_hir::Expression> {
+    use lark_hir::ExpressionData;
+
+    match fn_body.tables[expression] {
+        ExpressionData::Let {
+            initializer, body, ..
+        } => initializer.into_iter().chain(Some(body)).collect(),
+        ExpressionData::Place { place } => place_expressions(fn_body, place),
+        ExpressionData::Assignment { place, value } => {
+            let mut children = place_expressions(fn_body, place);
+            children.push(value);
+            children
+        }
+        ExpressionData::MethodCall { arguments, .. } => arguments.iter(fn_body).collect(),
+        ExpressionData::Call {
+            function,
+            arguments,
+        } => Some(function)
+            .into_iter()
+            .chain(arguments.iter(fn_body))
+            .collect(),
+        ExpressionData::Sequence { first, second } => vec![first, second],
+        ExpressionData::If {
+            condition,
+            if_true,
+            if_false,
+        } => vec![condition, if_true, if_false],
+        ExpressionData::Binary { left, right, .. } => vec![left, right],
+        ExpressionData::Unary { value, .. } => vec![value],
+        ExpressionData::Aggregate { fields, .. } => fields
+            .iter(fn_body)
+            .map(|field| fn_body.tables[field].expression)
+            .collect(),
+        ExpressionData::Literal { .. } | ExpressionData::Unit {} | ExpressionData::Error { .. } => {
+            vec![]
+        }
+    }
+}
+
+/// The expressions nested in a place (e.g. the `foo()` in `foo().bar`).
+fn place_expressions(
+    fn_body: &lark_hir::FnBody,
+    place: lark_hir::Place,
+) -> Vec<lark_hir::Expression> {
+    match fn_body.tables[place] {
+        lark_hir::PlaceData::Field { owner, .. } => place_expressions(fn_body, owner),
+        lark_hir::PlaceData::Temporary(expression) => vec![expression],
+        lark_hir::PlaceData::Variable(_) | lark_hir::PlaceData::Entity(_) => vec![],
+    }
+}
+
+/// If `text` ends inside the parentheses of a call, finds the `(` of
+/// that call, the start of the argument being typed and how many
+/// arguments come before it. Commas and parentheses in nested calls,
+/// blocks and string literals are skipped.
+fn enclosing_call(text: &str) -> Option<(usize, usize, usize)> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut argument_start = None;
+    let mut preceding_arguments = 0;
+
+    for (index, c) in text.char_indices().rev() {
+        if in_string {
+            in_string = c != '"';
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            ')' | '}' => depth += 1,
+            '(' if depth == 0 => {
+                return Some((
+                    index,
+                    argument_start.unwrap_or(index + 1),
+                    preceding_arguments,
+                ));
+            }
+            // We're in a block, not an argument list.
+            '{' if depth == 0 => return None,
+            '(' | '{' => depth -= 1,
+            ',' if depth == 0 => {
+                argument_start.get_or_insert(index + 1);
+                preceding_arguments += 1;
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Where to add an argument to the first argument list at or after
+/// `from` in `text`: the index right after the last argument already
+/// there, and what should separate that argument from the new one.
+fn argument_insertion(text: &str, from: usize) -> Option<(usize, &'static str)> {
+    let open = from + text[from..].find('(')?;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut close = None;
+
+    for (index, c) in text[open..].char_indices() {
+        if in_string {
+            in_string = c != '"';
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '(' | '{' => depth += 1,
+            ')' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(open + index);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let arguments = text[open + 1..close?].trim_end();
+    let separator = if arguments.trim_start().is_empty() {
+        ""
+    } else if arguments.ends_with(',') {
+        " "
+    } else {
+        ", "
+    };
+
+    Some((open + 1 + arguments.len(), separator))
+}
+
+/// The number of characters one has to insert, delete or replace to
+/// turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &b_char) in b.iter().enumerate() {
+            let replace = previous[j] + if a_char == b_char { 0 } else { 1 };
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Scores how well `candidate` matches `query`: every character of the
+/// query must appear in the candidate, in order and ignoring case, with
+/// a bonus for matching at the start or right after the previous match.
+/// Returns `None` if there is no match at all.
+fn fuzzy_match_score(query: &str, candidate: &str) -> Option<usize> {
+    let mut candidate_chars = candidate.chars().flat_map(char::to_lowercase).enumerate();
+    let mut previous_match = None;
+    let mut score = 0;
+
+    for query_char in query.chars().flat_map(char::to_lowercase) {
+        loop {
+            let (index, candidate_char) = candidate_chars.next()?;
+            if candidate_char == query_char {
+                score += 1;
+                if index == 0 || previous_match.map(|previous| previous + 1) == Some(index) {
+                    score += 2;
+                }
+                previous_match = Some(index);
+                break;
+            }
+        }
+    }
+
+    Some(score)
 }
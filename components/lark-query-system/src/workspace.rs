@@ -0,0 +1,59 @@
+//! The files of the workspace on disk. We load them all, so that (for
+//! example) references are found in files the IDE hasn't opened.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// The `.lark` files in `root` and the directories below it, skipping
+/// hidden ones (like `.git`).
+pub fn lark_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    let mut directories = vec![root.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(error) => {
+                log::warn!("could not read {}: {}", directory.display(), error);
+                continue;
+            }
+        };
+
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let hidden = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map_or(false, |name| name.starts_with('.'));
+            if hidden {
+                continue;
+            }
+
+            if path.is_dir() {
+                directories.push(path);
+            } else if is_lark_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    files
+}
+
+/// The contents of the file at `url`, if it is a `.lark` file we can
+/// read.
+pub fn read_lark_file(url: &Url) -> Option<String> {
+    let path = url.to_file_path().ok()?;
+    if !is_lark_file(&path) {
+        return None;
+    }
+
+    fs::read_to_string(&path).ok()
+}
+
+fn is_lark_file(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |extension| extension == "lark")
+}
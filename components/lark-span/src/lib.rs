@@ -4,10 +4,12 @@
 
 mod file;
 mod location;
+mod position_encoding;
 mod span;
 mod spanned;
 
 pub use self::file::*;
 pub use self::location::*;
+pub use self::position_encoding::*;
 pub use self::span::*;
 pub use self::spanned::*;
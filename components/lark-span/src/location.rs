@@ -7,7 +7,8 @@ pub struct Location {
     /// 0-based line number
     pub line: usize,
 
-    /// 0-based column number, in utf-8 characters
+    /// 0-based column number, in the units of the database's
+    /// `position_encoding`
     pub column: usize,
 
     /// byte index into file text
@@ -0,0 +1,66 @@
+use lark_debug_derive::DebugWith;
+
+/// The units in which a column within a line is counted. Lark itself
+/// (the CLI and the test harness) counts characters; the language
+/// server protocol counts in UTF-16 code units unless the editor and
+/// server agree otherwise.
+#[derive(Debug, DebugWith, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum PositionEncoding {
+    /// Bytes of UTF-8.
+    Utf8,
+
+    /// UTF-16 code units (so characters outside the basic
+    /// multilingual plane count twice).
+    Utf16,
+
+    /// Unicode characters.
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// The name the language server protocol uses for this encoding.
+    pub fn name(self) -> &'static str {
+        match self {
+            PositionEncoding::Utf8 => "utf-8",
+            PositionEncoding::Utf16 => "utf-16",
+            PositionEncoding::Utf32 => "utf-32",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PositionEncoding> {
+        match name {
+            "utf-8" => Some(PositionEncoding::Utf8),
+            "utf-16" => Some(PositionEncoding::Utf16),
+            "utf-32" => Some(PositionEncoding::Utf32),
+            _ => None,
+        }
+    }
+
+    /// The length of `text`, in the units of this encoding.
+    pub fn len(self, text: &str) -> usize {
+        match self {
+            PositionEncoding::Utf8 => text.len(),
+            PositionEncoding::Utf16 => text.chars().map(char::len_utf16).sum(),
+            PositionEncoding::Utf32 => text.chars().count(),
+        }
+    }
+
+    /// The byte offset within `line` of the column `column`, counted in
+    /// the units of this encoding. A column past the end of the line
+    /// (or in the middle of a character) gives the end of the line (or
+    /// the start of the character after).
+    pub fn byte_offset(self, line: &str, column: usize) -> usize {
+        let mut units = 0;
+        for (offset, c) in line.char_indices() {
+            if units >= column {
+                return offset;
+            }
+            units += match self {
+                PositionEncoding::Utf8 => c.len_utf8(),
+                PositionEncoding::Utf16 => c.len_utf16(),
+                PositionEncoding::Utf32 => 1,
+            };
+        }
+        line.len()
+    }
+}
@@ -0,0 +1,350 @@
+//! Differential testing: run the same program through `lark-eval` and
+//! through the Rust backend and check that both print the same thing.
+//!
+//! The programs come from the random generator in `generator`; when the
+//! two disagree, the program is shrunk to a (locally) minimal one that
+//! still shows the disagreement before being reported.
+
+use lark_parser::ParserDatabaseExt;
+use lark_query_system::ls_ops::{Cancelled, LsDatabase};
+use lark_query_system::LarkDatabase;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process::Command;
+
+mod generator;
+pub use generator::{
+    generate_program, Expr, FunctionDef, GeneratorOptions, Program, ProgramGenerator, Rng,
+    Statement, StructDef,
+};
+
+/// What happened when we ran a program one way or the other: either
+/// the printed output, or a description of how it failed.
+pub type Outcome = Result<String, String>;
+
+#[derive(Clone, Debug)]
+pub enum Verdict {
+    /// Both ways of running the program printed this.
+    Agree(String),
+
+    /// The program does not type check, so there is nothing to compare.
+    /// For generated programs this is a bug in the generator.
+    Rejected(Vec<String>),
+
+    Disagree {
+        eval: Outcome,
+        build: Outcome,
+    },
+}
+
+/// A program on which the interpreter and the compiled binary disagree.
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    pub seed: u64,
+    pub original: String,
+    pub minimized: String,
+    pub eval: Outcome,
+    pub build: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            fmt,
+            "eval and build disagree on the program for seed {}",
+            self.seed
+        )?;
+        writeln!(fmt, "# minimized program")?;
+        writeln!(fmt, "{}", self.minimized)?;
+        writeln!(fmt, "# eval")?;
+        writeln!(fmt, "{}", describe(&self.eval))?;
+        writeln!(fmt, "# build")?;
+        writeln!(fmt, "{}", describe(&self.build))?;
+        writeln!(fmt, "# original program")?;
+        write!(fmt, "{}", self.original)
+    }
+}
+
+fn describe(outcome: &Outcome) -> String {
+    match outcome {
+        Ok(output) => output.clone(),
+        Err(failure) => format!("failed: {}", failure),
+    }
+}
+
+pub struct DifferentialRunner {
+    /// Where we put the executables we build
+    work_dir: PathBuf,
+}
+
+impl DifferentialRunner {
+    pub fn new(work_dir: impl Into<PathBuf>) -> Self {
+        DifferentialRunner {
+            work_dir: work_dir.into(),
+        }
+    }
+
+    /// Generate the program for `seed`, run it both ways and, if they
+    /// disagree, minimize it. Panics if the generated program doesn't
+    /// type check, since that means the generator is broken.
+    pub fn run_seed(&self, seed: u64) -> Option<Mismatch> {
+        let program = generate_program(seed);
+        let original = program.to_string();
+
+        match self.check_source(&original) {
+            Verdict::Agree(_) => None,
+            Verdict::Rejected(errors) => panic!(
+                "generated program for seed {} does not type check: {:#?}\n{}",
+                seed, errors, original
+            ),
+            Verdict::Disagree { .. } => {
+                let minimized = self.minimize(program);
+                let minimized_source = minimized.to_string();
+                match self.check_source(&minimized_source) {
+                    Verdict::Disagree { eval, build } => Some(Mismatch {
+                        seed,
+                        original,
+                        minimized: minimized_source,
+                        eval,
+                        build,
+                    }),
+                    verdict => panic!("minimized program no longer disagrees: {:?}", verdict),
+                }
+            }
+        }
+    }
+
+    /// Type check `source` and, if that succeeds, run it with the
+    /// interpreter and as a compiled binary.
+    pub fn check_source(&self, source: &str) -> Verdict {
+        let mut db = LarkDatabase::default();
+        db.add_file("differential.lark", source);
+
+        let errors = match db.errors_for_project() {
+            Ok(errors) => errors,
+            Err(Cancelled) => panic!("encountered cancellation in differential test"),
+        };
+        let errors: Vec<String> = errors
+            .into_iter()
+            .flat_map(|(_, errors)| errors)
+            .map(|error| format!("{:?}", error))
+            .collect();
+        if !errors.is_empty() {
+            return Verdict::Rejected(errors);
+        }
+
+        let eval = self.run_eval(&db);
+        let build = self.run_build(&db);
+        match (&eval, &build) {
+            (Ok(eval_output), Ok(build_output)) if eval_output == build_output => {
+                Verdict::Agree(eval_output.clone())
+            }
+            _ => Verdict::Disagree { eval, build },
+        }
+    }
+
+    fn run_eval(&self, db: &LarkDatabase) -> Outcome {
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut handler = lark_eval::IOHandler::new(true);
+            lark_eval::eval(db, &mut handler);
+            handler.redirect.unwrap()
+        }))
+        .map_err(panic_message)
+    }
+
+    fn run_build(&self, db: &LarkDatabase) -> Outcome {
+        let exe_path = self.work_dir.join(if cfg!(windows) {
+            "differential.exe"
+        } else {
+            "differential"
+        });
+
+        let source = panic::catch_unwind(AssertUnwindSafe(|| {
+            lark_build::codegen(db, lark_build::CodegenType::Rust, &Default::default())
+        }))
+        .map_err(panic_message)?;
+
+        lark_build::build(
+            exe_path.to_str().unwrap(),
+            &source.value,
+            lark_build::CodegenType::Rust,
+            &Default::default(),
+            &Default::default(),
+        )
+        .map_err(|err| format!("generated Rust does not compile: {}", err))?;
+
+        let output = Command::new(&exe_path)
+            .output()
+            .map_err(|err| format!("failed to run `{}`: {}", exe_path.display(), err))?;
+        if !output.status.success() {
+            return Err(format!(
+                "binary exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        String::from_utf8(output.stdout).map_err(|err| format!("output not utf8: {}", err))
+    }
+
+    /// Greedily apply shrinking steps for as long as the result still
+    /// type checks and still makes eval and build disagree.
+    fn minimize(&self, mut program: Program) -> Program {
+        'shrink: loop {
+            for candidate in shrink_candidates(&program) {
+                if let Verdict::Disagree { .. } = self.check_source(&candidate.to_string()) {
+                    program = candidate;
+                    continue 'shrink;
+                }
+            }
+
+            return program;
+        }
+    }
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        format!("panicked: {}", message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        format!("panicked: {}", message)
+    } else {
+        "panicked".to_string()
+    }
+}
+
+/// All the programs that are one step smaller than `program`. Many of
+/// them will not type check (e.g. because they drop a `let` whose
+/// variable is still used); the minimizer just skips those.
+fn shrink_candidates(program: &Program) -> Vec<Program> {
+    let mut candidates = vec![];
+
+    // Drop a whole struct or function (never `main`, which is last).
+    for index in 0..program.structs.len() {
+        candidates.extend(remove_struct(program, index));
+    }
+    for index in 0..program.functions.len() - 1 {
+        let mut candidate = program.clone();
+        candidate.functions.remove(index);
+        candidates.push(candidate);
+    }
+
+    for (function_index, function) in program.functions.iter().enumerate() {
+        // Drop a statement.
+        for index in 0..function.statements.len() {
+            let mut candidate = program.clone();
+            candidate.functions[function_index].statements.remove(index);
+            candidates.push(candidate);
+        }
+
+        // Simplify one expression in a statement or the tail.
+        for (index, statement) in function.statements.iter().enumerate() {
+            let exprs: Vec<&Expr> = match statement {
+                Statement::Let(_, expr) | Statement::Debug(expr) => vec![expr],
+                Statement::Call(_, arguments) => arguments.iter().collect(),
+            };
+            for (argument, expr) in exprs.into_iter().enumerate() {
+                for smaller in shrink_expr(expr) {
+                    let mut candidate = program.clone();
+                    match &mut candidate.functions[function_index].statements[index] {
+                        Statement::Let(_, expr) | Statement::Debug(expr) => *expr = smaller,
+                        Statement::Call(_, arguments) => arguments[argument] = smaller,
+                    }
+                    candidates.push(candidate);
+                }
+            }
+        }
+        if let Some(tail) = &function.tail {
+            for smaller in shrink_expr(tail) {
+                let mut candidate = program.clone();
+                candidate.functions[function_index].tail = Some(smaller);
+                candidates.push(candidate);
+            }
+        }
+    }
+
+    candidates
+}
+
+/// `program` without the struct `index`, unless some declaration still
+/// names it. Aggregates of it are left for the type checker to reject.
+fn remove_struct(program: &Program, index: usize) -> Option<Program> {
+    let mut type_names: Vec<&String> = vec![];
+    for struct_def in &program.structs {
+        type_names.extend(struct_def.fields.iter().map(|(_, ty)| ty));
+    }
+    for function in &program.functions {
+        type_names.extend(function.params.iter().map(|(_, ty)| ty));
+        type_names.extend(function.output.iter());
+    }
+    if type_names.contains(&&program.structs[index].name) {
+        return None;
+    }
+
+    let mut candidate = program.clone();
+    candidate.structs.remove(index);
+    Some(candidate)
+}
+
+/// Smaller expressions of the same type as `expr`.
+fn shrink_expr(expr: &Expr) -> Vec<Expr> {
+    match expr {
+        Expr::Bool(_) | Expr::Uint(_) | Expr::String(_) | Expr::Variable(_) | Expr::Field(..) => {
+            vec![]
+        }
+
+        Expr::Add(left, right) => {
+            let mut result = vec![(**left).clone(), (**right).clone()];
+            result.extend(
+                shrink_expr(left)
+                    .into_iter()
+                    .map(|left| Expr::Add(Box::new(left), right.clone())),
+            );
+            result.extend(
+                shrink_expr(right)
+                    .into_iter()
+                    .map(|right| Expr::Add(left.clone(), Box::new(right))),
+            );
+            result
+        }
+
+        Expr::If(condition, if_true, if_false) => {
+            let mut result = vec![(**if_true).clone(), (**if_false).clone()];
+            result.extend(
+                shrink_expr(condition).into_iter().map(|condition| {
+                    Expr::If(Box::new(condition), if_true.clone(), if_false.clone())
+                }),
+            );
+            result
+        }
+
+        Expr::Call(name, arguments) => shrink_list(arguments, shrink_expr)
+            .into_iter()
+            .map(|arguments| Expr::Call(name.clone(), arguments))
+            .collect(),
+
+        Expr::Aggregate(name, fields) => shrink_list(fields, |(field, value)| {
+            shrink_expr(value)
+                .into_iter()
+                .map(|value| (field.clone(), value))
+                .collect()
+        })
+        .into_iter()
+        .map(|fields| Expr::Aggregate(name.clone(), fields))
+        .collect(),
+    }
+}
+
+/// Copies of `items` with exactly one element shrunk by `shrink`.
+fn shrink_list<T: Clone>(items: &[T], shrink: impl Fn(&T) -> Vec<T>) -> Vec<Vec<T>> {
+    let mut result = vec![];
+    for (index, item) in items.iter().enumerate() {
+        for smaller in shrink(item) {
+            let mut items = items.to_vec();
+            items[index] = smaller;
+            result.push(items);
+        }
+    }
+    result
+}
@@ -0,0 +1,779 @@
+//! Generates random, well-typed Lark programs for differential testing.
+//!
+//! Programs are kept inside the subset of the language that both
+//! `lark-eval` and the Rust backend claim to support: structs with
+//! `bool`/`uint`/`String`/struct fields, free functions, `let`, `if`,
+//! `+`, struct construction, field access on variables and `debug`.
+//! Functions only call functions defined before them, so every program
+//! terminates.
+//!
+//! Values get moved (`let b = a`) and shared (`let b = a.field`, then
+//! `b` used any number of times), since that is where the interpreter,
+//! which ignores permissions, and the Rust backend, which has to get
+//! them right, are most likely to differ.
+//!
+//! Generation happens in two steps. First we pick the declarations --
+//! the structs and the function signatures -- and run them through
+//! `lark-parser`. The bodies are then generated against the `lark-ty`
+//! types the front end gives back for them (`ty`, `signature` and
+//! `members`), so the generator has no idea of types of its own, and a
+//! declaration the grammar no longer accepts makes it panic rather than
+//! quietly produce programs that do not type check.
+//!
+//! Programs are kept as a small syntax tree (rather than as text) so
+//! that the minimizer can shrink them structurally.
+
+use lark_entity::{Entity, EntityData, ItemKind, LangItem, MemberKind};
+use lark_error::WithError;
+use lark_intern::{Intern, Untern};
+use lark_parser::{ParserDatabase, ParserDatabaseExt, LANG_ITEM_NAMES};
+use lark_query_system::LarkDatabase;
+use lark_ty::declaration::Declaration;
+use lark_ty::{BaseData, BaseKind, BoundVarOr, Signature, Ty};
+use std::fmt;
+
+/// The lang items that declarations use as types: the ones we can
+/// write literals of (and `debug`).
+const VALUE_TYPES: &[LangItem] = &[LangItem::Boolean, LangItem::Uint, LangItem::String];
+
+/// The file we give the declarations to `lark-parser` as.
+const DECLARATIONS_FILE: &str = "declarations.lark";
+
+/// A tiny xorshift generator; we want runs to be reproducible from a
+/// seed and don't need anything fancier.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero, so mix the seed first
+        Rng {
+            state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+        }
+    }
+
+    pub fn next(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    /// Random number in `0..bound`.
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next() % (bound as u64)) as usize
+    }
+
+    /// True with a probability of `numerator / denominator`.
+    pub fn chance(&mut self, numerator: usize, denominator: usize) -> bool {
+        self.below(denominator) < numerator
+    }
+}
+
+/// Knobs controlling the size of the generated programs.
+#[derive(Clone, Debug)]
+pub struct GeneratorOptions {
+    pub max_structs: usize,
+    pub max_fields: usize,
+    pub max_functions: usize,
+    pub max_params: usize,
+    pub max_statements: usize,
+    pub max_depth: usize,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        GeneratorOptions {
+            max_structs: 3,
+            max_fields: 3,
+            max_functions: 4,
+            max_params: 3,
+            max_statements: 5,
+            max_depth: 3,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub structs: Vec<StructDef>,
+
+    /// The functions, in definition order; `main` is always the last.
+    pub functions: Vec<FunctionDef>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StructDef {
+    pub name: String,
+
+    /// The name of each field and of its type, as written.
+    pub fields: Vec<(String, String)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FunctionDef {
+    pub name: String,
+
+    /// The name of each parameter and of its type, as written.
+    pub params: Vec<(String, String)>,
+    pub output: Option<String>,
+    pub statements: Vec<Statement>,
+    pub tail: Option<Expr>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Statement {
+    Let(String, Expr),
+    Debug(Expr),
+
+    /// A call to a function without a result.
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Bool(bool),
+    Uint(u32),
+    String(String),
+    Variable(String),
+    Field(String, String),
+    Call(String, Vec<Expr>),
+    Aggregate(String, Vec<(String, Expr)>),
+    Add(Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+/// Is the value being stored or passed on (which, for the type checker,
+/// moves out of a variable) or merely looked at?
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Use {
+    Owned,
+    Inspected,
+}
+
+#[derive(Clone)]
+struct Local {
+    name: String,
+    ty: Ty<Declaration>,
+
+    /// Once a variable has been moved from, we don't touch it again, so
+    /// that the program never trips the initialization analysis.
+    consumed: bool,
+
+    /// For a variable holding a field of another one (`let b = a.field`),
+    /// the index of that other one. We use such a variable as often as
+    /// we like, but only until the variable it came from is moved.
+    shared_from: Option<usize>,
+}
+
+pub struct ProgramGenerator {
+    rng: Rng,
+    options: GeneratorOptions,
+    program: Program,
+
+    /// Holds the declarations of `program`, once they are picked.
+    db: LarkDatabase,
+
+    /// The types of `program.structs` and the signatures of
+    /// `program.functions`, as `lark-parser` sees them.
+    struct_types: Vec<Ty<Declaration>>,
+    signatures: Vec<Signature<Declaration>>,
+
+    /// The body being generated may call the functions before this
+    /// index (those defined before it), so every program terminates.
+    callable: usize,
+
+    next_variable: usize,
+}
+
+impl ProgramGenerator {
+    pub fn new(seed: u64, options: GeneratorOptions) -> Self {
+        ProgramGenerator {
+            rng: Rng::new(seed),
+            options,
+            program: Program {
+                structs: vec![],
+                functions: vec![],
+            },
+            db: LarkDatabase::default(),
+            struct_types: vec![],
+            signatures: vec![],
+            callable: 0,
+            next_variable: 0,
+        }
+    }
+
+    pub fn generate(mut self) -> Program {
+        self.generate_declarations();
+        self.parse_declarations();
+
+        for index in 0..self.program.functions.len() {
+            self.callable = index;
+            let (statements, tail) = self.generate_body(index);
+            let function = &mut self.program.functions[index];
+            function.statements = statements;
+            function.tail = tail;
+        }
+
+        self.program
+    }
+
+    /// Picks the structs and the signatures of the functions, leaving
+    /// the bodies empty for now.
+    fn generate_declarations(&mut self) {
+        let struct_count = self.rng.below(self.options.max_structs + 1);
+        for index in 0..struct_count {
+            let field_count = 1 + self.rng.below(self.options.max_fields);
+            let fields = (0..field_count)
+                .map(|field| (format!("field{}", field), self.random_type_name(index)))
+                .collect();
+            self.program.structs.push(StructDef {
+                name: format!("Struct{}", index),
+                fields,
+            });
+        }
+
+        let function_count = self.rng.below(self.options.max_functions + 1);
+        for index in 0..function_count {
+            let param_count = self.rng.below(self.options.max_params + 1);
+            let params = (0..param_count)
+                .map(|param| {
+                    (
+                        format!("param{}", param),
+                        self.random_type_name(struct_count),
+                    )
+                })
+                .collect();
+            let output = if self.rng.chance(3, 4) {
+                Some(self.random_type_name(struct_count))
+            } else {
+                None
+            };
+            self.program
+                .functions
+                .push(declaration(format!("function{}", index), params, output));
+        }
+
+        self.program
+            .functions
+            .push(declaration("main".to_string(), vec![], None));
+    }
+
+    /// Picks the name of a type for a declaration; structs may only
+    /// refer to structs declared before them (index below
+    /// `struct_limit`), so there are no cycles.
+    fn random_type_name(&mut self, struct_limit: usize) -> String {
+        let choice = self.rng.below(VALUE_TYPES.len() + struct_limit.min(1));
+        match VALUE_TYPES.get(choice) {
+            Some(&lang_item) => lang_item_name(lang_item).to_string(),
+            None => self.program.structs[self.rng.below(struct_limit)]
+                .name
+                .clone(),
+        }
+    }
+
+    /// Runs the declarations through `lark-parser` and records the
+    /// types it gives them. The bodies are still empty, so we only look
+    /// at the declarations and never type check.
+    fn parse_declarations(&mut self) {
+        let source = self.program.to_string();
+        self.db.add_file(DECLARATIONS_FILE, source);
+
+        for index in 0..self.program.structs.len() {
+            let name = self.program.structs[index].name.clone();
+            let entity = self.item(&name);
+            let ty = self.declared(&name, self.db.ty(entity));
+            self.struct_types.push(ty);
+
+            for member in self.db.members(entity).unwrap().iter() {
+                self.declared(&name, self.db.ty(member.entity));
+            }
+        }
+
+        for index in 0..self.program.functions.len() {
+            let name = self.program.functions[index].name.clone();
+            let entity = self.item(&name);
+            let signature = self.declared(&name, self.db.signature(entity)).unwrap();
+            self.signatures.push(signature);
+        }
+    }
+
+    /// The entity of the top-level item `name`.
+    fn item(&self, name: &str) -> Entity {
+        let id = name.intern(&self.db);
+        self.db
+            .top_level_entities_in_file(DECLARATIONS_FILE)
+            .iter()
+            .cloned()
+            .find(|entity| match entity.untern(&self.db) {
+                EntityData::ItemName { id: item_id, .. } => item_id == id,
+                _ => false,
+            })
+            .unwrap_or_else(|| panic!("`{}` is missing from:\n{}", name, self.program))
+    }
+
+    /// The value of `result`, a query about the declaration of `name`,
+    /// which must not have reported any errors.
+    fn declared<T>(&self, name: &str, result: WithError<T>) -> T {
+        assert!(
+            result.errors.is_empty(),
+            "the declaration of `{}` does not parse:\n{}",
+            name,
+            self.program
+        );
+        result.value
+    }
+
+    /// The entity that `ty` names; declared types are never generic.
+    fn named_entity(&self, ty: Ty<Declaration>) -> Entity {
+        match ty.base.untern(&self.db) {
+            BoundVarOr::Known(BaseData {
+                kind: BaseKind::Named(entity),
+                ..
+            }) => entity,
+            base => panic!("unexpected declared type {:?}", base),
+        }
+    }
+
+    /// The lang item that `ty` names, if any.
+    fn lang_item(&self, ty: Ty<Declaration>) -> Option<LangItem> {
+        match self.named_entity(ty).untern(&self.db) {
+            EntityData::LangItem(lang_item) => Some(lang_item),
+            _ => None,
+        }
+    }
+
+    fn lang_item_ty(&self, lang_item: LangItem) -> Ty<Declaration> {
+        let entity = EntityData::LangItem(lang_item).intern(&self.db);
+        self.db.ty(entity).into_value()
+    }
+
+    /// The names and types of the fields of `ty`; empty unless it is a
+    /// struct.
+    fn fields(&self, ty: Ty<Declaration>) -> Vec<(String, Ty<Declaration>)> {
+        let entity = self.named_entity(ty);
+        match entity.untern(&self.db) {
+            EntityData::ItemName {
+                kind: ItemKind::Struct,
+                ..
+            } => self
+                .db
+                .members(entity)
+                .unwrap()
+                .iter()
+                .filter(|member| member.kind == MemberKind::Field)
+                .map(|member| {
+                    let name = member.name.untern(&self.db).to_string();
+                    (name, self.db.ty(member.entity).into_value())
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// The name to use for `ty` in an aggregate expression.
+    fn struct_name(&self, ty: Ty<Declaration>) -> String {
+        match self.named_entity(ty).untern(&self.db) {
+            EntityData::ItemName { id, .. } => id.untern(&self.db).to_string(),
+            data => panic!("not a struct: {:?}", data),
+        }
+    }
+
+    /// True unless `signature` returns `()`.
+    fn has_result(&self, signature: &Signature<Declaration>) -> bool {
+        self.lang_item(signature.output) != Some(LangItem::Tuple(0))
+    }
+
+    /// Picks the type of a new variable.
+    fn random_type(&mut self) -> Ty<Declaration> {
+        let choice = self.rng.below(VALUE_TYPES.len() + self.struct_types.len());
+        match VALUE_TYPES.get(choice) {
+            Some(&lang_item) => self.lang_item_ty(lang_item),
+            None => self.struct_types[choice - VALUE_TYPES.len()],
+        }
+    }
+
+    /// `debug` can print any of the value types.
+    fn printable_type(&mut self) -> Ty<Declaration> {
+        let lang_item = VALUE_TYPES[self.rng.below(VALUE_TYPES.len())];
+        self.lang_item_ty(lang_item)
+    }
+
+    fn generate_body(&mut self, index: usize) -> (Vec<Statement>, Option<Expr>) {
+        let signature = self.signatures[index].clone();
+        let mut locals: Vec<Local> = self.program.functions[index]
+            .params
+            .iter()
+            .zip(signature.inputs.iter())
+            .map(|((name, _), &ty)| Local {
+                name: name.clone(),
+                ty,
+                consumed: false,
+                shared_from: None,
+            })
+            .collect();
+
+        let mut statements = vec![];
+        let statement_count = self.rng.below(self.options.max_statements + 1);
+        for _ in 0..statement_count {
+            let statement = self.generate_statement(&mut locals);
+            statements.push(statement);
+        }
+
+        // Make sure something observable happens.
+        if !statements.iter().any(|s| match s {
+            Statement::Debug(_) => true,
+            _ => false,
+        }) {
+            let uint = self.lang_item_ty(LangItem::Uint);
+            let expr = self.generate_expr(uint, &mut locals, Use::Inspected, 1);
+            statements.push(Statement::Debug(expr));
+        }
+
+        let tail = if self.has_result(&signature) {
+            let depth = self.options.max_depth;
+            Some(self.generate_expr(signature.output, &mut locals, Use::Owned, depth))
+        } else {
+            None
+        };
+
+        (statements, tail)
+    }
+
+    fn generate_statement(&mut self, locals: &mut Vec<Local>) -> Statement {
+        let depth = self.options.max_depth;
+        match self.rng.below(4) {
+            0 => {
+                let ty = self.printable_type();
+                Statement::Debug(self.generate_expr(ty, locals, Use::Inspected, depth))
+            }
+            1 => {
+                let callees: Vec<usize> = (0..self.callable)
+                    .filter(|&i| !self.has_result(&self.signatures[i]))
+                    .collect();
+                if callees.is_empty() {
+                    let ty = self.printable_type();
+                    return Statement::Debug(self.generate_expr(ty, locals, Use::Inspected, depth));
+                }
+                let callee = callees[self.rng.below(callees.len())];
+                let (name, arguments) = self.generate_call(callee, locals, depth);
+                Statement::Call(name, arguments)
+            }
+            2 => {
+                if let Some(statement) = self.generate_move_or_share(locals) {
+                    return statement;
+                }
+                self.generate_let(locals, depth)
+            }
+            _ => self.generate_let(locals, depth),
+        }
+    }
+
+    fn generate_let(&mut self, locals: &mut Vec<Local>, depth: usize) -> Statement {
+        let ty = self.random_type();
+        let initializer = self.generate_expr(ty, locals, Use::Owned, depth);
+        self.bind(locals, ty, None, initializer)
+    }
+
+    /// Either moves a variable into a new one (`let b = a`), or shares
+    /// a field of a struct in a new one (`let b = a.field`), leaving
+    /// the struct to be used (or moved) later. `None` if there is
+    /// nothing to move.
+    fn generate_move_or_share(&mut self, locals: &mut Vec<Local>) -> Option<Statement> {
+        let candidates: Vec<usize> = (0..locals.len())
+            .filter(|&i| !locals[i].consumed && locals[i].shared_from.is_none())
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let local = candidates[self.rng.below(candidates.len())];
+        let ty = locals[local].ty;
+        let name = locals[local].name.clone();
+
+        let fields = self.fields(ty);
+        if !fields.is_empty() && self.rng.chance(2, 3) {
+            let (field, field_ty) = fields[self.rng.below(fields.len())].clone();
+            Some(self.bind(locals, field_ty, Some(local), Expr::Field(name, field)))
+        } else {
+            consume(locals, local);
+            Some(self.bind(locals, ty, None, Expr::Variable(name)))
+        }
+    }
+
+    /// `let varN = initializer`, making `varN` available to later
+    /// statements.
+    fn bind(
+        &mut self,
+        locals: &mut Vec<Local>,
+        ty: Ty<Declaration>,
+        shared_from: Option<usize>,
+        initializer: Expr,
+    ) -> Statement {
+        let name = format!("var{}", self.next_variable);
+        self.next_variable += 1;
+        locals.push(Local {
+            name: name.clone(),
+            ty,
+            consumed: false,
+            shared_from,
+        });
+        Statement::Let(name, initializer)
+    }
+
+    fn generate_call(
+        &mut self,
+        callee: usize,
+        locals: &mut Vec<Local>,
+        depth: usize,
+    ) -> (String, Vec<Expr>) {
+        let name = self.program.functions[callee].name.clone();
+        let inputs = self.signatures[callee].inputs.clone();
+        let arguments = inputs
+            .iter()
+            .map(|&ty| self.generate_expr(ty, locals, Use::Owned, depth.saturating_sub(1)))
+            .collect();
+        (name, arguments)
+    }
+
+    fn generate_expr(
+        &mut self,
+        ty: Ty<Declaration>,
+        locals: &mut Vec<Local>,
+        value_use: Use,
+        depth: usize,
+    ) -> Expr {
+        // Collect the ways we could produce a value of type `ty`.
+        let variables: Vec<usize> = (0..locals.len())
+            .filter(|&i| !locals[i].consumed && locals[i].ty == ty)
+            .collect();
+        let fields: Vec<(usize, String)> = (0..locals.len())
+            .filter(|&i| !locals[i].consumed)
+            .flat_map(|i| {
+                self.fields(locals[i].ty)
+                    .into_iter()
+                    .filter(|(_, field_ty)| *field_ty == ty)
+                    .map(move |(name, _)| (i, name))
+            })
+            .collect();
+        let callees: Vec<usize> = (0..self.callable)
+            .filter(|&i| self.signatures[i].output == ty)
+            .collect();
+
+        let choice = self.rng.below(6);
+
+        if choice == 0 && !variables.is_empty() {
+            let local = variables[self.rng.below(variables.len())];
+            if value_use == Use::Owned && locals[local].shared_from.is_none() {
+                consume(locals, local);
+            }
+            return Expr::Variable(locals[local].name.clone());
+        }
+
+        if choice == 1 && !fields.is_empty() {
+            let (local, field) = fields[self.rng.below(fields.len())].clone();
+            if value_use == Use::Owned && locals[local].shared_from.is_none() {
+                consume(locals, local);
+            }
+            return Expr::Field(locals[local].name.clone(), field);
+        }
+
+        if choice == 2 && !callees.is_empty() && depth > 0 {
+            let callee = callees[self.rng.below(callees.len())];
+            let (name, arguments) = self.generate_call(callee, locals, depth);
+            return Expr::Call(name, arguments);
+        }
+
+        if choice == 3 && depth > 0 {
+            let boolean = self.lang_item_ty(LangItem::Boolean);
+            let condition = self.generate_expr(boolean, locals, Use::Inspected, depth - 1);
+
+            // Only one branch runs, but we can't know which, so anything
+            // consumed by either one is gone afterwards.
+            let mut false_locals = locals.clone();
+            let if_true = self.generate_expr(ty, locals, value_use, depth - 1);
+            let if_false = self.generate_expr(ty, &mut false_locals, value_use, depth - 1);
+            for (local, false_local) in locals.iter_mut().zip(false_locals) {
+                local.consumed |= false_local.consumed;
+            }
+
+            return Expr::If(Box::new(condition), Box::new(if_true), Box::new(if_false));
+        }
+
+        if choice == 4 && self.lang_item(ty) == Some(LangItem::Uint) && depth > 0 {
+            let left = self.generate_expr(ty, locals, Use::Inspected, depth - 1);
+            let right = self.generate_expr(ty, locals, Use::Inspected, depth - 1);
+            return Expr::Add(Box::new(left), Box::new(right));
+        }
+
+        self.generate_literal(ty, locals, depth)
+    }
+
+    fn generate_literal(
+        &mut self,
+        ty: Ty<Declaration>,
+        locals: &mut Vec<Local>,
+        depth: usize,
+    ) -> Expr {
+        match self.lang_item(ty) {
+            Some(LangItem::Boolean) => Expr::Bool(self.rng.chance(1, 2)),
+
+            // Keep numbers small so sums never overflow.
+            Some(LangItem::Uint) => Expr::Uint(self.rng.below(100) as u32),
+
+            Some(LangItem::String) => Expr::String(format!("s{}", self.rng.below(1000))),
+
+            Some(lang_item) => panic!("no literals of `{:?}`", lang_item),
+
+            None => {
+                let name = self.struct_name(ty);
+                let fields = self
+                    .fields(ty)
+                    .into_iter()
+                    .map(|(field, field_ty)| {
+                        let value = self.generate_expr(
+                            field_ty,
+                            locals,
+                            Use::Owned,
+                            depth.saturating_sub(1),
+                        );
+                        (field, value)
+                    })
+                    .collect();
+                Expr::Aggregate(name, fields)
+            }
+        }
+    }
+}
+
+/// A function with an empty body, to be filled in once the
+/// declarations have been parsed.
+fn declaration(name: String, params: Vec<(String, String)>, output: Option<String>) -> FunctionDef {
+    FunctionDef {
+        name,
+        params,
+        output,
+        statements: vec![],
+        tail: None,
+    }
+}
+
+/// How `lang_item` is written in Lark source.
+fn lang_item_name(lang_item: LangItem) -> &'static str {
+    LANG_ITEM_NAMES
+        .iter()
+        .find(|&&(_, item)| item == lang_item)
+        .map(|&(name, _)| name)
+        .unwrap_or_else(|| panic!("`{:?}` has no name", lang_item))
+}
+
+/// Marks `local` as moved from, along with the variables sharing parts
+/// of it.
+fn consume(locals: &mut [Local], local: usize) {
+    locals[local].consumed = true;
+    for other in locals.iter_mut() {
+        if other.shared_from == Some(local) {
+            other.consumed = true;
+        }
+    }
+}
+
+/// Generates the program for `seed` with the default options.
+pub fn generate_program(seed: u64) -> Program {
+    ProgramGenerator::new(seed, GeneratorOptions::default()).generate()
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for struct_def in &self.structs {
+            writeln!(fmt, "struct {} {{", struct_def.name)?;
+            for (name, ty) in &struct_def.fields {
+                writeln!(fmt, "    {}: {},", name, ty)?;
+            }
+            writeln!(fmt, "}}")?;
+            writeln!(fmt)?;
+        }
+
+        for (index, function) in self.functions.iter().enumerate() {
+            if index > 0 {
+                writeln!(fmt)?;
+            }
+
+            write!(fmt, "def {}(", function.name)?;
+            for (index, (name, ty)) in function.params.iter().enumerate() {
+                if index > 0 {
+                    write!(fmt, ", ")?;
+                }
+                write!(fmt, "{}: {}", name, ty)?;
+            }
+            write!(fmt, ")")?;
+            if let Some(output) = &function.output {
+                write!(fmt, " -> {}", output)?;
+            }
+            writeln!(fmt, " {{")?;
+
+            for statement in &function.statements {
+                writeln!(fmt, "    {}", statement)?;
+            }
+            if let Some(tail) = &function.tail {
+                writeln!(fmt, "    {}", tail)?;
+            }
+            writeln!(fmt, "}}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Let(name, initializer) => write!(fmt, "let {} = {}", name, initializer),
+            Statement::Debug(expr) => write!(fmt, "debug({})", expr),
+            Statement::Call(name, arguments) => write_call(fmt, name, arguments),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Bool(value) => write!(fmt, "{}", value),
+            Expr::Uint(value) => write!(fmt, "{}", value),
+            Expr::String(value) => write!(fmt, "\"{}\"", value),
+            Expr::Variable(name) => write!(fmt, "{}", name),
+            Expr::Field(owner, name) => write!(fmt, "{}.{}", owner, name),
+            Expr::Call(name, arguments) => write_call(fmt, name, arguments),
+            Expr::Aggregate(name, fields) => {
+                write!(fmt, "{}(", name)?;
+                for (index, (field, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(fmt, ", ")?;
+                    }
+                    write!(fmt, "{}: {}", field, value)?;
+                }
+                write!(fmt, ")")
+            }
+            Expr::Add(left, right) => write!(fmt, "({} + {})", left, right),
+            Expr::If(condition, if_true, if_false) => write!(
+                fmt,
+                "if ({}) {{ {} }} else {{ {} }}",
+                condition, if_true, if_false
+            ),
+        }
+    }
+}
+
+fn write_call(fmt: &mut fmt::Formatter<'_>, name: &str, arguments: &[Expr]) -> fmt::Result {
+    write!(fmt, "{}(", name)?;
+    for (index, argument) in arguments.iter().enumerate() {
+        if index > 0 {
+            write!(fmt, ", ")?;
+        }
+        write!(fmt, "{}", argument)?;
+    }
+    write!(fmt, ")")
+}
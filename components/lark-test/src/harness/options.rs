@@ -1,3 +1,4 @@
+use languageserver_types::Position;
 use regex::Regex;
 use std::path::Path;
 
@@ -13,6 +14,14 @@ crate struct TestOptions {
     // Checked by code in `test::ls_test`.
     crate expected_hovers: Vec<ExpectedHover>,
 
+    // `//~ DEFINITION`, `//~ REFERENCES`, `//~ COMPLETION` and
+    // `//~ RENAME` annotations, likewise at the opening `/`.
+    // Checked by code in `test::ls_test`.
+    crate expected_definitions: Vec<ExpectedDefinition>,
+    crate expected_references: Vec<ExpectedReferences>,
+    crate expected_completions: Vec<ExpectedCompletion>,
+    crate expected_renames: Vec<ExpectedRename>,
+
     // Execution mode: do we run this code and -- if so -- how?
     //
     // Default: if there are errors, no. Otherwise, mode must be explicitly specified.
@@ -49,6 +58,33 @@ crate struct ExpectedHover {
     crate message: Regex,
 }
 
+#[derive(Clone, Debug)]
+crate struct ExpectedDefinition {
+    crate position: Position,
+    crate definition: Position,
+}
+
+#[derive(Clone, Debug)]
+crate struct ExpectedReferences {
+    crate position: Position,
+    crate references: Vec<Position>,
+}
+
+#[derive(Clone, Debug)]
+crate struct ExpectedCompletion {
+    crate position: Position,
+    crate label: String,
+}
+
+#[derive(Clone, Debug)]
+crate struct ExpectedRename {
+    crate position: Position,
+    crate new_name: String,
+
+    /// The file, next to the test file, with the renamed text.
+    crate expected_file: String,
+}
+
 lazy_static::lazy_static! {
     static ref WITH_OPTION: Regex = Regex::new(r"^(\s*)//~ ([a-zA-Z_]+):(.*)").unwrap();
     static ref NO_OPTION: Regex = Regex::new(r"^(\s*)//~ ([a-zA-Z_]+)\s*$").unwrap();
@@ -147,6 +183,71 @@ impl TestOptions {
                 },
             },
 
+            // `//~ DEFINITION: 3:5` says that the definition of what is
+            // at the `/` starts at line 3, column 5 (counting from 1)
+            "DEFINITION" => {
+                let position = annotation_position(prefix, last_non_comment_line)?;
+                let definition = parse_line_col(value)?;
+                self.expected_definitions.push(ExpectedDefinition {
+                    position,
+                    definition,
+                });
+                Ok(())
+            }
+
+            // `//~ REFERENCES: 3:5, 7:9` lists where all the references
+            // to what is at the `/` start, including the definition
+            "REFERENCES" => {
+                let position = annotation_position(prefix, last_non_comment_line)?;
+                let references = value
+                    .split(',')
+                    .map(parse_line_col)
+                    .collect::<Result<_, _>>()?;
+                self.expected_references.push(ExpectedReferences {
+                    position,
+                    references,
+                });
+                Ok(())
+            }
+
+            // `//~ COMPLETION: foo` says that `foo` is one of the
+            // completions offered at the `/`
+            "COMPLETION" => {
+                let position = annotation_position(prefix, last_non_comment_line)?;
+                if value.is_empty() {
+                    return Err("COMPLETION requires the expected label".to_string());
+                }
+                self.expected_completions.push(ExpectedCompletion {
+                    position,
+                    label: value.to_string(),
+                });
+                Ok(())
+            }
+
+            // `//~ RENAME: bar => foo.renamed` renames what is at the `/`
+            // to `bar`, and compares the result against `foo.renamed`
+            "RENAME" => {
+                let position = annotation_position(prefix, last_non_comment_line)?;
+                let mut parts = value.splitn(2, "=>").map(|part| part.trim());
+                match (parts.next(), parts.next()) {
+                    (Some(new_name), Some(expected_file))
+                        if !new_name.is_empty() && !expected_file.is_empty() =>
+                    {
+                        if expected_file.ends_with(".lark") {
+                            let error = "the expected file of a rename would be run as a test";
+                            return Err(error.to_string());
+                        }
+                        self.expected_renames.push(ExpectedRename {
+                            position,
+                            new_name: new_name.to_string(),
+                            expected_file: expected_file.to_string(),
+                        });
+                        Ok(())
+                    }
+                    _ => Err("RENAME requires `new_name => expected_file`".to_string()),
+                }
+            }
+
             "ERROR" => match last_non_comment_line {
                 None => Err("cannot find line that error applies to".to_string()),
                 Some(line_num) => match Regex::new(value.trim()) {
@@ -163,3 +264,28 @@ impl TestOptions {
         }
     }
 }
+
+/// The position of an annotation: the column of its opening `/` on the
+/// last line that wasn't a comment.
+fn annotation_position(
+    prefix: &str,
+    last_non_comment_line: Option<u64>,
+) -> Result<Position, String> {
+    match last_non_comment_line {
+        None => Err("cannot find line that annotation applies to".to_string()),
+        Some(line_num) => Ok(Position::new(line_num, prefix.len() as u64)),
+    }
+}
+
+/// Parses a `line:col` pair, both counting from 1 (like the positions
+/// in error messages).
+fn parse_line_col(text: &str) -> Result<Position, String> {
+    let text = text.trim();
+    let mut parts = text.splitn(2, ':').map(|part| part.trim().parse::<u64>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(line)), Some(Ok(col))) if line > 0 && col > 0 => {
+            Ok(Position::new(line - 1, col - 1))
+        }
+        _ => Err(format!("expected `line:col`, found `{}`", text)),
+    }
+}
@@ -12,3 +12,14 @@ fn no_error_no_mode() {
         false,
     );
 }
+
+#[test]
+#[should_panic(expected = "assertion failed: failures.is_empty()")]
+fn wrong_definition() {
+    run_test_harness(
+        "wrong_definition.lark",
+        "self_tests/wrong_definition.lark",
+        false,
+        false,
+    );
+}
@@ -7,7 +7,11 @@ impl TestContext<'_> {
     crate fn build_and_run_executable(&self) {
         let exe_path = self.executable_path();
         self.db
-            .build(exe_path.to_str().unwrap())
+            .build(
+                exe_path.to_str().unwrap(),
+                &Default::default(),
+                &Default::default(),
+            )
             .unwrap_or_else(|Cancelled| panic!("cancelled"));
 
         let cmd = Command::new(exe_path)
@@ -21,7 +25,7 @@ impl TestContext<'_> {
     crate fn run_eval(&self) {
         let mut handler = lark_eval::IOHandler::new(true);
         lark_eval::eval(&self.db, &mut handler);
-        let lark_eval::IOHandler { redirect: output } = handler;
+        let lark_eval::IOHandler { redirect: output, .. } = handler;
         let output = output.unwrap();
         self.compare_reference_contents("output", output.as_bytes(), false);
     }
@@ -1,207 +1,170 @@
 use crate::harness::test::TestContext;
-use languageserver_types::{
-    ClientCapabilities, DidOpenTextDocumentParams, Hover, HoverContents, InitializeParams,
-    InitializeResult, MarkedString, Position, PublishDiagnosticsParams, TextDocumentIdentifier,
-    TextDocumentItem, TextDocumentPositionParams,
-};
-use lark_language_server::{JsonRPCNotification, JsonRPCResponse, LSPCommand};
-use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
-use std::panic;
-use std::process::{Command, Stdio};
+use languageserver_types::{Position, Range};
+use lark_parser::ParserDatabase;
+use lark_query_system::ls_ops::{Cancelable, Cancelled, LsDatabase};
+use lark_span::IntoFileName;
 
 impl TestContext<'_> {
-    crate fn test_language_server(&self) -> Result<(), Box<std::error::Error>> {
-        if self.options.expected_hovers.is_empty() {
-            return Ok(());
+    /// Checks the `//~ HOVER`, `//~ DEFINITION`, `//~ REFERENCES`,
+    /// `//~ COMPLETION` and `//~ RENAME` annotations by asking the
+    /// database what the language server would answer.
+    crate fn test_language_server(&self) {
+        let mut failures = vec![];
+
+        self.check_language_server(&mut failures)
+            .unwrap_or_else(|Cancelled| panic!("encountered cancellation in unit test"));
+
+        if !failures.is_empty() {
+            eprintln!("# Unexpected language server answers");
+            for failure in &failures {
+                eprintln!("{}", failure);
+            }
         }
 
-        let mut child_session = ChildSession::spawn();
-
-        // Child that we are initialized
-        child_session.send_init(101)?;
-
-        let result = child_session.receive::<JsonRPCResponse<InitializeResult>>()?;
-
-        assert_eq!(result.id, 101);
-
-        // Open the document
-        let test_path: &str = self.test_path.to_str().unwrap();
-        child_session.send_open(test_path)?;
-
-        let result = child_session.receive::<JsonRPCNotification<PublishDiagnosticsParams>>()?;
+        assert!(failures.is_empty());
+    }
 
-        assert_eq!(result.method, "textDocument/publishDiagnostics");
-        assert_eq!(result.params.diagnostics.len(), 0);
+    fn check_language_server(&self, failures: &mut Vec<String>) -> Cancelable<()> {
+        let file = self.test_name.as_str();
 
-        // Hover to get the type
         for hover in &self.options.expected_hovers {
-            child_session.send_hover(900, test_path, hover.line_num, hover.character_num)?;
-
-            let result = child_session.receive::<JsonRPCResponse<Hover>>()?;
-            assert_eq!(result.id, 900);
-            match result.result.contents {
-                HoverContents::Scalar(MarkedString::String(s)) => {
-                    if !hover.message.is_match(&s) {
-                        eprintln!(
-                            "{}:{}:{}: unexpected hover message: `{}`",
-                            self.test_path.display(),
-                            hover.line_num + 1,
-                            hover.character_num + 1,
-                            s,
-                        );
-
-                        panic!("unexpected hover: {}", s);
+            let position = Position::new(hover.line_num, hover.character_num);
+            match self.db.hover_text_at_position(file, position)? {
+                Some((_, markdown)) => {
+                    if !hover.message.is_match(&markdown) {
+                        failures.push(self.failure(position, format!("hover was `{}`", markdown)));
                     }
                 }
-                x => panic!("Unexpected string type: {:?}", x),
+                None => failures.push(self.failure(position, "no hover".to_string())),
             }
         }
 
-        Ok(())
-    }
-}
-
-struct ChildSession {
-    child: std::process::Child,
-}
-
-impl Drop for ChildSession {
-    fn drop(&mut self) {
-        let _ = self.child.kill();
-    }
-}
-
-impl ChildSession {
-    fn spawn() -> ChildSession {
-        // LS server hardcodes stdin/stdout, so we need a distinct
-        // process; use `cargo run ide` to get one.
-        let child = Command::new("cargo")
-            .arg("run")
-            .arg("--")
-            .arg("ide")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("Failed to spawn child process");
-
-        ChildSession { child }
-    }
-    /// Helper function to do the work of sending a result back to the IDE
-    fn send<T: Serialize>(&mut self, msg: T) -> Result<(), Box<std::error::Error>> {
-        let child_stdin = self.child.stdin.as_mut().ok_or(std::io::Error::new(
-            std::io::ErrorKind::BrokenPipe,
-            "can connect to child stdin",
-        ))?;
-
-        let msg_raw = serde_json::to_string(&msg)?;
-
-        child_stdin
-            .write_all(format!("Content-Length: {}\r\n\r\n", msg_raw.len()).as_bytes())
-            .expect("Failed to write to stdin");
-        child_stdin
-            .write_all(msg_raw.as_bytes())
-            .expect("Failed to write to stdin");
-        //let _ = io::stdout().flush();
+        for definition in &self.options.expected_definitions {
+            let position = definition.position;
+            match self.db.definition_range_at_position(file, position, true)? {
+                Some((file_name, range)) => {
+                    if file_name != file || range.start != definition.definition {
+                        failures.push(self.failure(
+                            position,
+                            format!("definition was at {}", self.describe(&file_name, range)),
+                        ));
+                    }
+                }
+                None => failures.push(self.failure(position, "no definition".to_string())),
+            }
+        }
 
-        Ok(())
-    }
+        for references in &self.options.expected_references {
+            let position = references.position;
+            let mut actual: Vec<_> = self
+                .db
+                .find_all_references_at_position(file, position)?
+                .into_iter()
+                .map(|(file_name, range)| self.describe(&file_name, range))
+                .collect();
+            actual.sort();
+            actual.dedup();
+
+            let mut expected: Vec<_> = references
+                .references
+                .iter()
+                .map(|&start| self.describe(file, Range::new(start, start)))
+                .collect();
+            expected.sort();
+
+            if actual != expected {
+                failures.push(
+                    self.failure(position, format!("references were [{}]", actual.join(", "))),
+                );
+            }
+        }
 
-    fn receive<T: for<'de> Deserialize<'de>>(&mut self) -> Result<T, Box<std::error::Error>> {
-        let child_stdout = self.child.stdout.as_mut().ok_or(std::io::Error::new(
-            std::io::ErrorKind::BrokenPipe,
-            "can connect to child stdout",
-        ))?;
-
-        let mut buffer = [0; 16];
-        child_stdout.read(&mut buffer[..])?;
-
-        let mut digits = String::new();
-        let mut digit = [0; 1];
-        loop {
-            child_stdout.read(&mut digit[..])?;
-            let char_digit = digit[0] as char;
-
-            if char_digit.is_digit(10) {
-                digits.push(char_digit);
-            } else {
-                let mut whitespace = [0; 3];
-                child_stdout.read(&mut whitespace[..])?;
-                break;
+        for completion in &self.options.expected_completions {
+            let position = completion.position;
+            let labels: Vec<_> = self
+                .db
+                .completions_at_position(file, position)?
+                .into_iter()
+                .map(|(label, ..)| label)
+                .collect();
+
+            if !labels.contains(&completion.label) {
+                failures.push(self.failure(
+                    position,
+                    format!(
+                        "no completion `{}` among [{}]",
+                        completion.label,
+                        labels.join(", ")
+                    ),
+                ));
             }
         }
-        let num_bytes: usize = digits.trim().parse()?;
-        let mut buffer = vec![0u8; num_bytes];
-        let _ = child_stdout.read_exact(&mut buffer);
 
-        let buffer_string = String::from_utf8(buffer)?;
+        for rename in &self.options.expected_renames {
+            let position = rename.position;
+            let edits =
+                match self
+                    .db
+                    .rename_all_references_at_position(file, position, &rename.new_name)?
+                {
+                    Ok(edits) => edits,
+                    Err(error) => {
+                        failures.push(self.failure(position, format!("rename failed: {}", error)));
+                        continue;
+                    }
+                };
+
+            let file_name = file.into_file_name(&self.db);
+            let mut edits: Vec<_> = edits
+                .into_iter()
+                .filter(|(edit_file, ..)| edit_file == file)
+                .map(|(_, range, new_text)| {
+                    let start =
+                        self.db
+                            .byte_index(file_name, range.start.line, range.start.character);
+                    let end = self
+                        .db
+                        .byte_index(file_name, range.end.line, range.end.character);
+                    (start.to_usize()..end.to_usize(), new_text)
+                })
+                .collect();
+
+            // Apply the edits from the end, so that the earlier offsets
+            // stay valid.
+            edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+            edits.dedup_by_key(|(range, _)| range.start);
+            let mut text = self.db.file_text(file_name).to_string();
+            for (range, new_text) in edits {
+                text.replace_range(range, &new_text);
+            }
 
-        let response: T = serde_json::from_str(&buffer_string)?;
-        Ok(response)
-    }
+            let expected_path = self.test_path.with_file_name(&rename.expected_file);
+            self.compare_contents(&expected_path, text.as_bytes(), false);
+        }
 
-    fn send_init(&mut self, id: usize) -> Result<(), Box<std::error::Error>> {
-        self.send(LSPCommand::initialize {
-            id,
-            params: InitializeParams {
-                process_id: None,
-                root_path: None,
-                root_uri: None,
-                initialization_options: None,
-                capabilities: ClientCapabilities {
-                    experimental: None,
-                    text_document: None,
-                    workspace: None,
-                },
-                trace: None,
-                workspace_folders: None,
-            },
-        })
+        Ok(())
     }
 
-    fn send_open(&mut self, filepath: &str) -> Result<(), Box<std::error::Error>> {
-        let contents = std::fs::read_to_string(filepath)?;
-        let path = std::path::Path::new(filepath).canonicalize()?;
-        self.send(LSPCommand::didOpen {
-            params: DidOpenTextDocumentParams {
-                text_document: TextDocumentItem {
-                    uri: url::Url::parse(&format!(
-                        "file:///{}",
-                        path.to_str().ok_or(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            "Bad filepath"
-                        ))?
-                    ))?,
-                    language_id: "lark".into(),
-                    version: 1,
-                    text: contents,
-                },
-            },
-        })
+    /// A failure at `position` in the test file, in the same format as
+    /// the positions of errors.
+    fn failure(&self, position: Position, message: String) -> String {
+        format!(
+            "{}:{}:{}: {}",
+            self.test_path.display(),
+            position.line + 1,
+            position.character + 1,
+            message,
+        )
     }
 
-    fn send_hover(
-        &mut self,
-        id: usize,
-        filepath: &str,
-        line: u64,
-        character: u64,
-    ) -> Result<(), Box<std::error::Error>> {
-        let path = std::path::Path::new(filepath).canonicalize()?;
-        self.send(LSPCommand::hover {
-            id,
-            params: TextDocumentPositionParams {
-                text_document: TextDocumentIdentifier {
-                    uri: url::Url::parse(&format!(
-                        "file:///{}",
-                        path.to_str().ok_or(std::io::Error::new(
-                            std::io::ErrorKind::InvalidData,
-                            "Bad filepath"
-                        ))?
-                    ))?,
-                },
-                position: Position { line, character },
-            },
-        })
+    /// Describes where `range` starts, as `line:col` if it is in the
+    /// test file.
+    fn describe(&self, file_name: &str, range: Range) -> String {
+        let line_col = format!("{}:{}", range.start.line + 1, range.start.character + 1);
+        if file_name == self.test_name {
+            line_col
+        } else {
+            format!("{}:{}", file_name, line_col)
+        }
     }
 }
@@ -49,6 +49,18 @@ impl TestContext<'_> {
         extension: &str,
         actual_bytes: &[u8],
         normalize_paths: bool,
+    ) {
+        let reference_path = self.reference_path(extension);
+        self.compare_contents(&reference_path, actual_bytes, normalize_paths);
+    }
+
+    /// Compares `actual_bytes` against the contents of the file at
+    /// `reference_path` (or, in bless mode, writes them there).
+    crate fn compare_contents(
+        &self,
+        reference_path: &Path,
+        actual_bytes: &[u8],
+        normalize_paths: bool,
     ) {
         let mut actual_str = match std::str::from_utf8(actual_bytes) {
             Ok(s) => s.to_string(),
@@ -60,8 +72,6 @@ impl TestContext<'_> {
         }
         actual_str = actual_str.replace("\r\n", "\n");
 
-        let reference_path = self.reference_path(extension);
-
         if self.bless_mode {
             if !actual_bytes.is_empty() {
                 match fs::write(&reference_path, actual_bytes) {
@@ -76,7 +86,7 @@ impl TestContext<'_> {
             }
         }
 
-        let reference_contents = self.file_contents(&reference_path);
+        let reference_contents = self.file_contents(reference_path);
 
         let mut reference_str = reference_contents.unwrap_or(String::new());
         reference_str = reference_str.replace("\r\n", "\n");
@@ -0,0 +1,62 @@
+//! Setup shared by the tests of the language server queries in the
+//! top-level `tests` directory. Each of them opens a single file,
+//! `input.lark`, and asks about positions in it.
+
+use crate::db_with_test;
+use languageserver_types::{Position, Range};
+use lark_actor::{Actor, QueryRequest};
+use lark_query_system::ls_ops::{Cancelable, Cancelled};
+use lark_query_system::{LarkDatabase, QuerySystem};
+use std::collections::VecDeque;
+use url::Url;
+
+/// The name of the file the tests open.
+pub const INPUT: &str = "input.lark";
+
+/// A struct with a field and a method, a function, and a `main` that
+/// calls both: enough for most queries to find something.
+pub const SOURCE: &str = "struct Foo {
+    bar: bool,
+    baz(x: uint, y: uint) -> uint {
+        x + y
+    }
+}
+
+def add(a: uint, b: uint) -> uint {
+    a + b
+}
+
+def main() {
+    let foo = Foo(bar: true)
+    debug(add(1, foo.baz(2, 3)))
+}
+";
+
+/// A database holding `text` as `INPUT`.
+pub fn input_db(text: &str) -> LarkDatabase {
+    db_with_test(INPUT, text)
+}
+
+/// The URL an IDE would use for `INPUT`.
+pub fn input_url() -> Url {
+    Url::parse("file:///input.lark").unwrap()
+}
+
+/// The result of a query; nothing cancels queries in tests.
+pub fn uncancelled<T>(result: Cancelable<T>) -> T {
+    result.unwrap_or_else(|Cancelled| panic!("cancelled"))
+}
+
+/// The range from `start` to `end` on `line`.
+pub fn range(line: u64, start: u64, end: u64) -> Range {
+    Range::new(Position::new(line, start), Position::new(line, end))
+}
+
+/// Hands `message` to `query_system`, along with the messages it queues
+/// while handling it, until there are none left.
+pub fn send(query_system: &mut QuerySystem, message: QueryRequest) {
+    let mut messages: VecDeque<_> = Some(message).into_iter().collect();
+    while !messages.is_empty() {
+        query_system.receive_messages(&mut messages);
+    }
+}
@@ -11,7 +11,9 @@ use lark_string::Text;
 use salsa::Database;
 use std::fmt::Debug;
 
+pub mod differential;
 mod harness;
+pub mod language_server;
 pub use harness::run_test_harness;
 pub use harness::search_files;
 pub use harness::TestPath;
@@ -0,0 +1,89 @@
+//! The call graph: which functions and methods each body calls, and,
+//! turned around, which bodies call each function or method.
+
+use crate::TypeCheckDatabase;
+use lark_collections::{FxIndexMap, Seq};
+use lark_entity::{Entity, EntityData, MemberKind};
+use lark_hir as hir;
+use lark_intern::{Intern, Untern};
+use lark_ty::BaseKind;
+use std::sync::Arc;
+
+/// A call, in the body of `caller`, to the function or method `callee`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Call {
+    pub caller: Entity,
+    pub callee: Entity,
+
+    /// The call expression, in the body of `caller`.
+    pub expression: hir::Expression,
+}
+
+crate fn outgoing_calls(db: &impl TypeCheckDatabase, caller: Entity) -> Seq<Call> {
+    if !caller.untern(db).has_fn_body() {
+        return Seq::default();
+    }
+
+    let fn_body = db.fn_body(caller).into_value();
+    let results = db.full_type_check(caller).into_value();
+
+    let mut calls = vec![];
+    for expression in fn_body.tables.expressions.indices() {
+        let callee = match fn_body[expression] {
+            // `foo(...)`, where `foo` names a function
+            hir::ExpressionData::Call { function, .. } => match fn_body[function] {
+                hir::ExpressionData::Place { place } => match fn_body[place] {
+                    hir::PlaceData::Entity(entity) => Some(entity),
+                    _ => None,
+                },
+                _ => None,
+            },
+
+            // `receiver.foo(...)`: look for the method `foo` on the
+            // type of `receiver`
+            hir::ExpressionData::MethodCall { method, arguments } => {
+                let receiver = arguments.first(&fn_body).unwrap();
+                match results.opt_ty(receiver).map(|ty| ty.base.untern(db).kind) {
+                    Some(BaseKind::Named(owner)) => {
+                        db.member_entity(owner, MemberKind::Method, fn_body[method].text)
+                    }
+                    _ => None,
+                }
+            }
+
+            _ => None,
+        };
+
+        // Calls to built-in functions, or to structs (which are
+        // constructed rather than called), are not part of the graph.
+        if let Some(callee) = callee.filter(|callee| callee.untern(db).has_fn_body()) {
+            calls.push(Call {
+                caller,
+                callee,
+                expression,
+            });
+        }
+    }
+
+    Seq::from(calls)
+}
+
+crate fn incoming_calls(db: &impl TypeCheckDatabase) -> Arc<FxIndexMap<Entity, Seq<Call>>> {
+    let mut incoming: FxIndexMap<Entity, Vec<Call>> = FxIndexMap::default();
+
+    for &file in db.file_names().iter() {
+        let file_entity = EntityData::InputFile { file }.intern(db);
+        for &entity in db.descendant_entities(file_entity).iter() {
+            for &call in db.outgoing_calls(entity).iter() {
+                incoming.entry(call.callee).or_insert_with(Vec::new).push(call);
+            }
+        }
+    }
+
+    Arc::new(
+        incoming
+            .into_iter()
+            .map(|(callee, calls)| (callee, Seq::from(calls)))
+            .collect(),
+    )
+}
@@ -0,0 +1,51 @@
+//! Collects the errors reported while parsing and checking a file.
+
+use crate::TypeCheckDatabase;
+use lark_collections::Seq;
+use lark_entity::{EntityData, ItemKind, MemberKind};
+use lark_error::Diagnostic;
+use lark_intern::{Intern, Untern};
+use lark_span::FileName;
+
+crate fn file_diagnostics(db: &impl TypeCheckDatabase, file: FileName) -> Seq<Diagnostic> {
+    // Check file for syntax errors
+    let mut errors = vec![];
+    let _ = db.parsed_file(file).accumulate_errors_into(&mut errors);
+
+    // Next, check entities in file for type-safety
+    let file_entity = EntityData::InputFile { file }.intern(db);
+    for &entity in db.descendant_entities(file_entity).iter() {
+        match entity.untern(db) {
+            EntityData::InputFile { .. } | EntityData::LangItem(_) | EntityData::Error(_) => {}
+
+            EntityData::ItemName {
+                kind: ItemKind::Struct,
+                ..
+            }
+            | EntityData::MemberName {
+                kind: MemberKind::Field,
+                ..
+            } => {
+                let _ = db.generic_declarations(entity).accumulate_errors_into(&mut errors);
+                let _ = db.ty(entity).accumulate_errors_into(&mut errors);
+            }
+
+            EntityData::ItemName {
+                kind: ItemKind::Function,
+                ..
+            }
+            | EntityData::MemberName {
+                kind: MemberKind::Method,
+                ..
+            } => {
+                let _ = db.generic_declarations(entity).accumulate_errors_into(&mut errors);
+                let _ = db.ty(entity).accumulate_errors_into(&mut errors);
+                let _ = db.signature(entity).accumulate_errors_into(&mut errors);
+                let _ = db.fn_body(entity).accumulate_errors_into(&mut errors);
+                let _ = db.full_type_check(entity).accumulate_errors_into(&mut errors);
+            }
+        }
+    }
+
+    Seq::from(errors)
+}
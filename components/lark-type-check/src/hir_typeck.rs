@@ -7,6 +7,7 @@ use lark_collections::FxIndexSet;
 use lark_debug_derive::DebugWith;
 use lark_debug_with::DebugWith;
 use lark_entity::{Entity, EntityData, ItemKind, LangItem, MemberKind};
+use lark_error::DiagnosticKind;
 use lark_error::ErrorReported;
 use lark_error::ErrorSentinel;
 use lark_hir as hir;
@@ -433,7 +434,14 @@ where
             arguments.debug_with(self),
         );
         if inputs.len() != arguments.len() {
-            self.record_error("mismatched argument count", cause);
+            self.record_error_of_kind(
+                "mismatched argument count",
+                DiagnosticKind::MismatchedArgumentCount {
+                    expected: inputs.len() - skip,
+                    found: arguments.len() - skip,
+                },
+                cause,
+            );
             return self.check_arguments_in_case_of_error(arguments, skip);
         }
 
@@ -548,8 +556,16 @@ where
         }
 
         // If we are missing any members, that's an error.
-        for _missing_member in missing_members {
-            self.record_error("missing member", expression);
+        for missing_member in missing_members {
+            let field = match missing_member.untern(self) {
+                EntityData::MemberName { id, .. } => id.untern(&self.db).to_string(),
+                _ => String::new(),
+            };
+            self.record_error_of_kind(
+                "missing member",
+                DiagnosticKind::MissingField { field },
+                expression,
+            );
 
             // Propagate this error to the generics, since they may be
             // underconstrained as a result.
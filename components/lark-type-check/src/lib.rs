@@ -6,13 +6,14 @@
 #![feature(trait_alias)]
 
 use generational_arena::Arena;
-use lark_collections::{FxIndexMap, IndexVec};
+use lark_collections::{FxIndexMap, IndexVec, Seq};
 use lark_debug_derive::DebugWith;
 use lark_entity::{Entity, EntityTables};
 use lark_error::{Diagnostic, WithError};
 use lark_hir as hir;
 use lark_parser::ParserDatabase;
 use lark_pretty_print::PrettyPrintDatabase;
+use lark_span::FileName;
 use lark_ty::base_inferred::BaseInferred;
 use lark_ty::base_inferred::BaseInferredTables;
 use lark_ty::declaration::Declaration;
@@ -32,6 +33,8 @@ use lark_unify::UnificationTable;
 use std::sync::Arc;
 
 mod base_inference;
+mod call_graph;
+mod diagnostics;
 mod full_inference;
 mod hir_typeck;
 mod ops;
@@ -51,8 +54,23 @@ pub trait TypeCheckDatabase:
     /// This is the type information excluding permissions.
     #[salsa::invoke(full_inference::query_definition::full_type_check)]
     fn full_type_check(&self, key: Entity) -> WithError<Arc<TypeCheckResults<FullInferred>>>;
+
+    /// The calls to functions and methods made in the body of `key`.
+    #[salsa::invoke(call_graph::outgoing_calls)]
+    fn outgoing_calls(&self, key: Entity) -> Seq<Call>;
+
+    /// The reverse of `outgoing_calls`, across all files: for each
+    /// function or method, the calls to it.
+    #[salsa::invoke(call_graph::incoming_calls)]
+    fn incoming_calls(&self) -> Arc<FxIndexMap<Entity, Seq<Call>>>;
+
+    /// All the errors in `file`, from parsing it and from checking
+    /// each of its items.
+    #[salsa::invoke(diagnostics::file_diagnostics)]
+    fn file_diagnostics(&self, file: FileName) -> Seq<Diagnostic>;
 }
 
+pub use call_graph::Call;
 pub use results::TypeCheckResults;
 
 struct TypeChecker<'me, F: TypeCheckerFamily, S> {
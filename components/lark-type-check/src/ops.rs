@@ -7,7 +7,7 @@ use crate::UniverseBinder;
 use lark_entity::Entity;
 use lark_entity::EntityData;
 use lark_entity::LangItem;
-use lark_error::{Diagnostic, ErrorReported};
+use lark_error::{Diagnostic, DiagnosticKind, ErrorReported};
 use lark_hir as hir;
 use lark_intern::Intern;
 use lark_ty::BaseData;
@@ -99,6 +99,18 @@ where
         self.errors.push(Diagnostic::new(label.into(), span));
     }
 
+    /// Record that an error of the given kind occurred at the given
+    /// location.
+    crate fn record_error_of_kind(
+        &mut self,
+        label: impl Into<String>,
+        kind: DiagnosticKind,
+        location: impl Into<hir::MetaIndex>,
+    ) {
+        let span = self.hir.span(location.into());
+        self.errors.push(Diagnostic::new(label.into(), span).with_kind(kind));
+    }
+
     crate fn own_perm(&mut self) -> F::Perm {
         F::own_perm(self)
     }
@@ -0,0 +1,340 @@
+use lark_build::{BackendOptions, CodegenOptions, CodegenType, OutputKind};
+use lark_cli::build::{BuildOptions, LarkDatabaseExt};
+use lark_query_system::LarkDatabase;
+use lark_test::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn parse(args: &[&str]) -> Result<BuildOptions, String> {
+    BuildOptions::from_args(args.iter().map(|arg| arg.to_string()))
+}
+
+/// Generates a crate for everything in `db` and writes it into `dir`
+fn emit(db: &LarkDatabase, dir: &Path, crate_name: &str, options: &CodegenOptions) {
+    let crate_source = lark_build::codegen_crate(db, CodegenType::Rust, options);
+    assert!(crate_source.errors.is_empty());
+
+    lark_build::emit_crate(dir, crate_name, &crate_source.value, CodegenType::Rust).unwrap();
+}
+
+/// Building the libraries needs `rustc`.
+fn have_rustc() -> bool {
+    Command::new("rustc")
+        .arg("--version")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+fn read(path: impl AsRef<Path>) -> String {
+    fs::read_to_string(path.as_ref())
+        .unwrap_or_else(|err| panic!("failed to read `{}`: {}", path.as_ref().display(), err))
+}
+
+#[test]
+fn parse_output_file_name() {
+    let options = parse(&["out"]).unwrap();
+    assert_eq!(options.output_file_name, Some("out".to_string()));
+    assert_eq!(options.emit_crate, None);
+
+    assert!(parse(&["out", "other"]).is_err());
+}
+
+#[test]
+fn parse_emit_crate() {
+    let options = parse(&["--emit-crate", "out"]).unwrap();
+    assert_eq!(options.emit_crate, Some(PathBuf::from("out")));
+    assert_eq!(options.output_file_name, None);
+
+    assert!(parse(&["--emit-crate"]).is_err());
+}
+
+#[test]
+fn emitted_crate_layout() {
+    let db = db_with_test(
+        "hello world.lark",
+        "def main() {
+    debug(3 + 10)
+}
+",
+    );
+    let dir = tempfile::tempdir().unwrap();
+
+    // A stale library root and module from an earlier emit are removed
+    fs::create_dir_all(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/lib.rs"), "").unwrap();
+    fs::write(dir.path().join("src/removed.rs"), "").unwrap();
+
+    emit(&db, dir.path(), "hello", &CodegenOptions::default());
+
+    let manifest = read(dir.path().join("Cargo.toml"));
+    assert!(manifest.contains("name = \"hello\"\n"));
+    assert!(!manifest.contains("crate-type"));
+
+    let crate_root = read(dir.path().join("src/main.rs"));
+    assert!(crate_root.contains("mod hello_world;\n"));
+    assert!(crate_root.contains("\nuse self::hello_world::*;\n"));
+    assert!(crate_root.contains("fn main() {\n    hello_world::main()\n}\n"));
+    assert!(!dir.path().join("src/lib.rs").exists());
+    assert!(!dir.path().join("src/removed.rs").exists());
+
+    let module = read(dir.path().join("src/hello_world.rs"));
+    assert!(module.contains("fn main()"));
+}
+
+#[test]
+fn crate_names_do_not_start_with_a_digit() {
+    assert_eq!(
+        lark_cli::build::crate_name("hello world.lark"),
+        "hello_world"
+    );
+    assert_eq!(lark_cli::build::crate_name("2048.lark"), "lark_2048");
+}
+
+#[test]
+fn failing_to_emit_a_crate_is_reported() {
+    let db = db_with_test("input.lark", "def main() { }\n");
+    let dir = tempfile::tempdir().unwrap();
+
+    // The crate can't go inside a file
+    let file = dir.path().join("file");
+    fs::write(&file, "").unwrap();
+
+    let mut out = termcolor::NoColor::new(Vec::new());
+    let error_count = db
+        .emit_crate(
+            &file.join("out"),
+            "out",
+            &CodegenOptions::default(),
+            &mut out,
+        )
+        .unwrap_or_else(|_| panic!("cancelled"));
+    assert_eq!(error_count, 1);
+
+    let message = String::from_utf8(out.into_inner()).unwrap();
+    assert!(message.contains("failed to emit crate into"));
+}
+
+const LIBRARY: &str = "struct Point {
+    x: uint,
+}
+
+def add(a: uint, b: uint) -> uint {
+    a + b
+}
+
+def x_of(p: Point) -> uint {
+    p.x
+}
+";
+
+fn extern_c_library() -> CodegenOptions {
+    CodegenOptions {
+        output_kind: OutputKind::Library,
+        extern_c: true,
+    }
+}
+
+#[test]
+fn parse_lib_and_extern_c() {
+    let options = parse(&["--lib"]).unwrap();
+    assert_eq!(options.codegen.output_kind, OutputKind::Library);
+    assert!(!options.codegen.extern_c);
+
+    let options = parse(&["--lib", "--extern-c"]).unwrap();
+    assert_eq!(options.codegen.output_kind, OutputKind::Library);
+    assert!(options.codegen.extern_c);
+
+    assert!(parse(&["--extern-c"]).is_err());
+}
+
+#[test]
+fn extern_c_wrapper_signatures() {
+    let db = db_with_test("input.lark", LIBRARY);
+    let source = lark_build::codegen(&db, CodegenType::Rust, &extern_c_library());
+    assert!(source.errors.is_empty());
+
+    assert!(source.value.contains(
+        "#[no_mangle]\npub extern \"C\" fn lark_add(a: u32, b: u32) -> u32 {\nadd(a, b) }\n"
+    ));
+    assert!(source
+        .value
+        .contains("// `x_of` has no extern \"C\" wrapper: `p: Point` is not FFI-safe\n"));
+    assert!(!source.value.contains("lark_x_of"));
+
+    // Without `--extern-c`, there are no wrappers at all
+    let options = CodegenOptions {
+        output_kind: OutputKind::Library,
+        extern_c: false,
+    };
+    let source = lark_build::codegen(&db, CodegenType::Rust, &options);
+    assert!(!source.value.contains("extern \"C\""));
+}
+
+#[test]
+fn emitted_library_crate_layout() {
+    let db = db_with_test("input.lark", LIBRARY);
+    let dir = tempfile::tempdir().unwrap();
+
+    emit(&db, dir.path(), "points", &extern_c_library());
+
+    let manifest = read(dir.path().join("Cargo.toml"));
+    assert!(manifest.contains("[lib]\ncrate-type = [\"rlib\", \"staticlib\"]\n"));
+
+    let crate_root = read(dir.path().join("src/lib.rs"));
+    assert!(crate_root.contains("mod input;\n"));
+    assert!(crate_root.contains("pub use self::input::*;\n"));
+    assert!(!crate_root.contains("fn main()"));
+    assert!(!dir.path().join("src/main.rs").exists());
+
+    let module = read(dir.path().join("src/input.rs"));
+    assert!(module.contains("pub extern \"C\" fn lark_add(a: u32, b: u32) -> u32"));
+}
+
+#[test]
+fn build_extern_c_library() {
+    if !have_rustc() {
+        eprintln!("skipping: `rustc` not found");
+        return;
+    }
+
+    let db = db_with_test("input.lark", LIBRARY);
+    let source = lark_build::codegen(&db, CodegenType::Rust, &extern_c_library());
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join(if cfg!(windows) {
+        "points.lib"
+    } else {
+        "libpoints.a"
+    });
+
+    lark_build::build(
+        output.to_str().unwrap(),
+        &source.value,
+        CodegenType::Rust,
+        &extern_c_library(),
+        &BackendOptions::default(),
+    )
+    .unwrap_or_else(|err| panic!("failed to build the library: {}", err));
+
+    assert!(output.exists());
+}
+
+#[test]
+fn build_library_named_with_a_digit() {
+    if !have_rustc() {
+        eprintln!("skipping: `rustc` not found");
+        return;
+    }
+
+    let options = CodegenOptions {
+        output_kind: OutputKind::Library,
+        extern_c: false,
+    };
+    let db = db_with_test("input.lark", LIBRARY);
+    let source = lark_build::codegen(&db, CodegenType::Rust, &options);
+    let dir = tempfile::tempdir().unwrap();
+
+    // `2d` is not a valid crate name, so rustc gets `lark_2d` instead
+    let output = dir.path().join("lib2d.rlib");
+    lark_build::build(
+        output.to_str().unwrap(),
+        &source.value,
+        CodegenType::Rust,
+        &options,
+        &BackendOptions::default(),
+    )
+    .unwrap_or_else(|err| panic!("failed to build the library: {}", err));
+
+    assert!(output.exists());
+}
+
+#[test]
+fn parse_backend_options() {
+    let options = parse(&[
+        "--opt-level",
+        "2",
+        "-g",
+        "--target",
+        "wasm32-unknown-unknown",
+        "--backend-arg",
+        "-Ccodegen-units=1",
+        "--backend-arg",
+        "-Cpanic=abort",
+        "--keep-source",
+        "out",
+    ])
+    .unwrap();
+    assert_eq!(options.output_file_name, Some("out".to_string()));
+    assert_eq!(options.backend.opt_level, Some("2".to_string()));
+    assert!(options.backend.debug_info);
+    assert_eq!(
+        options.backend.target,
+        Some("wasm32-unknown-unknown".to_string())
+    );
+    assert_eq!(
+        options.backend.extra_args,
+        vec!["-Ccodegen-units=1", "-Cpanic=abort"]
+    );
+    assert!(options.backend.keep_source);
+
+    for release in &["-O", "--release"] {
+        let options = parse(&[release]).unwrap();
+        assert_eq!(options.backend.opt_level, Some("3".to_string()));
+    }
+
+    // The last optimization level given wins
+    let options = parse(&["-O", "--opt-level", "1"]).unwrap();
+    assert_eq!(options.backend.opt_level, Some("1".to_string()));
+}
+
+#[test]
+fn backend_options_require_values() {
+    assert!(parse(&["--opt-level"]).is_err());
+    assert!(parse(&["--target"]).is_err());
+    assert!(parse(&["--backend-arg"]).is_err());
+}
+
+#[test]
+fn reject_unknown_flag() {
+    assert_eq!(
+        parse(&["--optimize"]).unwrap_err(),
+        "unknown option `--optimize`"
+    );
+}
+
+#[test]
+fn reject_backend_options_with_emit_crate() {
+    for backend_args in &[
+        &["-O"][..],
+        &["-g"],
+        &["--target", "wasm32-unknown-unknown"],
+        &["--backend-arg", "-Cpanic=abort"],
+        &["--keep-source"],
+    ] {
+        let mut args = vec!["--emit-crate", "out"];
+        args.extend(backend_args.iter());
+        assert!(parse(&args).is_err(), "accepted {:?}", args);
+    }
+}
+
+#[test]
+fn borrowed_parameter_types() {
+    let db = db_with_test(
+        "input.lark",
+        include_str!("test_files/borrowed_argument.lark"),
+    );
+    let source = lark_build::codegen(&db, CodegenType::Rust, &CodegenOptions::default());
+    assert!(source.errors.is_empty());
+
+    assert!(source
+        .value
+        .contains("fn describe(counter: &Counter) -> ()"));
+    assert!(source
+        .value
+        .contains("fn bump(counter: &mut Counter) -> ()"));
+    assert!(source
+        .value
+        .contains("fn increment(self: &mut Counter) -> ()"));
+    assert!(source.value.contains("(&mut counter).increment()"));
+}
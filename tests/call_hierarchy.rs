@@ -0,0 +1,61 @@
+use languageserver_types::Position;
+use lark_query_system::ls_ops::LsDatabase;
+use lark_test::language_server::{input_db, range, uncancelled, INPUT};
+
+const SOURCE: &str = "struct Counter {
+    count: uint,
+    next(x: uint) -> uint {
+        helper(x)
+    }
+}
+
+def helper(x: uint) -> uint {
+    x + 1
+}
+
+def main() {
+    let c = Counter(count: 0)
+    debug(c.next(1))
+    debug(helper(2))
+}
+";
+
+#[test]
+fn prepare_at_call() {
+    let db = input_db(SOURCE);
+    let items = uncancelled(db.call_hierarchy_items(INPUT, Position::new(14, 12)));
+
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].name, "helper");
+    assert_eq!(items[0].selection_range, range(7, 4, 10));
+}
+
+#[test]
+fn incoming_calls() {
+    let db = input_db(SOURCE);
+    let mut calls = uncancelled(db.incoming_calls_at_position(INPUT, Position::new(7, 4)));
+    calls.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+
+    let callers: Vec<_> = calls
+        .iter()
+        .map(|(item, ranges)| (item.name.as_str(), ranges.clone()))
+        .collect();
+    assert_eq!(
+        callers,
+        vec![
+            ("main", vec![range(14, 10, 19)]),
+            ("next", vec![range(3, 8, 17)]),
+        ]
+    );
+}
+
+#[test]
+fn outgoing_calls_include_methods() {
+    let db = input_db(SOURCE);
+    let calls = uncancelled(db.outgoing_calls_at_position(INPUT, Position::new(11, 4)));
+
+    // `debug` is built in, so it is not part of the hierarchy.
+    let mut callees: Vec<_> = calls.iter().map(|(item, _)| item.name.as_str()).collect();
+    callees.sort();
+    assert_eq!(callees, vec!["helper", "next"]);
+}
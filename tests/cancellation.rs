@@ -0,0 +1,95 @@
+use languageserver_types::Position;
+use lark_actor::{spawn_actor, Actor, LspResponse, QueryRequest};
+use lark_query_system::ls_ops::{Cancelled, LsDatabase};
+use lark_query_system::QuerySystem;
+use lark_test::language_server::{input_db, input_url, INPUT};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+
+const SOURCE: &str = "def main() {
+    let x = 22
+    debug(x)
+}
+";
+
+#[test]
+fn cancelled_request_stops() {
+    let db = input_db(SOURCE);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let snapshot = db.snapshot_for_request(cancelled.clone());
+
+    assert!(snapshot
+        .hover_text_at_position(INPUT, Position::new(2, 10))
+        .is_ok());
+
+    cancelled.store(true, Ordering::SeqCst);
+    match snapshot.hover_text_at_position(INPUT, Position::new(2, 10)) {
+        Err(Cancelled) => {}
+        Ok(_) => panic!("request was not cancelled"),
+    }
+}
+
+#[test]
+fn cancel_queued_request() {
+    let (send, receive) = channel();
+    let mut query_system = QuerySystem::new(send);
+
+    let url = input_url();
+    let mut messages: VecDeque<_> = vec![
+        QueryRequest::TypeAtPosition(1, url, Position::new(2, 10)),
+        QueryRequest::Cancel(1),
+    ]
+    .into_iter()
+    .collect();
+    query_system.receive_messages(&mut messages);
+
+    assert!(messages.is_empty());
+    match receive.recv().unwrap() {
+        LspResponse::Cancelled(1) => {}
+        _ => panic!("expected the request to be cancelled"),
+    }
+}
+
+#[test]
+fn shutdown_is_acknowledged() {
+    let (send, receive) = channel();
+    let mut query_system = QuerySystem::new(send);
+
+    let mut messages: VecDeque<_> = Some(QueryRequest::Shutdown(1)).into_iter().collect();
+    query_system.receive_messages(&mut messages);
+
+    match receive.recv().unwrap() {
+        LspResponse::Nothing(1) => {}
+        _ => panic!("expected an empty response to the shutdown request"),
+    }
+}
+
+/// Handles one message each time it is called, recording it.
+struct Recorder {
+    seen: Arc<Mutex<Vec<usize>>>,
+}
+
+impl Actor for Recorder {
+    type InMessage = usize;
+
+    fn receive_messages(&mut self, messages: &mut VecDeque<usize>) {
+        if let Some(message) = messages.pop_front() {
+            self.seen.lock().unwrap().push(message);
+        }
+    }
+}
+
+#[test]
+fn queued_messages_are_handled_after_senders_leave() {
+    let seen = Arc::new(Mutex::new(vec![]));
+    let control = spawn_actor(Recorder { seen: seen.clone() });
+
+    for message in 0..100 {
+        control.channel.send(message).unwrap();
+    }
+    control.join().unwrap();
+
+    assert_eq!(*seen.lock().unwrap(), (0..100).collect::<Vec<_>>());
+}
@@ -0,0 +1,114 @@
+use lark_actor::{Actor, CodeLensKind, LspResponse, QueryRequest};
+use lark_query_system::ls_ops::LsDatabase;
+use lark_query_system::{LarkDatabase, QuerySystem, Runner};
+use lark_span::FileName;
+use lark_test::language_server::{input_db, input_url, range, uncancelled, INPUT};
+use std::collections::VecDeque;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const SOURCE: &str = "struct Point {
+    x: uint,
+}
+
+def make() -> Point {
+    Point(x: 1)
+}
+
+def main() {
+    let p = make()
+    let q = make()
+}
+";
+
+fn reference_count(kind: &CodeLensKind) -> Option<usize> {
+    match kind {
+        CodeLensKind::References(references) => Some(references.len()),
+        CodeLensKind::RunMain => None,
+    }
+}
+
+#[test]
+fn run_main_and_reference_counts() {
+    let db = input_db(SOURCE);
+    let lenses = uncancelled(db.code_lenses(INPUT));
+
+    let ranges: Vec<_> = lenses.iter().map(|lens| lens.range).collect();
+    let main = range(8, 4, 8);
+    assert_eq!(ranges, vec![range(0, 7, 12), range(4, 4, 8), main, main,]);
+
+    let counts: Vec<_> = lenses
+        .iter()
+        .map(|lens| reference_count(&lens.kind))
+        .collect();
+    assert_eq!(counts, vec![Some(1), Some(2), None, Some(0)]);
+}
+
+/// Runs the `main` of `SOURCE` with `runner`, sending `interruption`
+/// (if any) while it runs, and returns the response.
+fn run_main(runner: Runner, interruption: Option<QueryRequest>) -> LspResponse {
+    let (send_channel, receive) = channel();
+    let mut query_system = QuerySystem::new(send_channel).with_runner(runner);
+    let url = input_url();
+
+    let mut messages: VecDeque<_> = vec![
+        QueryRequest::OpenFile(url.clone(), SOURCE.to_string()),
+        QueryRequest::RunMain(1, url),
+    ]
+    .into_iter()
+    .collect();
+    while !messages.is_empty() {
+        query_system.receive_messages(&mut messages);
+    }
+    if let Some(interruption) = interruption {
+        messages.push_back(interruption);
+        query_system.receive_messages(&mut messages);
+    }
+
+    loop {
+        match receive.recv().unwrap() {
+            LspResponse::Diagnostics(..) | LspResponse::RunOutput(..) => {}
+            response => return response,
+        }
+    }
+}
+
+fn unsupported(_: &LarkDatabase, _: FileName, _: Box<dyn FnMut(String) + Send>) {
+    panic!("not supported yet")
+}
+
+/// Waits to be cancelled, by the IDE or by an edit, before evaluating
+/// the program.
+fn wait_then_eval(db: &LarkDatabase, file_name: FileName, sink: Box<dyn FnMut(String) + Send>) {
+    while db.check_for_cancellation().is_ok() {
+        thread::sleep(Duration::from_millis(1));
+    }
+    lark_eval::eval_file(db, file_name, &mut lark_eval::IOHandler::streaming(sink));
+}
+
+#[test]
+fn failing_program_is_reported() {
+    match run_main(Arc::new(unsupported), None) {
+        LspResponse::Failed(1, message) => assert!(message.contains("not supported yet")),
+        _ => panic!("expected the run to fail"),
+    }
+}
+
+#[test]
+fn running_program_can_be_cancelled() {
+    match run_main(Arc::new(wait_then_eval), Some(QueryRequest::Cancel(1))) {
+        LspResponse::Cancelled(1) => {}
+        _ => panic!("expected the run to be cancelled"),
+    }
+}
+
+#[test]
+fn running_program_is_cancelled_by_an_edit() {
+    let edit = QueryRequest::EditFile(input_url(), vec![(None, SOURCE.to_string())]);
+    match run_main(Arc::new(wait_then_eval), Some(edit)) {
+        LspResponse::Cancelled(1) => {}
+        _ => panic!("expected the run to be cancelled"),
+    }
+}
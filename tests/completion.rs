@@ -0,0 +1,72 @@
+use languageserver_types::{CompletionItemKind, Position};
+use lark_query_system::ls_ops::LsDatabase;
+use lark_test::language_server::*;
+
+// Which names are offered is checked by the `//~ COMPLETION`
+// annotations in `test_files/language_server`; these check what else
+// the completions say, and what is left out.
+
+fn completions(
+    source: &str,
+    line: u64,
+    character: u64,
+) -> Vec<(String, String, CompletionItemKind)> {
+    let db = input_db(source);
+    uncancelled(db.completions_at_position(INPUT, Position::new(line, character)))
+}
+
+fn labels(completions: &[(String, String, CompletionItemKind)]) -> Vec<&str> {
+    completions
+        .iter()
+        .map(|(label, ..)| label.as_str())
+        .collect()
+}
+
+#[test]
+fn complete_members_after_dot() {
+    // debug(add(1, foo.b|az(2, 3)))
+    let completions = completions(SOURCE, 13, 22);
+    assert_eq!(labels(&completions), vec!["bar", "baz"]);
+    assert_eq!(completions[0].1, "bool");
+    assert_eq!(completions[0].2, CompletionItemKind::Field);
+    assert_eq!(completions[1].2, CompletionItemKind::Method);
+}
+
+#[test]
+fn complete_only_variables_in_scope() {
+    // debug(add(1, |foo.baz(2, 3)))
+    let completions = completions(SOURCE, 13, 17);
+    let labels = labels(&completions);
+    assert!(labels.contains(&"foo"));
+
+    // The parameters of `baz` and `add` are only in scope inside them
+    for parameter in &["x", "y", "a", "b"] {
+        assert!(!labels.contains(parameter));
+    }
+}
+
+#[test]
+fn complete_filters_by_prefix() {
+    // debug(add(1, fo|o.baz(2, 3)))
+    let completions = completions(SOURCE, 13, 19);
+    assert_eq!(labels(&completions), vec!["foo"]);
+}
+
+#[test]
+fn complete_local_over_item_of_same_name() {
+    let source = "def make() -> uint {
+    22
+}
+
+def main() {
+    let make = true
+    debug(make)
+}
+";
+
+    // debug(ma|ke)
+    let completions = completions(source, 6, 12);
+    assert_eq!(labels(&completions), vec!["make"]);
+    assert_eq!(completions[0].1, "bool");
+    assert_eq!(completions[0].2, CompletionItemKind::Variable);
+}
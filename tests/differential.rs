@@ -0,0 +1,59 @@
+use lark_test::differential::{generate_program, DifferentialRunner};
+use lark_test::*;
+use std::process::Command;
+
+/// How many random programs to try, unless `LARK_DIFFERENTIAL_SEEDS`
+/// says otherwise (to search more widely).
+fn seed_count(default: u64) -> u64 {
+    std::env::var("LARK_DIFFERENTIAL_SEEDS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Building the programs needs `rustc`.
+fn have_rustc() -> bool {
+    Command::new("rustc")
+        .arg("--version")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+#[test]
+fn generated_programs_type_check() {
+    for seed in 0..seed_count(100) {
+        let source = generate_program(seed).to_string();
+        run_test(&source, NoErrors);
+    }
+}
+
+// Builds a binary per program, so by default we only try a few.
+#[test]
+fn eval_and_build_agree() {
+    if !have_rustc() {
+        eprintln!("skipping: `rustc` not found");
+        return;
+    }
+
+    let seeds = seed_count(10);
+    let work_dir = std::env::temp_dir().join(format!("lark-differential-{}", std::process::id()));
+    std::fs::create_dir_all(&work_dir).unwrap();
+
+    let runner = DifferentialRunner::new(&work_dir);
+    let mismatches: Vec<_> = (0..seeds)
+        .filter_map(|seed| runner.run_seed(seed))
+        .collect();
+
+    let _ = std::fs::remove_dir_all(&work_dir);
+
+    for mismatch in &mismatches {
+        eprintln!("{}\n", mismatch);
+    }
+    assert!(
+        mismatches.is_empty(),
+        "{} of {} programs behave differently under eval and build",
+        mismatches.len(),
+        seeds
+    );
+}
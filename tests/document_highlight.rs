@@ -0,0 +1,53 @@
+use languageserver_types::{DocumentHighlightKind, Position};
+use lark_query_system::ls_ops::LsDatabase;
+use lark_test::language_server::{input_db, range, uncancelled, INPUT};
+
+const SOURCE: &str = "struct Foo { x: uint }
+struct Bar { foo: Foo }
+
+def main() {
+  let bar = Bar(foo: Foo(x: 22))
+  take_foo(bar.foo)
+  bar.foo = Foo(x: 44)
+  take_bar(bar)
+}
+
+def take_bar(v: Bar) { }
+def take_foo(v: Foo) { }
+";
+
+#[test]
+fn reads_and_writes() {
+    let db = input_db(SOURCE);
+    let highlights = uncancelled(db.document_highlights_at_position(INPUT, Position::new(5, 15)));
+
+    let highlights: Vec<_> = highlights
+        .into_iter()
+        .map(|highlight| (highlight.range, highlight.kind))
+        .collect();
+    assert_eq!(
+        highlights,
+        vec![
+            (range(1, 13, 16), Some(DocumentHighlightKind::Read)),
+            (range(4, 16, 19), Some(DocumentHighlightKind::Read)),
+            (range(5, 15, 18), Some(DocumentHighlightKind::Read)),
+            (range(6, 6, 9), Some(DocumentHighlightKind::Write)),
+        ]
+    );
+}
+
+#[test]
+fn type_definition_of_variable() {
+    let db = input_db(SOURCE);
+    let definition = uncancelled(db.type_definition_at_position(INPUT, Position::new(7, 12)));
+
+    assert_eq!(definition, Some((INPUT.to_string(), range(1, 7, 10))));
+}
+
+#[test]
+fn type_definition_of_field() {
+    let db = input_db(SOURCE);
+    let definition = uncancelled(db.type_definition_at_position(INPUT, Position::new(5, 15)));
+
+    assert_eq!(definition, Some((INPUT.to_string(), range(0, 7, 10))));
+}
@@ -0,0 +1,279 @@
+use languageserver_types::{FileChangeType, Position, Range};
+use lark_actor::{Actor, LspResponse, QueryRequest};
+use lark_parser::{ParserDatabase, ParserDatabaseExt};
+use lark_query_system::ls_ops::LsDatabase;
+use lark_query_system::QuerySystem;
+use lark_test::language_server::{input_url, send, uncancelled};
+use lark_test::*;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// The names of the symbols in the response to a `DocumentSymbols`
+/// request, skipping any diagnostics that get published meanwhile.
+fn symbol_names(receive: &Receiver<LspResponse>) -> Vec<String> {
+    loop {
+        match receive.recv().unwrap() {
+            LspResponse::DocumentSymbols(_, symbols) => {
+                return symbols.into_iter().map(|symbol| symbol.name).collect();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn full_and_incremental_edits() {
+    let (send_channel, receive) = channel();
+    let mut query_system = QuerySystem::new(send_channel);
+    let url = input_url();
+
+    send(
+        &mut query_system,
+        QueryRequest::OpenFile(url.clone(), "def foo() {}\n".to_string()),
+    );
+
+    // A change without a range replaces the whole text...
+    send(
+        &mut query_system,
+        QueryRequest::EditFile(url.clone(), vec![(None, "def bar() {}\n".to_string())]),
+    );
+
+    // ...and later changes in the same batch apply to the result.
+    let range = Range::new(Position::new(0, 4), Position::new(0, 7));
+    send(
+        &mut query_system,
+        QueryRequest::EditFile(
+            url.clone(),
+            vec![
+                (None, "def baz() {}\n".to_string()),
+                (Some(range), "quux".to_string()),
+            ],
+        ),
+    );
+
+    send(&mut query_system, QueryRequest::DocumentSymbols(1, url));
+    assert_eq!(symbol_names(&receive), vec!["quux"]);
+}
+
+#[test]
+fn closing_a_file_clears_its_diagnostics() {
+    let (send_channel, receive) = channel();
+    let mut query_system = QuerySystem::new(send_channel);
+    let url = input_url();
+
+    send(
+        &mut query_system,
+        QueryRequest::OpenFile(url.clone(), "def foo() {\n    bar()\n}\n".to_string()),
+    );
+    send(&mut query_system, QueryRequest::CloseFile(url.clone()));
+
+    loop {
+        match receive.recv().unwrap() {
+            LspResponse::Diagnostics(diagnostics_url, diagnostics) => {
+                if diagnostics_url == url && diagnostics.is_empty() {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn removed_file_is_not_checked() {
+    let mut db = db_with_test("a.lark", "def main() {}\n");
+    db.add_file("b.lark", "def foo() {\n    bar()\n}\n");
+    let errors = uncancelled(db.errors_for_project());
+    assert!(errors["a.lark"].is_empty());
+    assert!(!errors["b.lark"].is_empty());
+
+    db.remove_file("b.lark");
+    assert_eq!(db.file_names().len(), 1);
+
+    let errors = uncancelled(db.errors_for_project());
+    assert!(!errors.contains_key("b.lark"));
+}
+
+/// The next diagnostics published, skipping other responses.
+fn next_diagnostics(receive: &Receiver<LspResponse>) -> (Url, Vec<(Range, String)>) {
+    loop {
+        match receive.recv().unwrap() {
+            LspResponse::Diagnostics(url, diagnostics) => return (url, diagnostics),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn error_check_waits_for_edits_to_stop() {
+    let (send_channel, receive) = channel();
+    let mut query_system = QuerySystem::new(send_channel);
+    let url = input_url();
+
+    send(
+        &mut query_system,
+        QueryRequest::OpenFile(url.clone(), "def foo() {\n    bar()\n}\n".to_string()),
+    );
+    let wake_up_at = query_system
+        .wake_up_at()
+        .expect("no error check after an edit");
+
+    let now = Instant::now();
+    if wake_up_at > now {
+        std::thread::sleep(wake_up_at - now);
+    }
+    query_system.receive_messages(&mut VecDeque::new());
+    assert_eq!(query_system.wake_up_at(), None);
+
+    let (diagnostics_url, diagnostics) = next_diagnostics(&receive);
+    assert_eq!(diagnostics_url, url);
+    assert!(!diagnostics.is_empty());
+}
+
+#[test]
+fn only_changed_diagnostics_are_republished() {
+    let (send_channel, receive) = channel();
+    let mut query_system = QuerySystem::new(send_channel);
+    let a = Url::parse("file:///a.lark").unwrap();
+    let b = Url::parse("file:///b.lark").unwrap();
+
+    send(
+        &mut query_system,
+        QueryRequest::OpenFile(a.clone(), "def foo() {\n    bar()\n}\n".to_string()),
+    );
+    send(
+        &mut query_system,
+        QueryRequest::OpenFile(b.clone(), "def baz() {}\n".to_string()),
+    );
+    query_system.check_for_errors_and_report();
+
+    let mut published: Vec<_> = vec![next_diagnostics(&receive), next_diagnostics(&receive)];
+    published.sort_by_key(|(url, _)| url.to_string());
+    assert_eq!(published[0].0, a);
+    assert!(!published[0].1.is_empty());
+    assert_eq!(published[1], (b.clone(), vec![]));
+
+    // Fixing `a.lark` changes only its diagnostics.
+    send(
+        &mut query_system,
+        QueryRequest::EditFile(a.clone(), vec![(None, "def foo() {}\n".to_string())]),
+    );
+    query_system.check_for_errors_and_report();
+
+    assert_eq!(next_diagnostics(&receive), (a.clone(), vec![]));
+    match receive.recv_timeout(Duration::from_millis(500)) {
+        Err(RecvTimeoutError::Timeout) => {}
+        _ => panic!("expected nothing else to be published"),
+    }
+}
+
+#[test]
+fn pull_diagnostics() {
+    let (send_channel, receive) = channel();
+    let mut query_system = QuerySystem::new(send_channel);
+    let url = input_url();
+
+    send(
+        &mut query_system,
+        QueryRequest::OpenFile(url.clone(), "def foo() {\n    bar()\n}\n".to_string()),
+    );
+    send(
+        &mut query_system,
+        QueryRequest::PullDiagnostics(1, url.clone(), None),
+    );
+
+    loop {
+        match receive.recv().unwrap() {
+            LspResponse::PulledDiagnostics(1, pulled_url, diagnostics, None) => {
+                assert_eq!(pulled_url, url);
+                assert!(!diagnostics.is_empty());
+                break;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A fresh directory for the files of a workspace.
+fn workspace_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lark-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::create_dir_all(dir.join(".hidden")).unwrap();
+    dir
+}
+
+#[test]
+fn workspace_files_are_loaded_from_disk() {
+    let (send_channel, receive) = channel();
+    let mut query_system = QuerySystem::new(send_channel);
+
+    let dir = workspace_dir("load");
+    fs::write(dir.join("a.lark"), "def foo() {}\n").unwrap();
+    fs::write(dir.join("nested/b.lark"), "def bar() {}\n").unwrap();
+    fs::write(dir.join(".hidden/c.lark"), "def baz() {}\n").unwrap();
+    let a = Url::from_file_path(dir.join("a.lark")).unwrap();
+    let b = Url::from_file_path(dir.join("nested/b.lark")).unwrap();
+
+    send(
+        &mut query_system,
+        QueryRequest::LoadWorkspace(vec![Url::from_directory_path(&dir).unwrap()]),
+    );
+
+    send(
+        &mut query_system,
+        QueryRequest::WorkspaceSymbols(1, String::new()),
+    );
+    let mut names = loop {
+        match receive.recv().unwrap() {
+            LspResponse::WorkspaceSymbols(1, symbols) => {
+                break symbols
+                    .into_iter()
+                    .map(|symbol| symbol.name)
+                    .collect::<Vec<_>>();
+            }
+            _ => {}
+        }
+    };
+    names.sort();
+    assert_eq!(names, vec!["bar", "foo"]);
+
+    // The IDE's buffer wins over the file on disk, even when the file
+    // changes...
+    send(
+        &mut query_system,
+        QueryRequest::OpenFile(a.clone(), "def edited() {}\n".to_string()),
+    );
+    fs::write(dir.join("a.lark"), "def saved() {}\n").unwrap();
+    send(
+        &mut query_system,
+        QueryRequest::WatchedFilesChanged(vec![(a.clone(), FileChangeType::Changed)]),
+    );
+    send(
+        &mut query_system,
+        QueryRequest::DocumentSymbols(2, a.clone()),
+    );
+    assert_eq!(symbol_names(&receive), vec!["edited"]);
+
+    // ...until the IDE closes it.
+    send(&mut query_system, QueryRequest::CloseFile(a.clone()));
+    send(
+        &mut query_system,
+        QueryRequest::DocumentSymbols(3, a.clone()),
+    );
+    assert_eq!(symbol_names(&receive), vec!["saved"]);
+
+    fs::remove_file(dir.join("nested/b.lark")).unwrap();
+    send(
+        &mut query_system,
+        QueryRequest::WatchedFilesChanged(vec![(b.clone(), FileChangeType::Deleted)]),
+    );
+    send(&mut query_system, QueryRequest::DocumentSymbols(4, b));
+    assert!(symbol_names(&receive).is_empty());
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,78 @@
+use languageserver_types::{FoldingRangeKind, Position};
+use lark_query_system::ls_ops::LsDatabase;
+use lark_test::language_server::{input_db, range, uncancelled, INPUT};
+
+const SOURCE: &str = "// A point
+// in two dimensions
+struct Point {
+    x: uint,
+    y: uint,
+}
+
+def main() {
+    let p = Point(x: 1, y: 2)
+    if p.x > 0 {
+        debug(p.y)
+    }
+}
+";
+
+#[test]
+fn items_blocks_and_comments_fold() {
+    let db = input_db(SOURCE);
+    let folds = uncancelled(db.folding_ranges(INPUT));
+
+    let lines: Vec<_> = folds
+        .iter()
+        .map(|fold| (fold.start_line, fold.end_line))
+        .collect();
+    assert_eq!(lines, vec![(0, 1), (2, 5), (7, 12), (9, 11)]);
+
+    assert_eq!(folds[0].kind, Some(FoldingRangeKind::Comment));
+    assert_eq!(folds[1].kind, None);
+}
+
+#[test]
+fn method_blocks_fold() {
+    let db = input_db(
+        "struct Counter {
+    count: uint,
+    positive() -> bool {
+        if self.count > 0 {
+            debug(self.count)
+        }
+        self.count > 0
+    }
+}
+",
+    );
+    let folds = uncancelled(db.folding_ranges(INPUT));
+
+    let lines: Vec<_> = folds
+        .iter()
+        .map(|fold| (fold.start_line, fold.end_line))
+        .collect();
+    assert_eq!(lines, vec![(0, 8), (2, 7), (3, 5)]);
+}
+
+#[test]
+fn selection_expands_from_token_to_entity() {
+    let db = input_db(SOURCE);
+    let ranges = uncancelled(db.selection_ranges(INPUT, &[Position::new(9, 9)]));
+
+    assert_eq!(ranges.len(), 1);
+    let ranges = &ranges[0];
+
+    // The `x` of `p.x`, then the comparison, up to `main` and the file.
+    assert_eq!(ranges[0], range(9, 9, 10));
+    assert!(ranges.contains(&range(9, 7, 14)));
+    assert!(ranges
+        .iter()
+        .any(|range| range.start == Position::new(7, 0)));
+    assert_eq!(ranges.last().unwrap().start, Position::new(0, 0));
+
+    for pair in ranges.windows(2) {
+        assert!(pair[1].start <= pair[0].start && pair[0].end <= pair[1].end);
+        assert_ne!(pair[0], pair[1]);
+    }
+}
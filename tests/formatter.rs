@@ -0,0 +1,68 @@
+use languageserver_types::{Position, Range};
+use lark_query_system::ls_ops::LsDatabase;
+use lark_test::language_server::{input_db, range, uncancelled, INPUT};
+
+const UNFORMATTED: &str = "struct Foo {
+  a:uint // the a
+    b : uint
+}
+
+
+def main( ) {
+let x=22 // comment
+  let foo = Foo(a:x, b:x)
+    /* block */ let y = foo.a+1
+}";
+
+const FORMATTED: &str = "struct Foo {
+    a: uint, // the a
+    b: uint,
+}
+
+def main() {
+    let x = 22 // comment
+    let foo = Foo(a: x, b: x)
+    /* block */ let y = foo.a + 1
+}
+";
+
+#[test]
+fn format_whole_file() {
+    let db = input_db(UNFORMATTED);
+    let edits = uncancelled(db.formatting_edits(INPUT, None)).unwrap();
+
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].0.start, Position::new(0, 0));
+    assert_eq!(edits[0].1, FORMATTED);
+}
+
+#[test]
+fn formatted_file_is_unchanged() {
+    let db = input_db(FORMATTED);
+    let edits = uncancelled(db.formatting_edits(INPUT, None)).unwrap();
+
+    assert!(edits.is_empty());
+}
+
+#[test]
+fn format_range() {
+    let db = input_db(UNFORMATTED);
+    let line = range(7, 0, 3);
+    let edits = uncancelled(db.formatting_edits(INPUT, Some(line))).unwrap();
+
+    assert_eq!(
+        edits,
+        vec![(
+            Range::new(Position::new(7, 0), Position::new(8, 0)),
+            "    let x = 22 // comment\n".to_string(),
+        )]
+    );
+}
+
+#[test]
+fn syntax_errors_are_not_formatted() {
+    let db = input_db("struct {\n  a: uint\n}\n");
+    let edits = uncancelled(db.formatting_edits(INPUT, None));
+
+    assert!(edits.is_none());
+}
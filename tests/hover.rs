@@ -0,0 +1,25 @@
+use languageserver_types::{Position, Range};
+use lark_query_system::ls_ops::LsDatabase;
+use lark_test::language_server::*;
+
+// What the hovers say is checked by the `//~ HOVER` annotations in
+// `test_files/language_server`; these check the range each covers.
+
+fn hover_range(line: u64, character: u64) -> Range {
+    let db = input_db(SOURCE);
+    let (range, _) = uncancelled(db.hover_text_at_position(INPUT, Position::new(line, character)))
+        .unwrap_or_else(|| panic!("no hover at {}:{}", line, character));
+    range
+}
+
+#[test]
+fn function_name() {
+    // debug(a|dd(1, ...))
+    assert_eq!(hover_range(13, 11), range(13, 10, 13));
+}
+
+#[test]
+fn variable_name() {
+    // debug(add(1, f|oo.baz(2, 3)))
+    assert_eq!(hover_range(13, 18), range(13, 17, 20));
+}
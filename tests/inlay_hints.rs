@@ -0,0 +1,49 @@
+use languageserver_types::{Position, Range};
+use lark_query_system::ls_ops::LsDatabase;
+use lark_test::language_server::{input_db, range, uncancelled, INPUT};
+
+const SOURCE: &str = "struct Foo {
+    a: uint,
+}
+
+def take(foo: Foo) {}
+
+def main() {
+    let x = 22
+    let foo = Foo(a: x)
+    take(foo)
+}
+";
+
+#[test]
+fn let_bindings_and_arguments() {
+    let db = input_db(SOURCE);
+    let everything = Range::new(Position::new(0, 0), Position::new(11, 0));
+    let hints = uncancelled(db.inlay_hints(INPUT, everything));
+
+    let positions: Vec<_> = hints.iter().map(|hint| hint.position).collect();
+    assert_eq!(
+        positions,
+        vec![
+            Position::new(7, 9),
+            Position::new(8, 11),
+            Position::new(9, 12),
+        ]
+    );
+
+    assert!(hints[0].label.ends_with("uint"));
+    assert!(hints[1].label.ends_with("Foo"));
+
+    // `take` needs to own its argument, so `foo` is moved into it.
+    assert_eq!(hints[2].label, ": own Foo");
+}
+
+#[test]
+fn only_hints_in_range() {
+    let db = input_db(SOURCE);
+    let line = range(8, 0, 23);
+    let hints = uncancelled(db.inlay_hints(INPUT, line));
+
+    let positions: Vec<_> = hints.iter().map(|hint| hint.position).collect();
+    assert_eq!(positions, vec![Position::new(8, 11)]);
+}
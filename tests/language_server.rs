@@ -145,7 +145,26 @@ mod tests {
 
         assert_eq!(result.method, "textDocument/publishDiagnostics",);
         assert_eq!(result.params.diagnostics.len(), 1,);
-        assert_eq!(result.params.diagnostics[0].message, "mismatched types (uint vs bool)",);
+        assert_eq!(
+            result.params.diagnostics[0].message,
+            "mismatched types (uint vs bool)",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn count_columns_in_utf16_unless_negotiated() -> Result<(), Box<std::error::Error>> {
+        let mut child_session = ChildSession::spawn();
+
+        // We don't list any position encodings, so the server must fall
+        // back to the protocol's default rather than counting characters.
+        child_session.send_init(100)?;
+
+        let result = child_session.receive::<JsonRPCResponse<serde_json::Value>>()?;
+
+        assert_eq!(result.id, 100);
+        assert_eq!(result.result["capabilities"]["positionEncoding"], "utf-16");
 
         Ok(())
     }
@@ -1,5 +1,5 @@
 use lark_parser::ParserDatabase;
-use lark_span::ByteIndex;
+use lark_span::{ByteIndex, PositionEncoding};
 use lark_test::*;
 
 #[test]
@@ -42,3 +42,50 @@ fn location() {
         &loc_4,
     );
 }
+
+#[test]
+fn location_counts_characters_by_default() {
+    let file_name = "foo.lark";
+    let db = db_with_test(file_name, "/* 𝕏é */ def main() {\n}\n");
+    let file_name = file_name.into_file_name(&db);
+
+    // `𝕏` is four bytes and `é` is two, but each is one character. So
+    // `def` starts at byte 13, column 9.
+    let location = db.location(file_name, ByteIndex::from(13));
+    assert_eq!((location.line, location.column), (0, 9));
+    assert_eq!(db.byte_index(file_name, 0, 9), ByteIndex::from(13));
+
+    // Columns past the end of a line stop at its end.
+    assert_eq!(db.byte_index(file_name, 0, 4), ByteIndex::from(7));
+    assert_eq!(db.byte_index(file_name, 1, 50), ByteIndex::from(27));
+    assert_eq!(db.byte_index(file_name, 5, 0), ByteIndex::from(28));
+}
+
+#[test]
+fn location_counts_code_units_in_utf16() {
+    let file_name = "foo.lark";
+    let mut db = db_with_test(file_name, "/* 𝕏é */ def main() {\n}\n");
+    db.set_position_encoding(PositionEncoding::Utf16);
+    let file_name = file_name.into_file_name(&db);
+
+    // `𝕏` is two UTF-16 code units and `é` is one. So `def` starts at
+    // column 10.
+    let location = db.location(file_name, ByteIndex::from(13));
+    assert_eq!((location.line, location.column), (0, 10));
+    assert_eq!(db.byte_index(file_name, 0, 10), ByteIndex::from(13));
+
+    // A column in the middle of `𝕏` moves on to the character after.
+    assert_eq!(db.byte_index(file_name, 0, 4), ByteIndex::from(7));
+}
+
+#[test]
+fn location_counts_bytes_in_utf8() {
+    let file_name = "foo.lark";
+    let mut db = db_with_test(file_name, "/* 𝕏é */ def main() {\n}\n");
+    db.set_position_encoding(PositionEncoding::Utf8);
+    let file_name = file_name.into_file_name(&db);
+
+    let location = db.location(file_name, ByteIndex::from(13));
+    assert_eq!((location.line, location.column), (0, 13));
+    assert_eq!(db.byte_index(file_name, 0, 13), ByteIndex::from(13));
+}
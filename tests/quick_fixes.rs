@@ -0,0 +1,76 @@
+use languageserver_types::{Position, Range};
+use lark_actor::QuickFix;
+use lark_query_system::ls_ops::LsDatabase;
+use lark_test::language_server::{input_db, range, uncancelled, INPUT};
+
+fn quick_fixes(source: &str, position: Position) -> Vec<QuickFix> {
+    let db = input_db(source);
+    uncancelled(db.quick_fixes(INPUT, Range::new(position, position)))
+}
+
+fn titles(fixes: &[QuickFix]) -> Vec<&str> {
+    fixes.iter().map(|fix| &fix.title[..]).collect()
+}
+
+#[test]
+fn unknown_identifier() {
+    let source = "def foo() {}
+
+def main() {
+    fooo()
+}
+";
+    let fixes = quick_fixes(source, Position::new(3, 5));
+    assert_eq!(
+        titles(&fixes),
+        vec![
+            "Change to `foo`",
+            "Create struct `fooo`",
+            "Create function `fooo`",
+        ]
+    );
+
+    let change = range(3, 4, 8);
+    assert_eq!(fixes[0].edits, vec![(change, "foo".to_string())]);
+
+    let end = range(5, 0, 0);
+    assert_eq!(
+        fixes[2].edits,
+        vec![(end, "\ndef fooo() {\n}\n".to_string())]
+    );
+}
+
+#[test]
+fn missing_field() {
+    let source = "struct Foo {
+    a: uint,
+    b: uint,
+}
+
+def main() {
+    let foo = Foo(a: 1)
+}
+";
+    let fixes = quick_fixes(source, Position::new(6, 16));
+    assert_eq!(titles(&fixes), vec!["Add missing field `b`"]);
+
+    let insertion = range(6, 22, 22);
+    assert_eq!(fixes[0].edits, vec![(insertion, ", b: b".to_string())]);
+}
+
+#[test]
+fn missing_argument() {
+    let source = "def add(x: uint, y: uint) -> uint {
+    x
+}
+
+def main() {
+    add(1)
+}
+";
+    let fixes = quick_fixes(source, Position::new(5, 5));
+    assert_eq!(titles(&fixes), vec!["Add missing argument `y`"]);
+
+    let insertion = range(5, 9, 9);
+    assert_eq!(fixes[0].edits, vec![(insertion, ", y".to_string())]);
+}
@@ -0,0 +1,118 @@
+use languageserver_types::{Position, Range};
+use lark_parser::ParserDatabaseExt;
+use lark_query_system::ls_ops::{LsDatabase, RenameError};
+use lark_test::language_server::{input_db, range, uncancelled, INPUT};
+
+// Successful renames are checked by the `//~ RENAME` annotations in
+// `test_files/language_server`; these check preparing a rename and
+// the renames we refuse.
+
+const SOURCE: &str = "struct Point {
+    x: uint,
+    y: uint,
+}
+
+def main() {
+    let a = 1
+    let b = 2
+    debug(a)
+    show(Point(x: a, y: b))
+}
+
+def show(p: Point) {
+    debug(p.x)
+}
+";
+
+fn rename(
+    db: &impl LsDatabase,
+    position: Position,
+    new_name: &str,
+) -> Result<Vec<Range>, RenameError> {
+    let edits = uncancelled(db.rename_all_references_at_position(INPUT, position, new_name))?;
+
+    let mut ranges: Vec<_> = edits
+        .into_iter()
+        .map(|(file, range, text)| {
+            assert_eq!(file, INPUT);
+            assert_eq!(text, new_name);
+            range
+        })
+        .collect();
+    ranges.sort_by_key(|range| range.start);
+    ranges.dedup();
+    Ok(ranges)
+}
+
+#[test]
+fn prepare_rename_gives_the_name_under_the_cursor() {
+    let db = input_db(SOURCE);
+    let prepared = uncancelled(db.prepare_rename(INPUT, Position::new(8, 10)));
+    assert_eq!(prepared, Ok((range(8, 10, 11), "a".to_string())));
+}
+
+#[test]
+fn built_in_names_cannot_be_renamed() {
+    let db = input_db(SOURCE);
+    let prepare =
+        |line, character| uncancelled(db.prepare_rename(INPUT, Position::new(line, character)));
+
+    assert_eq!(
+        prepare(8, 5),
+        Err(RenameError::BuiltIn("debug".to_string()))
+    );
+    assert_eq!(prepare(6, 5), Err(RenameError::BuiltIn("let".to_string())));
+    assert_eq!(prepare(6, 1), Err(RenameError::NothingToRename));
+}
+
+#[test]
+fn new_name_must_be_an_identifier() {
+    let db = input_db(SOURCE);
+    assert_eq!(
+        rename(&db, Position::new(8, 10), "2x"),
+        Err(RenameError::InvalidName("2x".to_string()))
+    );
+    assert_eq!(
+        rename(&db, Position::new(8, 10), "let"),
+        Err(RenameError::InvalidName("let".to_string()))
+    );
+}
+
+#[test]
+fn conflicting_names_are_rejected() {
+    let db = input_db(SOURCE);
+    let conflict = |reason: &str| -> Result<Vec<Range>, RenameError> {
+        Err(RenameError::Conflict(reason.to_string()))
+    };
+
+    assert_eq!(
+        rename(&db, Position::new(8, 10), "b"),
+        conflict("there is already a variable named `b`")
+    );
+    assert_eq!(
+        rename(&db, Position::new(13, 12), "y"),
+        conflict("`Point` already has a field named `y`")
+    );
+    assert_eq!(
+        rename(&db, Position::new(0, 8), "main"),
+        conflict("there is already a function named `main`")
+    );
+    assert_eq!(
+        rename(&db, Position::new(12, 5), "a"),
+        conflict("it is used where the variable `a` in `main` would hide it")
+    );
+}
+
+#[test]
+fn names_taken_in_other_files_are_rejected() {
+    let mut db = input_db(SOURCE);
+    db.add_file("other.lark", "def draw() {}\n");
+
+    assert_eq!(
+        rename(&db, Position::new(12, 5), "draw"),
+        Err(RenameError::Conflict(
+            "there is already a function named `draw` in `other.lark`".to_string()
+        ))
+    );
+    assert!(rename(&db, Position::new(12, 5), "render").is_ok());
+}
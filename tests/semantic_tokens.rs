@@ -0,0 +1,54 @@
+use lark_actor::SemanticTokenKind;
+use lark_query_system::ls_ops::LsDatabase;
+use lark_test::language_server::{input_db, uncancelled, INPUT};
+
+const SOURCE: &str = "struct Foo {
+    bar: bool,
+    baz(x: uint) -> uint {
+        x
+    }
+}
+
+def main() {
+    let foo = Foo(bar: true)
+    debug(foo.baz(1))
+}
+";
+
+#[test]
+fn classify_identifiers() {
+    let db = input_db(SOURCE);
+    let tokens = uncancelled(db.semantic_tokens(INPUT));
+
+    let tokens: Vec<_> = tokens
+        .iter()
+        .map(|token| {
+            let line = SOURCE.lines().nth(token.range.start.line as usize).unwrap();
+            let text =
+                &line[token.range.start.character as usize..token.range.end.character as usize];
+            (text, token.kind, token.declaration)
+        })
+        .collect();
+
+    assert_eq!(
+        tokens,
+        vec![
+            ("Foo", SemanticTokenKind::Struct, true),
+            ("bar", SemanticTokenKind::Field, true),
+            ("bool", SemanticTokenKind::BuiltinType, false),
+            ("baz", SemanticTokenKind::Method, true),
+            ("x", SemanticTokenKind::Parameter, true),
+            ("uint", SemanticTokenKind::BuiltinType, false),
+            ("uint", SemanticTokenKind::BuiltinType, false),
+            ("x", SemanticTokenKind::Parameter, false),
+            ("main", SemanticTokenKind::Function, true),
+            ("foo", SemanticTokenKind::Variable, true),
+            ("Foo", SemanticTokenKind::Struct, false),
+            ("bar", SemanticTokenKind::Field, false),
+            ("true", SemanticTokenKind::BuiltinConstant, false),
+            ("debug", SemanticTokenKind::BuiltinFunction, false),
+            ("foo", SemanticTokenKind::Variable, false),
+            ("baz", SemanticTokenKind::Method, false),
+        ]
+    );
+}
@@ -0,0 +1,41 @@
+use languageserver_types::Position;
+use lark_query_system::ls_ops::LsDatabase;
+use lark_test::language_server::*;
+
+fn signature_help(line: u64, character: u64) -> Option<(String, Vec<String>, usize)> {
+    let db = input_db(SOURCE);
+    uncancelled(db.signature_help_at_position(INPUT, Position::new(line, character)))
+}
+
+#[test]
+fn function_arguments() {
+    // debug(add(1, |foo.baz(2, 3)))
+    let (label, parameters, active) = signature_help(13, 17).unwrap();
+    assert_eq!(label, "add(a: uint, b: uint) -> uint");
+    assert_eq!(parameters, vec!["a: uint", "b: uint"]);
+    assert_eq!(active, 1);
+}
+
+#[test]
+fn method_arguments_skip_self() {
+    // debug(add(1, foo.baz(2, |3)))
+    let (label, parameters, active) = signature_help(13, 28).unwrap();
+    assert_eq!(label, "baz(x: uint, y: uint) -> uint");
+    assert_eq!(parameters, vec!["x: uint", "y: uint"]);
+    assert_eq!(active, 1);
+}
+
+#[test]
+fn struct_constructor_fields() {
+    // let foo = Foo(bar: |true)
+    let (label, parameters, active) = signature_help(12, 23).unwrap();
+    assert_eq!(label, "Foo(bar: bool)");
+    assert_eq!(parameters, vec!["bar: bool"]);
+    assert_eq!(active, 0);
+}
+
+#[test]
+fn outside_of_call() {
+    // let |foo = ...
+    assert_eq!(signature_help(12, 8), None);
+}
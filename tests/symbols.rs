@@ -0,0 +1,50 @@
+use languageserver_types::SymbolKind;
+use lark_query_system::ls_ops::LsDatabase;
+use lark_test::language_server::*;
+
+#[test]
+fn document_symbols_nest_members() {
+    let db = input_db(SOURCE);
+    let symbols = uncancelled(db.document_symbols(INPUT));
+
+    let names: Vec<_> = symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect();
+    assert_eq!(
+        names,
+        vec![
+            ("Foo", SymbolKind::Struct),
+            ("add", SymbolKind::Function),
+            ("main", SymbolKind::Function),
+        ]
+    );
+
+    let members: Vec<_> = symbols[0]
+        .children
+        .as_ref()
+        .unwrap()
+        .iter()
+        .map(|s| (s.name.as_str(), s.kind))
+        .collect();
+    assert_eq!(
+        members,
+        vec![("bar", SymbolKind::Field), ("baz", SymbolKind::Method)]
+    );
+
+    // The selection range is just the name.
+    assert_eq!(symbols[1].selection_range, range(7, 4, 7));
+}
+
+#[test]
+fn workspace_symbols_fuzzy_match() {
+    let db = input_db(SOURCE);
+    let symbols = uncancelled(db.workspace_symbols("ad"));
+
+    let names: Vec<_> = symbols.iter().map(|s| s.0.as_str()).collect();
+    assert_eq!(names, vec!["add"]);
+
+    let symbols = uncancelled(db.workspace_symbols("ba"));
+    let names: Vec<_> = symbols
+        .iter()
+        .map(|s| (s.0.as_str(), s.4.as_ref().map(|c| c.as_str())))
+        .collect();
+    assert_eq!(names, vec![("bar", Some("Foo")), ("baz", Some("Foo"))]);
+}